@@ -0,0 +1,217 @@
+use crate::ResourcesVersion;
+use crate::source::{GameFileSource, DirectorySource};
+
+#[cfg(test)]
+mod tests {
+    use crate::source::MemorySource;
+    use super::*;
+
+    fn memory_game(logdir:&[u8]) -> MemorySource {
+        let mut source = MemorySource::new("memory");
+        source.insert("LOGDIR",logdir.to_vec());
+        source.insert("VOL.0",vec![0x12u8,0x34,0,0,0]);
+        source.insert("VOL.1",vec![0x12u8,0x34,1,0,0]);
+        source
+    }
+
+    #[test]
+    fn fingerprint_directory_uses_first_5000_bytes() {
+        let mut long = vec![1u8;5000];
+        long.extend_from_slice(&[2u8;1000]);
+        let a = GameFingerprint::new_from_source(&memory_game(&[1u8;6000])).unwrap();
+        let b = GameFingerprint::new_from_source(&memory_game(&long)).unwrap();
+        assert_eq!(a.directory,b.directory);
+        assert_eq!(a.volumes,b.volumes);
+    }
+
+    #[test]
+    fn fingerprint_fail_no_directory() {
+        assert!(GameFingerprint::new_from_source(&MemorySource::new("none")).is_err());
+    }
+
+    #[test]
+    fn identify_unknown_game() {
+        let game = identify_source(&memory_game(&[0u8;3])).unwrap();
+        assert!(!game.is_known());
+        assert!(game.quirks.is_empty());
+        assert_eq!(game.title,"memory");
+        assert_eq!(game.platform,GamePlatform::Dos);
+        assert!(game.version.is_none());
+    }
+
+    #[test]
+    fn identify_from_memory() {
+        let mut source = MemorySource::new("memory");
+        source.insert("KQ4DIR",vec![8,0,8,0,8,0,8,0]);
+        source.insert("AGI",b"Version 3.002.086".to_vec());
+        let game = identify_source(&source).unwrap();
+        assert_eq!(game.title,"memory");
+        assert!(game.version==Some(ResourcesVersion::new("3.002.086")));
+    }
+
+    #[test]
+    fn lookup_known_release() {
+        let release = &KNOWN_RELEASES[0];
+        let fingerprint = GameFingerprint { directory:release.directory_md5.to_string(), volumes:String::new() };
+        let game = GameDescriptor::lookup(fingerprint).unwrap();
+        assert_eq!(game.quirks,release.quirks.to_vec());
+        assert!(game.is_known());
+        assert_eq!(game.id,release.id);
+        assert!(game.version==Some(ResourcesVersion::new(release.version)));
+    }
+
+    #[test]
+    fn known_releases_are_unique() {
+        for (i,a) in KNOWN_RELEASES.iter().enumerate() {
+            assert_eq!(a.directory_md5.len(),32);
+            assert!(KNOWN_RELEASES[i+1..].iter().all(|b| b.directory_md5!=a.directory_md5));
+        }
+    }
+}
+
+/// Number of bytes of the directory file that are hashed, the same as ScummVM so its detection tables can be reused
+pub const GAME_FINGERPRINT_SIZE:usize = 5000;
+
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum GamePlatform {
+    Dos,
+    Amiga,
+    AppleIIgs,
+    AtariSt,
+    Macintosh,
+}
+
+/// Known differences of a release that the interpreter or tools may need to allow for
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum GameQuirk {
+    /// Cut down release of a game
+    Demo,
+    /// Asks age verification questions before the game starts
+    AgeQuiz,
+}
+
+/// Hashes identifying a game's data files
+#[derive(Clone,Debug,PartialEq)]
+pub struct GameFingerprint {
+    /// md5 of the first GAME_FINGERPRINT_SIZE bytes of LOGDIR (v2) or the combined directory (v3)
+    pub directory:String,
+    /// md5 of every VOL.n file in volume order
+    pub volumes:String,
+}
+
+struct KnownRelease {
+    directory_md5:&'static str,
+    id:&'static str,
+    title:&'static str,
+    platform:GamePlatform,
+    version:&'static str,
+    quirks:&'static [GameQuirk],
+}
+
+/// What we know about the game in a folder
+#[derive(Clone,Debug)]
+pub struct GameDescriptor {
+    /// Short name (e.g. "kq1"), empty if the game wasn't found in the table
+    pub id:&'static str,
+    pub title:String,
+    pub platform:GamePlatform,
+    /// None when the game isn't known and the game files hold no version signature
+    pub version:Option<ResourcesVersion>,
+    pub quirks:Vec<GameQuirk>,
+    pub fingerprint:GameFingerprint,
+}
+
+// Directory hashes as listed by ScummVM's AGI detection tables
+const KNOWN_RELEASES:[KnownRelease;15] = [
+    KnownRelease { directory_md5:"3067b8d5957e2861e069c3c0011bd43d", id:"xmascard", title:"Christmas Card (1986)", platform:GamePlatform::Dos, version:"2.272", quirks:&[] },
+    KnownRelease { directory_md5:"9c4a5b09cc3564bc48b4766e679ea332", id:"kq1", title:"King's Quest 1.0U", platform:GamePlatform::Dos, version:"2.272", quirks:&[] },
+    KnownRelease { directory_md5:"10ad66e2ecbd66951534a50aedcd0128", id:"kq1", title:"King's Quest 2.0F", platform:GamePlatform::Dos, version:"2.917", quirks:&[] },
+    KnownRelease { directory_md5:"759e39f891a0e1d86dd29d7de485c6ac", id:"kq2", title:"King's Quest II 2.1", platform:GamePlatform::Dos, version:"2.411", quirks:&[] },
+    KnownRelease { directory_md5:"d3d17b77b3b3cd13246749231d9473cd", id:"kq3", title:"King's Quest III 2.14", platform:GamePlatform::Dos, version:"2.936", quirks:&[] },
+    KnownRelease { directory_md5:"f50f7f997208ca0e35b2650baec43a2d", id:"kq4", title:"King's Quest IV 2.0", platform:GamePlatform::Dos, version:"3.002.086", quirks:&[] },
+    KnownRelease { directory_md5:"1fe764e66857e7f305a5f03ca3f4971d", id:"lsl1", title:"Leisure Suit Larry 1.00", platform:GamePlatform::Dos, version:"2.440", quirks:&[GameQuirk::AgeQuiz] },
+    KnownRelease { directory_md5:"af93941b6c51460790a9efa0e8cb7122", id:"sq1", title:"Space Quest 1.0X", platform:GamePlatform::Dos, version:"2.089", quirks:&[] },
+    KnownRelease { directory_md5:"5d67630aba008ec5f7f9a6d0a00582f4", id:"sq1", title:"Space Quest 2.2", platform:GamePlatform::Dos, version:"2.440", quirks:&[] },
+    KnownRelease { directory_md5:"bd71fe54869e86945041700f1804a651", id:"sq2", title:"Space Quest II 2.0C", platform:GamePlatform::Dos, version:"2.917", quirks:&[] },
+    KnownRelease { directory_md5:"28add5125484302d213911df60d2aded", id:"sq2", title:"Space Quest II 2.0F", platform:GamePlatform::Dos, version:"2.936", quirks:&[] },
+    KnownRelease { directory_md5:"db733d199238d4009a9e95f11ece34e9", id:"goldrush", title:"Gold Rush! 2.01", platform:GamePlatform::Dos, version:"3.002.149", quirks:&[] },
+    KnownRelease { directory_md5:"0c5a9acbcc3b8e6ab65427c9b9ef5ee4", id:"bc", title:"The Black Cauldron 2.10", platform:GamePlatform::Dos, version:"3.002.098", quirks:&[] },
+    KnownRelease { directory_md5:"5b625329021ad49fd0c1d6f2d6f54bba", id:"mh1", title:"Manhunter: New York 1.22", platform:GamePlatform::Dos, version:"3.002.102", quirks:&[] },
+    KnownRelease { directory_md5:"e8ebeb0bbe978172fe166f91f51598c7", id:"agidemo", title:"AGI Demo Pack 1987-11-24", platform:GamePlatform::Dos, version:"2.915", quirks:&[GameQuirk::Demo] },
+];
+
+fn find_file(names:&[String],wanted:impl Fn(&str) -> bool) -> Option<&String> {
+    names.iter().find(|n| wanted(&n.to_uppercase()))
+}
+
+impl GameFingerprint {
+    pub fn new(base_path:&str) -> Result<GameFingerprint,&'static str> {
+        GameFingerprint::new_from_source(&DirectorySource::new(base_path))
+    }
+
+    pub fn new_from_source(source:&dyn GameFileSource) -> Result<GameFingerprint,&'static str> {
+        let mut names = source.list_files();
+        names.sort();
+
+        let directory = match find_file(&names,|n| n=="LOGDIR").or_else(|| find_file(&names,|n| n.ends_with("DIR"))) {
+            Some(name) => source.read_file(name).unwrap_or_default(),
+            None => return Err("Failed to locate a directory file to fingerprint"),
+        };
+        let directory = &directory[..directory.len().min(GAME_FINGERPRINT_SIZE)];
+
+        let mut volumes:Vec<(u32,&String)> = names.iter()
+            .filter_map(|n| n.to_uppercase().rsplit_once("VOL.").and_then(|(_,v)| v.parse::<u32>().ok()).map(|v| (v,n)))
+            .collect();
+        volumes.sort();
+        let mut context = md5::Context::new();
+        for (_,name) in volumes {
+            context.consume(source.read_file(name).unwrap_or_default());
+        }
+
+        Ok(GameFingerprint { directory:format!("{:x}",md5::compute(directory)), volumes:format!("{:x}",context.compute()) })
+    }
+}
+
+impl GameDescriptor {
+    /// Finds the release in the table of known games
+    pub fn lookup(fingerprint:GameFingerprint) -> Option<GameDescriptor> {
+        let release = KNOWN_RELEASES.iter().find(|r| r.directory_md5==fingerprint.directory)?;
+        Some(GameDescriptor {
+            id:release.id,
+            title:release.title.to_string(),
+            platform:release.platform,
+            version:Some(ResourcesVersion::new(release.version)),
+            quirks:release.quirks.to_vec(),
+            fingerprint,
+        })
+    }
+
+    pub fn is_known(&self) -> bool {
+        !self.id.is_empty()
+    }
+
+    pub fn has_quirk(&self,quirk:GameQuirk) -> bool {
+        self.quirks.contains(&quirk)
+    }
+}
+
+/// Identifies the game in a folder, unknown games get a (DOS) descriptor named after the folder with the detected version (if any)
+pub fn identify(base_path:&str) -> Result<GameDescriptor,&'static str> {
+    identify_source(&DirectorySource::new(base_path))
+}
+
+pub fn identify_source(source:&dyn GameFileSource) -> Result<GameDescriptor,&'static str> {
+    let fingerprint = GameFingerprint::new_from_source(source)?;
+    if let Some(game) = GameDescriptor::lookup(fingerprint.clone()) {
+        return Ok(game);
+    }
+
+    Ok(GameDescriptor {
+        id:"",
+        title:source.name().to_string(),
+        platform:GamePlatform::Dos,
+        version:ResourcesVersion::detect_source(source).ok(),
+        quirks:Vec::new(),
+        fingerprint,
+    })
+}
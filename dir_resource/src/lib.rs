@@ -1,8 +1,156 @@
-use std::{ops::Index, path::Path, fs, cmp::Ordering};
+use std::{ops::Index, cmp::Ordering};
+
+use source::{GameFileSource, DirectorySource};
+
+pub mod games;
+pub mod source;
 
 #[cfg(test)]
 mod tests {
-    use crate::ResourceDirectory;
+    use crate::{ResourceDirectory, ResourceDirectoryEntry, ResourceCompression, ResourceType, ResourcesVersion, Root, DIRECTORY_MAX_POSITION};
+    use crate::source::MemorySource;
+
+    #[test]
+    fn signature_v2() {
+        let bytes = b"\0\0Copyright 1987\0Version 2.917\0";
+        assert!(ResourcesVersion::find_signature(bytes)==Some(ResourcesVersion::new("2.917")));
+    }
+
+    #[test]
+    fn signature_v3() {
+        let bytes = b"xxVersion 3.002.149\0";
+        assert!(ResourcesVersion::find_signature(bytes)==Some(ResourcesVersion::new("3.002.149")));
+    }
+
+    #[test]
+    fn signature_requires_version_label() {
+        assert!(ResourcesVersion::find_signature(b"\0\x012.936\0Copyright 1987 2.089\0").is_none());
+        let bytes = b"12.34 2.089\0VERSION: 2.272\0";
+        assert!(ResourcesVersion::find_signature(bytes)==Some(ResourcesVersion::new("2.272")));
+    }
+
+    #[test]
+    fn version_display() {
+        assert_eq!(ResourcesVersion::new("2.089").to_string(),"2.089");
+        assert_eq!(ResourcesVersion::new("3.002.149").to_string(),"3.002.149");
+        assert!(ResourcesVersion::new(&ResourcesVersion::new("2.917").to_string())==ResourcesVersion::new("2.917"));
+    }
+
+    #[test]
+    fn signature_ignores_longer_numbers() {
+        assert!(ResourcesVersion::find_signature(b"Version 12.9171 Version 2.91 Version 4.123").is_none());
+    }
+
+    fn memory_game(files:&[(&str,&[u8])]) -> MemorySource {
+        let mut source = MemorySource::new("memory");
+        for (name,data) in files {
+            source.insert(name,data.to_vec());
+        }
+        source
+    }
+
+    #[test]
+    fn detect_from_agidata() {
+        let source = memory_game(&[("LOGDIR",&[0u8;3]),("AGIDATA.OVL",b"\0\x01Version 2.936\0")]);
+        let root = Root::detect_source(Box::new(source)).unwrap();
+        assert!(*root.version()==ResourcesVersion::new("2.936"));
+    }
+
+    #[test]
+    fn detect_v3_directory_prefix() {
+        let source = memory_game(&[("GRDIR",&[8u8,0,8,0,8,0,8,0]),("AGI",b"Version 3.002.149\0")]);
+        let root = Root::detect_source(Box::new(source)).unwrap();
+        assert!(*root.version()==ResourcesVersion::new("3.002.149"));
+    }
+
+    #[test]
+    fn detect_fail_without_signature() {
+        let source = memory_game(&[("LOGDIR",&[0u8;3]),("SIERRA.COM",b"2.917")]);
+        assert!(ResourcesVersion::detect_source(&source).is_err());
+        let source = memory_game(&[("GRDIR",&[8u8,0,8,0,8,0,8,0])]);
+        assert!(ResourcesVersion::detect_source(&source).is_err());
+    }
+
+    #[test]
+    fn directory_from_memory_source() {
+        let mut source = MemorySource::new("memory");
+        source.insert("LOGDIR",vec![0x01,0x23,0x45,0xFF,0xFF,0xFF]);
+        let root = Root::new_from_source(Box::new(source),"2.917");
+        let dir = ResourceDirectory::new(&root,ResourceType::Logic).unwrap();
+        assert_eq!(dir[0].volume,0);
+        assert_eq!(dir[0].position,0x12345);
+        assert!(dir[1].empty());
+    }
+
+    #[test]
+    fn directory_missing_v2_sounds_is_empty() {
+        let source = memory_game(&[("LOGDIR",&[0x01,0x23,0x45])]);
+        let root = Root::new_from_source(Box::new(source),"2.089");
+        assert!(ResourceDirectory::new(&root,ResourceType::Sounds).unwrap().is_empty());
+        assert!(ResourceDirectory::new(&root,ResourceType::Views).is_err());
+    }
+
+    fn entry(volume:u8,position:u32) -> ResourceDirectoryEntry {
+        ResourceDirectoryEntry { volume, position, compression:ResourceCompression::None }
+    }
+
+    #[test]
+    fn write_v2_round_trip() {
+        let bytes = vec![0x01,0x23,0x45, 0xFF,0xFF,0xFF, 0xE0,0x00,0x10];
+        let dir = ResourceDirectory::new_v2(bytes.clone()).unwrap();
+        assert_eq!(dir.write_v2().unwrap(),bytes);
+    }
+
+    #[test]
+    fn write_v2_keeps_empty_entry_bytes() {
+        let bytes = vec![0xF1,0x23,0x45, 0xFF,0xFF,0xFF, 0xF0,0x00,0x00];
+        let dir = ResourceDirectory::new_v2(bytes.clone()).unwrap();
+        assert!((&dir).into_iter().all(|e| e.empty()));
+        assert_eq!(dir.write_v2().unwrap(),bytes);
+    }
+
+    #[test]
+    fn write_v2_entries() {
+        let dir = ResourceDirectory::new_from_entries(vec![entry(2,0x4567),ResourceDirectoryEntry::new_empty(),entry(0,0)]);
+        let bytes = dir.write_v2().unwrap();
+        assert_eq!(bytes,vec![0x20,0x45,0x67, 0xFF,0xFF,0xFF, 0x00,0x00,0x00]);
+        let parsed = ResourceDirectory::new_v2(bytes).unwrap();
+        assert_eq!(parsed.len(),3);
+        assert!(parsed.into_iter().zip(dir).all(|(a,b)| a==b));
+    }
+
+    #[test]
+    fn write_v2_fail_position_out_of_range() {
+        let dir = ResourceDirectory::new_from_entries(vec![entry(1,DIRECTORY_MAX_POSITION+1)]);
+        assert!(dir.write_v2().is_err());
+    }
+
+    #[test]
+    fn write_v3_round_trip() {
+        let logic = ResourceDirectory::new_from_entries(vec![entry(0,0x10),entry(1,0x20)]);
+        let pictures = ResourceDirectory::new_from_entries(vec![ResourceDirectoryEntry::new_empty(),entry(2,0x30)]);
+        let views = ResourceDirectory::new_from_entries(vec![]);
+        let sounds = ResourceDirectory::new_from_entries(vec![entry(3,0xABCDE)]);
+        let bytes = ResourceDirectory::write_v3(&logic,&pictures,&views,&sounds).unwrap();
+        assert_eq!(&bytes[0..8],&[8,0,14,0,20,0,20,0]);
+        assert_eq!(bytes.len(),23);
+
+        let parsed = [ResourceType::Logic,ResourceType::Pictures,ResourceType::Views,ResourceType::Sounds]
+            .map(|t| ResourceDirectory::new_v3(bytes.clone(),t).unwrap());
+        for (parsed,original) in parsed.iter().zip([&logic,&pictures,&views,&sounds]) {
+            assert_eq!(parsed.len(),original.len());
+            assert!(parsed.into_iter().zip(original).all(|(a,b)| a.volume==b.volume && a.position==b.position));
+        }
+        assert!(parsed[1][1].compression==ResourceCompression::Picture);
+
+        let rewritten = ResourceDirectory::write_v3(&parsed[0],&parsed[1],&parsed[2],&parsed[3]).unwrap();
+        assert_eq!(rewritten,bytes);
+    }
+
+    #[test]
+    fn detect_fail_empty() {
+        assert!(ResourcesVersion::detect_source(&MemorySource::new("empty")).is_err());
+    }
 /*
     #[test]
     fn construct_ok0() {
@@ -56,19 +204,24 @@ mod tests {
 }
 
 
-#[derive(Eq,Clone,Copy)]
+#[derive(Eq,Clone,Copy,Debug)]
 pub struct ResourcesVersion {
     comparing:u64,
 }
 
 impl std::fmt::Display for ResourcesVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f,"{}.{}.{}",self.comparing>>32,(self.comparing>>16)&0xFFFF,self.comparing&0xFFFF)
+        // AGI's own notation, 2.xxx before version 3 and 3.xxx.xxx after
+        let (major,minor,patch) = (self.comparing>>32,(self.comparing>>16)&0xFFFF,self.comparing&0xFFFF);
+        match major {
+            0..=2 if patch==0 => write!(f,"{}.{:03}",major,minor),
+            _ => write!(f,"{}.{:03}.{:03}",major,minor,patch),
+        }
     }
 }
 
 pub struct Root<'a> {
-    base_path:&'a Path,
+    source:Box<dyn GameFileSource + 'a>,
     version:ResourcesVersion,
 }
 
@@ -92,6 +245,81 @@ impl ResourcesVersion {
         let comparing = comparing + (patch as u64);
         ResourcesVersion { comparing }
     }
+
+    /// Looks for the interpreter version in the game files - the signature embedded in AGIDATA.OVL
+    /// or the interpreter executable. Fails if there is none, leaving the caller to supply the version.
+    pub fn detect(base_path:&str) -> Result<ResourcesVersion,&'static str> {
+        ResourcesVersion::detect_source(&DirectorySource::new(base_path))
+    }
+
+    pub fn detect_source(source:&dyn GameFileSource) -> Result<ResourcesVersion,&'static str> {
+        let mut names = source.list_files();
+        names.sort_by_key(|n| (signature_file_priority(n),n.to_uppercase()));
+
+        for name in names.iter().filter(|n| signature_file_priority(n)<SIGNATURE_FILE_NONE) {
+            if let Some(version) = ResourcesVersion::find_signature(&source.read_file(name).unwrap_or_default()) {
+                return Ok(version);
+            }
+        }
+
+        if names.iter().any(|n| n.eq_ignore_ascii_case("LOGDIR") || n.to_uppercase().ends_with("DIR")) {
+            return Err("Failed to detect interpreter version, no version signature in the game files");
+        }
+        Err("Failed to detect interpreter version, no AGI files found")
+    }
+
+    /// Finds the version string (2.xxx or 3.xxx.xxx) the interpreter labels "Version" in a block of bytes
+    pub fn find_signature(bytes:&[u8]) -> Option<ResourcesVersion> {
+        let mut search = 0;
+        while let Some(found) = find_ignore_case(&bytes[search..],SIGNATURE_LABEL) {
+            let mut start = search+found+SIGNATURE_LABEL.len();
+            while matches!(bytes.get(start),Some(b' ') | Some(b':')) {
+                start+=1;
+            }
+            if let Some(length) = signature_length(bytes,start) {
+                return Some(ResourcesVersion::new(std::str::from_utf8(&bytes[start..start+length]).unwrap()));
+            }
+            search = start;
+        }
+        None
+    }
+}
+
+const SIGNATURE_LABEL:&[u8] = b"version";
+
+const SIGNATURE_FILE_NONE:u8 = 3;
+
+fn signature_file_priority(name:&str) -> u8 {
+    let name = name.to_uppercase();
+    if name=="AGIDATA.OVL" {
+        0
+    } else if name=="AGI" || name.ends_with(".OVL") {
+        1
+    } else if name.ends_with(".EXE") || name.ends_with(".COM") {
+        2
+    } else {
+        SIGNATURE_FILE_NONE
+    }
+}
+
+fn find_ignore_case(bytes:&[u8],wanted:&[u8]) -> Option<usize> {
+    bytes.windows(wanted.len()).position(|w| w.eq_ignore_ascii_case(wanted))
+}
+
+// Length of a version string at start, the major digit must be 2 or 3 and the string must not be part of a longer number
+fn signature_length(bytes:&[u8],start:usize) -> Option<usize> {
+    let is_digits = |from:usize| bytes.get(from..from+3).is_some_and(|d| d.iter().all(|b| b.is_ascii_digit()));
+    if start>0 && (bytes[start-1].is_ascii_digit() || bytes[start-1]==b'.') {
+        return None;
+    }
+    if !matches!(bytes.get(start),Some(b'2') | Some(b'3')) || bytes.get(start+1)!=Some(&b'.') || !is_digits(start+2) {
+        return None;
+    }
+    let length = if bytes[start]==b'3' && bytes.get(start+5)==Some(&b'.') && is_digits(start+6) {9} else {5};
+    match bytes.get(start+length) {
+        Some(b) if b.is_ascii_digit() || *b==b'.' => None,
+        _ => Some(length),
+    }
 }
 
 impl PartialOrd for ResourcesVersion {
@@ -111,17 +339,35 @@ impl PartialEq for ResourcesVersion {
     }
 }
 
-impl<'a> Root<'_> {
-    pub fn new(base_path:&'a str, version:&'a str) -> Root<'a> {
-        Root {base_path:Path::new(base_path), version:ResourcesVersion::new(version)}
+impl<'a> Root<'a> {
+    pub fn new(base_path:&str, version:&str) -> Root<'a> {
+        Root::new_from_source(Box::new(DirectorySource::new(base_path)), version)
+    }
+
+    /// As new, but with the version detected from the game files (see ResourcesVersion::detect)
+    pub fn detect(base_path:&str) -> Result<Root<'a>,&'static str> {
+        Root::detect_source(Box::new(DirectorySource::new(base_path)))
+    }
+
+    pub fn new_from_source(source:Box<dyn GameFileSource + 'a>, version:&str) -> Root<'a> {
+        Root {source, version:ResourcesVersion::new(version)}
+    }
+
+    pub fn detect_source(source:Box<dyn GameFileSource + 'a>) -> Result<Root<'a>,&'static str> {
+        let version = ResourcesVersion::detect_source(source.as_ref())?;
+        Ok(Root {source, version})
+    }
+
+    pub fn source(&self) -> &dyn GameFileSource {
+        self.source.as_ref()
     }
 
     pub fn read_data_or_default(&self,file:&str) -> Vec<u8> {
-        fs::read(self.base_path.join(file).into_os_string()).unwrap_or_default()
+        self.source.read_file(file).unwrap_or_default()
     }
 
     pub fn file_exists(&self,file:&str) -> bool {
-        self.base_path.join(file).exists()
+        self.source.file_exists(file)
     }
 
     pub fn version(&self) -> &ResourcesVersion {
@@ -129,35 +375,22 @@ impl<'a> Root<'_> {
     }
 
     pub fn v3_directory_file(&self) -> Result<String,&'static str> {
-        if let Ok(iter) = fs::read_dir(self.base_path) {
-            for a in iter {
-                if let Ok(entry) = a {
-                    if let Ok(name) = entry.file_name().into_string() {
-                        if name.ends_with("DIR") {
-                            return Ok(name.clone());
-                        }
-                    }
-                }
+        for name in self.source.list_files() {
+            if name.ends_with("DIR") {
+                return Ok(name);
             }
         }
-        return Err("Failed to locate V3 Directory Resource");
+        Err("Failed to locate V3 Directory Resource")
     }
 
     fn fetch_volume_name(&self,entry:&ResourceDirectoryEntry) -> Result<String,&'static str> {
         let vol_name = format!("VOL.{}",entry.volume);
-        if let Ok(iter) = fs::read_dir(self.base_path) {
-            for a in iter {
-                if let Ok(entry) = a {
-                    if let Ok(name) = entry.file_name().into_string() {
-                        if name.ends_with(vol_name.as_str()) {
-                            return Ok(name.clone());
-                        }
-                    }
-                }
+        for name in self.source.list_files() {
+            if name.ends_with(vol_name.as_str()) {
+                return Ok(name);
             }
         }
-        return Err("Failed to locate V3 Directory Resource");
-
+        Err("Failed to locate Volume Resource")
     }
 
     pub fn fetch_volume(&self,entry:&ResourceDirectoryEntry) -> Vec<u8> {
@@ -168,21 +401,29 @@ impl<'a> Root<'_> {
     }
 }
 
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Hash)]
 pub enum ResourceType {
     Words,
     Pictures,
     Logic,
     Objects,
     Views,
+    Sounds,
 }
 
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub enum ResourceCompression {
     None,
     LZW,
     Picture,
 }
 
+/// Largest offset a directory entry can hold (20 bits)
+pub const DIRECTORY_MAX_POSITION:u32 = 0xFFFFF;
+const V3_DIRECTORY_HEADER_SIZE:usize = 8;
+
 /// Represents an entry in a Directory Resource in AGI
+#[derive(Clone,Copy,Debug,PartialEq)]
 pub struct ResourceDirectoryEntry {
     pub volume:u8,
     pub position:u32,
@@ -213,9 +454,25 @@ impl<'a> IntoIterator for &'a ResourceDirectory {
 }
 
 impl ResourceDirectoryEntry {
+    /// An unused slot, written as FF FF FF
+    pub fn new_empty() -> ResourceDirectoryEntry {
+        ResourceDirectoryEntry { volume:0xF, position:DIRECTORY_MAX_POSITION, compression:ResourceCompression::None }
+    }
+
     pub fn empty(&self) -> bool {
         self.volume==0xF
     }
+
+    // Empty entries keep the position they were read with, so non canonical ones (not FF FF FF) write back unchanged
+    fn write(&self,out:&mut Vec<u8>) -> Result<(),&'static str> {
+        if self.volume>0xF || self.position>DIRECTORY_MAX_POSITION {
+            return Err("Directory entry volume or position is out of range");
+        }
+        out.push((self.volume<<4) | ((self.position>>16) as u8));
+        out.push((self.position>>8) as u8);
+        out.push(self.position as u8);
+        Ok(())
+    }
 }
 
 impl Index<usize> for ResourceDirectory {
@@ -236,11 +493,19 @@ impl ResourceDirectory {
             ResourceType::Pictures => "PICDIR",
             ResourceType::Views => "VIEWDIR",
             ResourceType::Logic => "LOGDIR",
+            ResourceType::Sounds => "SNDDIR",
         };
         if root.file_exists(directory_name) {
             let bytes = root.read_data_or_default(directory_name);
             return ResourceDirectory::new_v2(bytes);
         }
+        // v2 games without sound (e.g. early releases) have no SNDDIR at all
+        if root.file_exists("LOGDIR") {
+            return match resource_type {
+                ResourceType::Sounds => Ok(ResourceDirectory { entries:Vec::new() }),
+                _ => Err("Missing directory file"),
+            };
+        }
 
         // Presumably we are looking at a v3 directory resource
         if let Ok(v3) = root.v3_directory_file() {
@@ -329,6 +594,7 @@ impl ResourceDirectory {
             ResourceType::Logic => (logic_offset-8,picture_offset-logic_offset),
             ResourceType::Pictures => (picture_offset-8,view_offset-picture_offset),
             ResourceType::Views => (view_offset-8,sound_offset-view_offset),
+            ResourceType::Sounds => (sound_offset-8,u16::MAX),   // sound directory runs to the end of the file
         };
         let mut bytes = bytes.skip(skip as usize).take(take as usize);
         while let Some(b) = bytes.next() {
@@ -361,6 +627,44 @@ impl ResourceDirectory {
         self.entries.get(index)
     }
 
+    pub fn new_from_entries(entries:Vec<ResourceDirectoryEntry>) -> ResourceDirectory {
+        ResourceDirectory { entries }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Builds a v2 directory file (LOGDIR, PICDIR, VIEWDIR or SNDDIR)
+    pub fn write_v2(&self) -> Result<Vec<u8>, &'static str> {
+        let mut bytes = Vec::with_capacity(self.entries.len()*3);
+        for entry in &self.entries {
+            entry.write(&mut bytes)?;
+        }
+        Ok(bytes)
+    }
+
+    /// Builds a combined v3 directory file (e.g. GRDIR), the four section offsets followed by each directory
+    pub fn write_v3(logic:&ResourceDirectory,pictures:&ResourceDirectory,views:&ResourceDirectory,sounds:&ResourceDirectory) -> Result<Vec<u8>, &'static str> {
+        let sections = [logic.write_v2()?,pictures.write_v2()?,views.write_v2()?,sounds.write_v2()?];
+
+        let mut header:Vec<u8> = Vec::with_capacity(V3_DIRECTORY_HEADER_SIZE);
+        let mut offset = V3_DIRECTORY_HEADER_SIZE;
+        for section in &sections {
+            if offset>u16::MAX as usize {
+                return Err("V3 directory is too large for its 16 bit offsets");
+            }
+            header.extend_from_slice(&(offset as u16).to_le_bytes());
+            offset+=section.len();
+        }
+
+        Ok(header.into_iter().chain(sections.into_iter().flatten()).collect())
+    }
+
 
 
 }
\ No newline at end of file
@@ -1,8 +1,16 @@
 use dir_resource::{ResourceDirectory, Root};
+use dir_resource::games::identify;
 
 fn main() {
-    let root = Root::new("../images/Space Quest- The Sarien Encounter v1.0X (1986)(Sierra On-Line, Inc.) [Adventure]/","2.089");
-    //let root = Root::new("../images/Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.149");
+    let base_path = "../images/Space Quest- The Sarien Encounter v1.0X (1986)(Sierra On-Line, Inc.) [Adventure]/";
+    //let base_path = "../images/Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]/";
+
+    // Print the fingerprint, so unknown releases can be added to the games table
+    let game = identify(base_path).unwrap();
+    println!("{} {:?} AGI {:?} | dir {} vol {}",game.title,game.platform,game.version,game.fingerprint.directory,game.fingerprint.volumes);
+
+    let version = game.version.expect("Unknown interpreter version").to_string();
+    let root = Root::new(base_path,&version);
 
     let dir = ResourceDirectory::new(&root,dir_resource::ResourceType::Logic);
 
@@ -0,0 +1,211 @@
+use std::{collections::HashMap, fs, io::{Cursor, Read}, path::{Path, PathBuf}};
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use super::*;
+
+    #[test]
+    fn memory_source_reads_files() {
+        let mut source = MemorySource::new("test");
+        source.insert("LOGDIR",vec![1,2,3]);
+        assert!(source.file_exists("LOGDIR"));
+        assert!(!source.file_exists("PICDIR"));
+        assert_eq!(source.read_file("LOGDIR"),Some(vec![1,2,3]));
+        assert_eq!(source.list_files(),vec!["LOGDIR".to_string()]);
+        assert_eq!(source.name(),"test");
+    }
+
+    #[test]
+    fn directory_source_reads_files() {
+        let name = format!("dir_resource_source_directory_{}",std::process::id());
+        let dir = std::env::temp_dir().join(&name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("WORDS.TOK"),[9u8;4]).unwrap();
+        let source = DirectorySource::new(dir.to_str().unwrap());
+        let exists = source.file_exists("WORDS.TOK");
+        let words = source.read_file("WORDS.TOK");
+        let object = source.read_file("OBJECT");
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(exists);
+        assert_eq!(words,Some(vec![9u8;4]));
+        assert!(object.is_none());
+        assert_eq!(source.name(),name);
+    }
+
+    fn zip(files:&[&str]) -> Vec<u8> {
+        let mut bytes:Vec<u8> = Vec::new();
+        {
+            let mut zip = zip::ZipWriter::new(Cursor::new(&mut bytes));
+            let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+            for file in files {
+                zip.start_file(*file,options).unwrap();
+                zip.write_all(&[0x12,0x34,0,0,0]).unwrap();
+            }
+            zip.finish().unwrap();
+        }
+        bytes
+    }
+
+    #[test]
+    fn zip_source_flattens_folders() {
+        let source = ZipSource::new("game",zip(&["GAME/VOL.0","GAME/LOGDIR"])).unwrap();
+        assert_eq!(source.list_files(),vec!["LOGDIR".to_string(),"VOL.0".to_string()]);
+        assert_eq!(source.read_file("VOL.0"),Some(vec![0x12,0x34,0,0,0]));
+    }
+
+    #[test]
+    fn zip_source_fail_duplicate_names() {
+        assert!(ZipSource::new("games",zip(&["KQ1/VOL.0","KQ2/VOL.0"])).is_err());
+    }
+
+    #[test]
+    fn zip_source_fail_not_a_zip() {
+        assert!(ZipSource::new("bad",vec![0u8;16]).is_err());
+    }
+}
+
+/// Somewhere the files of a game can be read from (a folder on disk, a zip archive, memory)
+pub trait GameFileSource {
+    /// Name of the game's folder or archive
+    fn name(&self) -> &str;
+    /// Names of every file in the game's folder
+    fn list_files(&self) -> Vec<String>;
+    fn read_file(&self,file:&str) -> Option<Vec<u8>>;
+
+    fn file_exists(&self,file:&str) -> bool {
+        self.list_files().iter().any(|n| n==file)
+    }
+}
+
+/// Game files in a folder on disk
+pub struct DirectorySource {
+    base_path:PathBuf,
+    name:String,
+}
+
+/// Game files held in memory, e.g. embedded in a test or handed over by a host application
+pub struct MemorySource {
+    name:String,
+    files:HashMap<String,Vec<u8>>,
+}
+
+/// Game files in a zip archive, which is unpacked into memory on opening. Folders within the
+/// archive are ignored, so the archive should only hold one game (opening fails if names repeat).
+pub struct ZipSource {
+    files:MemorySource,
+}
+
+impl DirectorySource {
+    pub fn new(base_path:&str) -> DirectorySource {
+        let base_path = Path::new(base_path);
+        let name = base_path.file_name().and_then(|n| n.to_str()).unwrap_or_default().to_string();
+        DirectorySource { base_path:base_path.to_path_buf(), name }
+    }
+}
+
+impl GameFileSource for DirectorySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn list_files(&self) -> Vec<String> {
+        match fs::read_dir(&self.base_path) {
+            Ok(iter) => iter.filter_map(|e| e.ok()).filter_map(|e| e.file_name().into_string().ok()).collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn read_file(&self,file:&str) -> Option<Vec<u8>> {
+        fs::read(self.base_path.join(file)).ok()
+    }
+
+    fn file_exists(&self,file:&str) -> bool {
+        self.base_path.join(file).exists()
+    }
+}
+
+impl MemorySource {
+    pub fn new(name:&str) -> MemorySource {
+        MemorySource { name:name.to_string(), files:HashMap::new() }
+    }
+
+    pub fn insert(&mut self,file:&str,data:Vec<u8>) {
+        self.files.insert(file.to_string(),data);
+    }
+}
+
+impl GameFileSource for MemorySource {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn list_files(&self) -> Vec<String> {
+        let mut names:Vec<String> = self.files.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn read_file(&self,file:&str) -> Option<Vec<u8>> {
+        self.files.get(file).cloned()
+    }
+
+    fn file_exists(&self,file:&str) -> bool {
+        self.files.contains_key(file)
+    }
+}
+
+impl ZipSource {
+    pub fn new(name:&str,bytes:Vec<u8>) -> Result<ZipSource,&'static str> {
+        let mut archive = match zip::ZipArchive::new(Cursor::new(bytes)) {
+            Ok(a) => a,
+            Err(_) => return Err("Failed to read zip archive"),
+        };
+        let mut files = MemorySource::new(name);
+        for index in 0..archive.len() {
+            let mut file = match archive.by_index(index) {
+                Ok(f) => f,
+                Err(_) => return Err("Failed to read zip archive entry"),
+            };
+            if file.is_dir() {
+                continue;
+            }
+            let file_name = file.name().rsplit('/').next().unwrap_or_default().to_string();
+            if files.file_exists(&file_name) {
+                return Err("Zip archive holds more than one file with the same name, it should only hold one game");
+            }
+            let mut data:Vec<u8> = Vec::new();
+            if file.read_to_end(&mut data).is_err() {
+                return Err("Failed to decompress zip archive entry");
+            }
+            files.insert(&file_name,data);
+        }
+        Ok(ZipSource { files })
+    }
+
+    pub fn open(path:&str) -> Result<ZipSource,&'static str> {
+        let name = Path::new(path).file_stem().and_then(|n| n.to_str()).unwrap_or_default();
+        match fs::read(path) {
+            Ok(bytes) => ZipSource::new(name,bytes),
+            Err(_) => Err("Failed to open zip archive"),
+        }
+    }
+}
+
+impl GameFileSource for ZipSource {
+    fn name(&self) -> &str {
+        self.files.name()
+    }
+
+    fn list_files(&self) -> Vec<String> {
+        self.files.list_files()
+    }
+
+    fn read_file(&self,file:&str) -> Option<Vec<u8>> {
+        self.files.read_file(file)
+    }
+
+    fn file_exists(&self,file:&str) -> bool {
+        self.files.file_exists(file)
+    }
+}
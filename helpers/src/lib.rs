@@ -119,6 +119,7 @@ pub fn conv_rgba_transparent(data: &Vec<u8>, trans_col:u8) -> Vec<u8> {
 use std::fs::File;
 use std::path::Path;
 use std::io::BufWriter;
+use std::io::Write;
 
 pub fn dump_png(filepath: &str, width:u32, height:u32, data: &[u8]) {
     let path = Path::new(filepath);
@@ -142,3 +143,27 @@ pub fn dump_png(filepath: &str, width:u32, height:u32, data: &[u8]) {
 
     writer.write_image_data(data).unwrap(); // Save
 }
+
+pub fn dump_wav(filepath: &str, sample_rate:u32, channels:u16, data: &[i16]) {
+    let path = Path::new(filepath);
+    let file = File::create(path).unwrap();
+    let mut w = BufWriter::new(file);
+
+    let data_size = (data.len()*2) as u32;
+    let block_align = channels*2;
+    w.write_all(b"RIFF").unwrap();
+    w.write_all(&(36+data_size).to_le_bytes()).unwrap();
+    w.write_all(b"WAVEfmt ").unwrap();
+    w.write_all(&16u32.to_le_bytes()).unwrap();
+    w.write_all(&1u16.to_le_bytes()).unwrap();     // PCM
+    w.write_all(&channels.to_le_bytes()).unwrap();
+    w.write_all(&sample_rate.to_le_bytes()).unwrap();
+    w.write_all(&(sample_rate*(block_align as u32)).to_le_bytes()).unwrap();
+    w.write_all(&block_align.to_le_bytes()).unwrap();
+    w.write_all(&16u16.to_le_bytes()).unwrap();
+    w.write_all(b"data").unwrap();
+    w.write_all(&data_size.to_le_bytes()).unwrap();
+    for s in data {
+        w.write_all(&s.to_le_bytes()).unwrap();
+    }
+}
@@ -1,6 +1,6 @@
-use std::{collections::HashMap, fs, fmt, cmp::Ordering, hash::Hash, process::exit, time::Instant};
+use std::{cell::{OnceCell, RefCell}, collections::HashMap, fs, fmt, cmp::Ordering, hash::Hash, process::exit, time::{Duration, Instant}};
 
-use dir_resource::{Root, ResourceDirectory, ResourceType, ResourcesVersion};
+use dir_resource::{Root, ResourceCompression, ResourceDirectory, ResourceDirectoryEntry, ResourceType, ResourcesVersion};
 use fixed::{FixedU16, FixedI32, types::extra::U8};
 use from_to_repr::FromToRepr;
 use helpers::double_pic_width;
@@ -10,8 +10,10 @@ use objects::{Objects, Object};
 use picture::*;
 use rand::Rng;
 use serde::{Deserialize, Serialize, Serializer, Deserializer};
+use sound::{SoundResource, SOUND_VOICES};
+use sound::synth::SoundRenderer;
 use view::{ViewResource, ViewLoop, ViewCel};
-use volume::Volume;
+use volume::ResourceStore;
 use words::Words;
 
 pub const SCREEN_WIDTH_USIZE:usize = 320;
@@ -495,34 +497,34 @@ impl Sprite {
    
     pub fn set_view(&mut self, view:u8, resources:&GameResources) {
         self.view = view;
-        if resources.views[&(view as usize)].get_loops().len()<=(self.cloop as usize) {
+        if resources.view(view as usize).get_loops().len()<=(self.cloop as usize) {
             self.cloop=0;
         }
-        self.last_cel = (resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels().len()-1) as u8;
+        self.last_cel = (resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels().len()-1) as u8;
         if self.cel>self.last_cel {
             self.cel=0;
         }
-        self.width = resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_width();
-        self.height = resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_height();
+        self.width = resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_width();
+        self.height = resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_height();
     }
 
     pub fn set_loop(&mut self,n:u8, resources:&GameResources) {
         self.cloop = n;
-        if resources.views[&(self.view as usize)].get_loops().len()<=(self.cloop as usize) {
+        if resources.view(self.view as usize).get_loops().len()<=(self.cloop as usize) {
             self.cloop=0;
         }
-        self.last_cel = (resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels().len()-1) as u8;
+        self.last_cel = (resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels().len()-1) as u8;
         if self.cel>self.last_cel {
             self.cel=0;
         }
-        self.width = resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_width();
-        self.height = resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_height();
+        self.width = resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_width();
+        self.height = resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels()[self.cel as usize].get_height();
     }
     
     pub fn set_cel(&mut self,n:u8,resources:&GameResources) {
         self.cel = n;
-        self.width = resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels()[n as usize].get_width();
-        self.height = resources.views[&(self.view as usize)].get_loops()[self.cloop as usize].get_cels()[n as usize].get_height();
+        self.width = resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels()[n as usize].get_width();
+        self.height = resources.view(self.view as usize).get_loops()[self.cloop as usize].get_cels()[n as usize].get_height();
 
         // Reposition in bounds along edge
         if self.get_x() + self.width > PIC_WIDTH_U8 {
@@ -650,81 +652,218 @@ impl Sprite {
     }
 }
 
+/// Size of the heap resources are loaded into, original AGI reports what is left of it in 256 byte pages
+pub const RESOURCE_HEAP_SIZE:usize = 0xFF00;
+
+/// A resource that is parsed from its volume the first time it is needed
+struct LazyResource<T> {
+    entry:ResourceDirectoryEntry,
+    resource:OnceCell<Option<(T,usize)>>,   // parsed resource and its size, None once loading has failed
+}
+
+impl<T> LazyResource<T> {
+    fn new(entry:ResourceDirectoryEntry) -> LazyResource<T> {
+        LazyResource { entry, resource:OnceCell::new() }
+    }
+}
+
+/// Which resources are loaded and how much of the heap they take up
+#[derive(Default)]
+struct ResourceMemory {
+    loaded:HashMap<(ResourceType,usize),usize>,
+    discarded:Vec<(ResourceType,usize)>,
+    max_used:usize,
+    script_size:u8,
+}
+
+impl ResourceMemory {
+    fn used(&self) -> usize {
+        self.loaded.values().sum()
+    }
+}
+
 pub struct GameResources
 {
     pub version:ResourcesVersion,
     pub objects:Objects,
     pub words:Words,
-    pub views:HashMap<usize,ViewResource>,
-    pub pictures:HashMap<usize,PictureResource>,
-    pub logic:HashMap<usize,LogicResource>,
+    views:HashMap<usize,LazyResource<ViewResource>>,
+    pictures:HashMap<usize,LazyResource<PictureResource>>,
+    logic:HashMap<usize,LazyResource<LogicResource>>,
+    sounds:HashMap<usize,LazyResource<SoundResource>>,
     pub font:Vec<u8>,
+    /// Volume data and fetched payloads, shared by everything that needs the raw resources
+    pub store:RefCell<ResourceStore>,
+    memory:RefCell<ResourceMemory>,
+    /// Resources that could not be loaded (e.g. a damaged VOL file), the rest of the game is still usable.
+    /// Resources load on first use, so errors are added while the game runs.
+    pub load_errors:RefCell<Vec<String>>,
 }
 
 impl GameResources {
-    pub fn new (base_path:&'static str,version:&str) -> Result<GameResources,String> {
+    /// version overrides the interpreter version, otherwise it is detected from the game files
+    pub fn new (base_path:&'static str,version:Option<&str>) -> Result<GameResources,String> {
+        let root = match version {
+            Some(version) => Root::new(base_path,version),
+            None => Root::detect(base_path)?,
+        };
+        GameResources::new_from_root(&root)
+    }
+
+    /// Reads the game's volumes through the root's file source (folder, zip archive or memory), resources
+    /// are only parsed once they are loaded or first used
+    pub fn new_from_root(root:&Root) -> Result<GameResources,String> {
 
         // hack for font
         let font = fs::read("../images/BM.PSF").unwrap();
 
-        let root = Root::new(base_path,version);
-    
-        let mut volumes:HashMap<u8,Volume>=HashMap::new();
+        let resources = GameResources {
+            version: *root.version(),
+            words : Words::new(root.read_data_or_default("WORDS.TOK").into_iter())?,
+            objects: Objects::new(&root.read_data_or_default("OBJECT"))?,
+            views: GameResources::lazy_directory(root,ResourceType::Views)?,
+            pictures: GameResources::lazy_directory(root,ResourceType::Pictures)?,
+            logic: GameResources::lazy_directory(root,ResourceType::Logic)?,
+            sounds: GameResources::lazy_directory(root,ResourceType::Sounds)?,
+            font,
+            store: RefCell::new(ResourceStore::new_from_root(root)?),
+            memory: RefCell::new(ResourceMemory::default()),
+            load_errors: RefCell::new(Vec::new()),
+        };
 
-        let dir = ResourceDirectory::new(&root,ResourceType::Views).unwrap();
+        // Logic 0 stays loaded for the whole game
+        resources.load(ResourceType::Logic,0);
+        Ok(resources)
+    }
 
-        let mut views:HashMap<usize,ViewResource> = HashMap::new();
-        views.reserve(256);
-        for (index,entry) in dir.into_iter().enumerate() {
-            if !entry.empty() {
-                if let std::collections::hash_map::Entry::Vacant(e) =volumes.entry(entry.volume) {
-                    let bytes = root.fetch_volume(&entry);
-                    e.insert(Volume::new(bytes.into_iter())?);
-                }
-                views.insert(index, ViewResource::new(&volumes[&entry.volume],&entry)?);
+    fn lazy_directory<T>(root:&Root,resource_type:ResourceType) -> Result<HashMap<usize,LazyResource<T>>,String> {
+        let dir = ResourceDirectory::new(root,resource_type)?;
+        Ok(dir.into_iter().enumerate().filter(|(_,entry)| !entry.empty()).map(|(index,entry)| (index,LazyResource::new(entry))).collect())
+    }
+
+    fn fetch<'a,T>(&self,resources:&'a HashMap<usize,LazyResource<T>>,resource_type:ResourceType,num:usize,parse:impl FnOnce(&[u8],ResourceCompression) -> Result<T,String>) -> Option<&'a T> {
+        let lazy = resources.get(&num)?;
+        let (resource,size) = lazy.resource.get_or_init(|| {
+            let fetched = self.store.borrow_mut().fetch(&lazy.entry);
+            match fetched.map_err(String::from).and_then(|(data,compression)| parse(&data,compression).map(|r| (r,data.len()))) {
+                Ok(loaded) => Some(loaded),
+                Err(error) => {
+                    self.load_errors.borrow_mut().push(format!("{:?} {}: {}",resource_type,num,error));
+                    None
+                },
             }
+        }).as_ref()?;
+
+        let mut memory = self.memory.borrow_mut();
+        if let std::collections::hash_map::Entry::Vacant(e) = memory.loaded.entry((resource_type,num)) {
+            e.insert(*size);
+            memory.discarded.retain(|d| *d!=(resource_type,num));
+            memory.max_used = memory.max_used.max(memory.used());
         }
-        views.shrink_to_fit();
+        Some(resource)
+    }
 
-        let dir = ResourceDirectory::new(&root, ResourceType::Pictures).unwrap();
+    pub fn get_view(&self,num:usize) -> Option<&ViewResource> {
+        self.fetch(&self.views,ResourceType::Views,num,|data,_| ViewResource::new_from_slice(data))
+    }
 
-        let mut pictures:HashMap<usize,PictureResource> = HashMap::new();
-        pictures.reserve(256);
-        for (index,entry) in dir.into_iter().enumerate() {
-            if !entry.empty() {
-                if let std::collections::hash_map::Entry::Vacant(e) = volumes.entry(entry.volume) {
-                    let bytes = root.fetch_volume(&entry);
-                    e.insert(Volume::new(bytes.into_iter())?);
-                }
-                pictures.insert(index, PictureResource::new(&volumes[&entry.volume],&entry)?);
-            }
+    pub fn get_picture(&self,num:usize) -> Option<&PictureResource> {
+        self.fetch(&self.pictures,ResourceType::Pictures,num,|data,compression| Ok(PictureResource::new_from_slice(data,compression==ResourceCompression::Picture)))
+    }
+
+    pub fn get_logic(&self,num:usize) -> Option<&LogicResource> {
+        self.fetch(&self.logic,ResourceType::Logic,num,|data,compression| LogicResource::new_from_slice(data,compression,&self.version))
+    }
+
+    pub fn get_sound(&self,num:usize) -> Option<&SoundResource> {
+        self.fetch(&self.sounds,ResourceType::Sounds,num,|data,_| SoundResource::new_from_slice(data))
+    }
+
+    /// As get_view, for callers that cannot carry on without the view
+    pub fn view(&self,num:usize) -> &ViewResource {
+        self.get_view(num).unwrap_or_else(|| panic!("View {} is missing or failed to load",num))
+    }
+
+    /// As get_logic, for callers that cannot carry on without the logic
+    pub fn logic(&self,num:usize) -> &LogicResource {
+        self.get_logic(num).unwrap_or_else(|| panic!("Logic {} is missing or failed to load",num))
+    }
+
+    /// Loads a resource ahead of its use (load.view, load.logics etc.), false if it is missing or broken
+    pub fn load(&self,resource_type:ResourceType,num:usize) -> bool {
+        match resource_type {
+            ResourceType::Views => self.get_view(num).is_some(),
+            ResourceType::Pictures => self.get_picture(num).is_some(),
+            ResourceType::Logic => self.get_logic(num).is_some(),
+            ResourceType::Sounds => self.get_sound(num).is_some(),
+            ResourceType::Objects | ResourceType::Words => true,
         }
-        pictures.shrink_to_fit();
+    }
 
-        let dir = ResourceDirectory::new(&root, ResourceType::Logic).unwrap();
+    pub fn is_loaded(&self,resource_type:ResourceType,num:usize) -> bool {
+        self.memory.borrow().loaded.contains_key(&(resource_type,num))
+    }
 
-        let mut logic:HashMap<usize,LogicResource> = HashMap::new();
-        logic.reserve(256);
-        for (index,entry) in dir.into_iter().enumerate() {
-            if !entry.empty() {
-                if let std::collections::hash_map::Entry::Vacant(e) = volumes.entry(entry.volume) {
-                    let bytes = root.fetch_volume(&entry);
-                    e.insert(Volume::new(bytes.into_iter())?);
-                }
-                logic.insert(index, LogicResource::new(&volumes[&entry.volume],&entry,&ResourcesVersion::new(version))?);
+    /// Gives the resource's memory back, the parsed resource itself is dropped by release_discarded
+    pub fn discard(&self,resource_type:ResourceType,num:usize) {
+        let mut memory = self.memory.borrow_mut();
+        if memory.loaded.remove(&(resource_type,num)).is_some() {
+            memory.discarded.push((resource_type,num));
+        }
+    }
+
+    /// Discards everything but logic 0, as happens on new.room
+    pub fn discard_room(&self) {
+        let loaded:Vec<(ResourceType,usize)> = self.memory.borrow().loaded.keys().copied().collect();
+        for (resource_type,num) in loaded {
+            if (resource_type,num) != (ResourceType::Logic,0) {
+                self.discard(resource_type,num);
             }
         }
-        logic.shrink_to_fit();
+    }
 
-        return Ok(GameResources {
-            version: *root.version(),
-            words : Words::new(root.read_data_or_default("WORDS.TOK").into_iter())?,
-            objects: Objects::new(&root.read_data_or_default("OBJECT"))?,
-            views,
-            pictures,
-            logic,
-            font,
-        });
+    /// Drops resources discarded since the last call, unless they were used again in the meantime. Both the
+    /// parsed resource and its payload in the store are freed.
+    pub fn release_discarded(&mut self) {
+        let memory = self.memory.get_mut();
+        let discarded:Vec<(ResourceType,usize)> = memory.discarded.drain(..).filter(|d| !memory.loaded.contains_key(d)).collect();
+        let store = self.store.get_mut();
+        for (resource_type,num) in discarded {
+            let entry = match resource_type {
+                ResourceType::Views => self.views.get_mut(&num).map(|l| { l.resource.take(); l.entry }),
+                ResourceType::Pictures => self.pictures.get_mut(&num).map(|l| { l.resource.take(); l.entry }),
+                ResourceType::Logic => self.logic.get_mut(&num).map(|l| { l.resource.take(); l.entry }),
+                ResourceType::Sounds => self.sounds.get_mut(&num).map(|l| { l.resource.take(); l.entry }),
+                ResourceType::Objects | ResourceType::Words => None,
+            };
+            if let Some(entry) = entry {
+                store.evict(&entry);
+            }
+        }
+    }
+
+    pub fn get_memory_used(&self) -> usize {
+        self.memory.borrow().used()
+    }
+
+    pub fn get_max_memory_used(&self) -> usize {
+        self.memory.borrow().max_used
+    }
+
+    /// Value for VAR_FREE_PAGES
+    pub fn get_free_pages(&self) -> u8 {
+        (RESOURCE_HEAP_SIZE.saturating_sub(self.get_memory_used())/256).min(255) as u8
+    }
+
+    pub fn set_script_size(&self,size:u8) {
+        self.memory.borrow_mut().script_size=size;
+    }
+
+    /// Text shown by show.mem, laid out as original AGI does
+    pub fn get_memory_report(&self) -> String {
+        let memory = self.memory.borrow();
+        let room_zero = memory.loaded.get(&(ResourceType::Logic,0)).copied().unwrap_or_default();
+        format!("heapsize: {}\nnow: {}  max: {}\nrm.0, etc.: {}\nmax script: {}",RESOURCE_HEAP_SIZE,memory.used(),memory.max_used,room_zero,memory.script_size)
     }
 }
 
@@ -791,6 +930,90 @@ impl AgiRandom {
     }
 }
 
+/// Receives rendered sound, implemented by the frontend's audio device
+pub trait AudioSink {
+    fn get_sample_rate(&self) -> u32;
+    fn get_channels(&self) -> usize;
+    fn queue_samples(&mut self,samples:&[i16]);
+}
+
+const SOUND_DEFAULT_SAMPLE_RATE:u32 = 22050;
+const SOUND_TICKS_PER_SECOND:u64 = 60;
+const SOUND_MAX_CATCH_UP_TICKS:u128 = 15;
+
+#[derive(Serialize,Deserialize)]
+pub struct SoundPlayer {
+    playing:Option<u8>,
+    #[serde(serialize_with = "serialize_type_flag")]
+    #[serde(deserialize_with = "deserialize_type_flag")]
+    flag:TypeFlag,
+    ticks:u32,
+    #[serde(skip)]
+    renderer:Option<SoundRenderer>,    // rebuilt (and fast forwarded to ticks) after a restore
+    #[serde(skip)]
+    buffer:Vec<i16>,
+}
+
+impl SoundPlayer {
+    pub fn new() -> SoundPlayer {
+        SoundPlayer { playing: None, flag: TypeFlag::from(0u8), ticks: 0, renderer: None, buffer: Vec::new() }
+    }
+
+    pub fn get_playing(&self) -> Option<u8> {
+        self.playing
+    }
+
+    pub fn get_ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    fn start(&mut self,num:u8,flag:&TypeFlag) {
+        self.playing=Some(num);
+        self.flag = *flag;
+        self.ticks=0;
+        self.renderer=None;
+    }
+
+    fn stop(&mut self) -> Option<TypeFlag> {
+        self.renderer=None;
+        self.playing.take().map(|_| self.flag)
+    }
+
+    fn tick(&mut self,sound:&SoundResource,voice_count:usize,sink:Option<&mut dyn AudioSink>) -> Option<TypeFlag> {
+        let sample_rate = sink.as_ref().map(|s| s.get_sample_rate()).unwrap_or(SOUND_DEFAULT_SAMPLE_RATE);
+        if self.renderer.as_ref().map(|r| r.get_sample_rate()!=sample_rate).unwrap_or(true) {
+            let mut renderer = SoundRenderer::new(sample_rate);
+            renderer.set_voice_count(voice_count);
+            while renderer.get_ticks()<self.ticks && !renderer.is_finished() {
+                renderer.skip_tick(sound);
+            }
+            self.renderer=Some(renderer);
+        }
+
+        let renderer = self.renderer.as_mut().unwrap();
+        renderer.set_voice_count(voice_count);
+        match sink {
+            Some(sink) => {
+                self.buffer.clear();
+                renderer.render_tick(sound,&mut self.buffer,sink.get_channels());
+                sink.queue_samples(&self.buffer);
+            },
+            None => renderer.skip_tick(sound),
+        }
+        self.ticks=renderer.get_ticks();
+
+        if renderer.is_finished() {
+            return self.stop();
+        }
+        None
+    }
+}
+
+impl Default for SoundPlayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Serialize,Deserialize)]
 pub struct LogicState {
@@ -821,6 +1044,8 @@ pub struct LogicState {
     #[serde(with = "serde_arrays")]
     item_location:[u8;256],
 
+    sound:SoundPlayer,
+
     num_string:String,
     command_input:String,
     previous_input:String,
@@ -902,6 +1127,7 @@ impl LogicState {
             words_found: 0,
             item_location: [0u8;256],
             logic_start: [0usize;256],
+            sound: SoundPlayer::new(),
             num_string: String::from(""),
             command_input: String::from(""),
             previous_input: String::from(""),
@@ -944,6 +1170,51 @@ impl LogicState {
         }
     }
 
+    pub fn sound(&self) -> &SoundPlayer {
+        &self.sound
+    }
+
+    pub fn start_sound(&mut self,resources:&GameResources,num:u8,flag:&TypeFlag) {
+        self.stop_sound();
+        self.set_flag(flag,false);
+        if !self.get_flag(&FLAG_SOUND_ENABLED) || resources.get_sound(num as usize).is_none() {
+            self.set_flag(flag,true);
+            return;
+        }
+        self.sound.start(num,flag);
+    }
+
+    pub fn stop_sound(&mut self) {
+        if let Some(flag) = self.sound.stop() {
+            self.set_flag(&flag,true);
+        }
+    }
+
+    /// Advances the playing sound by one tick (1/60th second), sets the sound's flag once it has finished
+    pub fn update_sound(&mut self,resources:&GameResources,sink:Option<&mut dyn AudioSink>) {
+        let num = match self.sound.get_playing() {
+            Some(num) => num,
+            None => return,
+        };
+        if !self.get_flag(&FLAG_SOUND_ENABLED) {
+            self.stop_sound();
+            return;
+        }
+        // 1 is a PC speaker (voice 0 only), 3 or more is the PCjr/Tandy chip with all voices
+        let voice_count = match self.get_var(&VAR_SOUND_CHANNEL_COUNT) {
+            0 | 1 => 1,
+            2 => 2,
+            _ => SOUND_VOICES,
+        };
+        let flag = match resources.get_sound(num as usize) {
+            Some(sound) => self.sound.tick(sound,voice_count,sink),
+            None => self.sound.stop(),
+        };
+        if let Some(flag) = flag {
+            self.set_flag(&flag,true);
+        }
+    }
+
     pub fn get_menu_allowed(&self) -> bool {
         self.menu_allowed
     }
@@ -1595,11 +1866,20 @@ pub struct Interpretter {
     pub instruction_breakpoints:HashMap<&'static str,bool>,
     pub started:u64,     // 1/20 ticks since started, so seconds is divdide this by 20
     pub real_time:Instant,
+    pub audio_sink:Option<Box<dyn AudioSink>>,
+    sound_clock:Instant,    // real time the playing sound has been advanced up to
 }
 
 impl Interpretter {
-    pub fn new(base_path:&'static str,version:&str) -> Result<Interpretter,String> {
-        let resources = GameResources::new(base_path,version)?;
+    pub fn new(base_path:&'static str,version:Option<&str>) -> Result<Interpretter,String> {
+        Interpretter::new_from_resources(GameResources::new(base_path,version)?)
+    }
+
+    pub fn new_from_root(root:&Root) -> Result<Interpretter,String> {
+        Interpretter::new_from_resources(GameResources::new_from_root(root)?)
+    }
+
+    fn new_from_resources(resources:GameResources) -> Result<Interpretter,String> {
         let mut i = Interpretter {
             resources,
             state: LogicState::new(),
@@ -1608,9 +1888,11 @@ impl Interpretter {
             instruction_breakpoints: HashMap::new(),
             started:0,
             real_time:Instant::now(),
+            audio_sink:None,
+            sound_clock:Instant::now(),
         };
         i.state.set_var(&VAR_TIME_DELAY,2);
-        i.state.set_var(&VAR_FREE_PAGES,255);
+        i.state.set_var(&VAR_FREE_PAGES,i.resources.get_free_pages());
         i.state.set_var(&VAR_SOUND_CHANNEL_COUNT,1);
         i.state.set_var(&VAR_COMPUTER_TYPE,0);
         i.state.set_var(&VAR_MONITOR_TYPE,3);   // EGA
@@ -1624,12 +1906,12 @@ impl Interpretter {
         !self.state.stack.is_empty() && !self.state.stack[self.state.stack.len()-1].is_input_request()
     }
 
-    pub fn do_call(breakpoints:&mut HashMap<LogicExecutionPosition,bool>,instruction_breakpoints:&mut HashMap<&'static str,bool>,resources:&GameResources,state:&mut LogicState, logics:&HashMap<usize,LogicResource>,resume:bool,single_step:bool) {
+    pub fn do_call(breakpoints:&mut HashMap<LogicExecutionPosition,bool>,instruction_breakpoints:&mut HashMap<&'static str,bool>,resources:&GameResources,state:&mut LogicState,resume:bool,single_step:bool) {
         let mut resume = resume || single_step;
         while !state.stack.is_empty() {
             let stack_pos = state.stack.len()-1;
             let entry = state.stack[stack_pos];
-            let logic_sequence = logics[&entry.get_logic()].get_logic_sequence();
+            let logic_sequence = resources.logic(entry.get_logic()).get_logic_sequence();
             let actions = logic_sequence.get_operations();
             let mut exec = entry;
             loop {
@@ -1689,11 +1971,11 @@ impl Interpretter {
 
     }
 
-    pub fn call(breakpoints:&mut HashMap<LogicExecutionPosition,bool>,instruction_breakpoints:&mut HashMap<&'static str,bool>,resources:&GameResources,state:&mut LogicState,logic_file:usize, logics:&HashMap<usize,LogicResource>,resume:bool,single_step:bool) {
+    pub fn call(breakpoints:&mut HashMap<LogicExecutionPosition,bool>,instruction_breakpoints:&mut HashMap<&'static str,bool>,resources:&GameResources,state:&mut LogicState,logic_file:usize,resume:bool,single_step:bool) {
         if state.stack.is_empty() {
             state.stack.push(LogicExecutionPosition::new(logic_file,0));
         }
-        Interpretter::do_call(breakpoints, instruction_breakpoints, resources, state,logics,resume,single_step);
+        Interpretter::do_call(breakpoints, instruction_breakpoints, resources, state,resume,single_step);
     }
 
     pub fn key_code_pressed(&mut self,key_code:AgiKeyCodes) {
@@ -1704,9 +1986,31 @@ impl Interpretter {
         self.keys.clear();
     }
 
+    pub fn set_audio_sink(&mut self,sink:Box<dyn AudioSink>) {
+        self.audio_sink=Some(sink);
+    }
+
+    /// Advances the playing sound by the number of ticks of real time since the last call, so it plays at
+    /// the right speed whatever the frame rate. After a long stall (e.g. the debugger pausing) it only catches up a little.
+    fn update_sound(&mut self) {
+        let tick = Duration::from_nanos(1_000_000_000/SOUND_TICKS_PER_SECOND);
+        let mut due = self.sound_clock.elapsed().as_nanos()/tick.as_nanos();
+        if due>SOUND_MAX_CATCH_UP_TICKS {
+            due=SOUND_MAX_CATCH_UP_TICKS;
+            self.sound_clock=Instant::now();
+        } else {
+            self.sound_clock+=tick*(due as u32);
+        }
+        for _ in 0..due {
+            self.state.update_sound(&self.resources,self.audio_sink.as_mut().map(|s| s.as_mut() as &mut dyn AudioSink));
+        }
+    }
+
     pub fn run(&mut self,resume:bool,single_step:bool) {
 
         let mut resuming = !self.state.stack.is_empty();
+        self.resources.release_discarded();
+        self.update_sound();
         let mutable_state = &mut self.state;
         if !resuming && mutable_state.menu_input {
             mutable_state.menu_buffer.copy_from_slice(&[255u8;SCREEN_WIDTH_USIZE*SCREEN_HEIGHT_USIZE]);
@@ -1841,7 +2145,7 @@ impl Interpretter {
                 mutable_state.reset_new_room();
             }
             
-            Interpretter::call(&mut self.breakpoints,&mut self.instruction_breakpoints,&self.resources,mutable_state, 0, &self.resources.logic,resume,single_step);
+            Interpretter::call(&mut self.breakpoints,&mut self.instruction_breakpoints,&self.resources,mutable_state, 0,resume,single_step);
             if !mutable_state.stack.is_empty() {
                 break;
             } else {
@@ -1946,6 +2250,7 @@ impl Interpretter {
     }
 
     pub fn new_room(resources:&GameResources,state:&mut LogicState,room:u8) {
+        state.stop_sound();
         state.text_buffer.copy_from_slice(&[255u8;SCREEN_WIDTH_USIZE*SCREEN_HEIGHT_USIZE]);
 
         // Stop.update()
@@ -1970,6 +2275,8 @@ impl Interpretter {
 
         }
         //destroy all resources
+        resources.discard_room();
+        state.set_var(&VAR_FREE_PAGES,resources.get_free_pages());
         state.set_player_control();
         //unblock()
         state.set_horizon(36);
@@ -1982,7 +2289,7 @@ impl Interpretter {
         
         let c = usize::from(state.object(&OBJECT_EGO).get_cel());
         let v = usize::from(state.object(&OBJECT_EGO).get_view());
-        if resources.views.contains_key(&v) {
+        if resources.get_view(v).is_some() {
             let cels = get_cells_clamped(resources, state.object(&OBJECT_EGO));
             let cell = &cels[c];
 
@@ -2068,7 +2375,7 @@ impl Interpretter {
     }
 
     fn decode_message_from_resource(state:&LogicState,resources:&GameResources,file:usize,message:&TypeMessage) -> String {
-        Self::decode_message_from_string(state,resources,file,&resources.logic[&file].get_logic_messages().strings[state.get_message(message) as usize])
+        Self::decode_message_from_string(state,resources,file,&resources.logic(file).get_logic_messages().strings[state.get_message(message) as usize])
     }
 
     fn handle_window_with_key(resources:&GameResources,state:&mut LogicState,m:String,x:u8,y:u8,w:u8) -> Option<AgiKeyCodes> {
@@ -2117,8 +2424,6 @@ impl Interpretter {
 
         match action {
             // Not complete
-            ActionOperation::Sound((_num,flag)) => /* TODO RAGI  - for now, just pretend sound finished*/ {/*println!("TODO : Sound@{}",pc); */state.set_flag(flag,true);},
-            ActionOperation::StopSound(()) => /* TODO RAGI - for now, since we complete sounds straight away, does nothing */ {/*println!("TODO : StopSound@{}",pc);*/},
             ActionOperation::TraceInfo((num1,num2,num3)) => /* TODO RAGI */ { println!("TODO : TraceInfo@{} {} {} {}",pc,state.get_num(num1),state.get_num(num2),state.get_num(num3)); }
             ActionOperation::ShakeScreen((num,)) => /* TODO RAGI */ println!("TODO : ShakeScreen@{} {:?}",pc,num),
             ActionOperation::PrintAtV0((m,x,y,)) => /* TODO RAGI */ { let m = Interpretter::decode_message_from_resource(state, resources, pc.logic_file, m); println!("TODO : PrintAtV0@{} {} {},{}",pc,m,state.get_num(x),state.get_num(y)); },
@@ -2135,17 +2440,27 @@ impl Interpretter {
             
 
             // Not needed
-            ActionOperation::ScriptSize((_num,)) => {/* NO-OP-RAGI */},
-            ActionOperation::LoadView((_num,)) => {/* NO-OP-RAGI */},
-            ActionOperation::LoadViewV((_var,)) => {/* NO-OP-RAGI */},
-            ActionOperation::LoadPic((_var,)) => {/* NO-OP-RAGI */},
-            ActionOperation::LoadLogic((_num,)) => {/* NO-OP-RAGI */},
-            ActionOperation::LoadLogicV((_var,)) => {/* NO-OP-RAGI */},
-            ActionOperation::LoadSound((_num,)) => {/* NO-OP-RAGI */},
-            ActionOperation::DiscardPic((_var,)) => {/* NO-OP-RAGI */},
-            ActionOperation::DiscardView((_num,)) => {/* NO-OP-RAGI */},
+            ActionOperation::ScriptSize((num,)) => resources.set_script_size(state.get_num(num)),
+            ActionOperation::LoadView((num,)) => Self::load_resource(resources, state, ResourceType::Views, state.get_num(num)),
+            ActionOperation::LoadViewV((var,)) => Self::load_resource(resources, state, ResourceType::Views, state.get_var(var)),
+            ActionOperation::LoadPic((var,)) => Self::load_resource(resources, state, ResourceType::Pictures, state.get_var(var)),
+            ActionOperation::LoadLogic((num,)) => Self::load_resource(resources, state, ResourceType::Logic, state.get_num(num)),
+            ActionOperation::LoadLogicV((var,)) => Self::load_resource(resources, state, ResourceType::Logic, state.get_var(var)),
+            ActionOperation::LoadSound((num,)) => Self::load_resource(resources, state, ResourceType::Sounds, state.get_num(num)),
+            ActionOperation::DiscardPic((var,)) => Self::discard_resource(resources, state, ResourceType::Pictures, state.get_var(var)),
+            ActionOperation::DiscardView((num,)) => Self::discard_resource(resources, state, ResourceType::Views, state.get_num(num)),
+            ActionOperation::DiscardViewV((var,)) => Self::discard_resource(resources, state, ResourceType::Views, state.get_var(var)),
+            ActionOperation::DiscardSound((num,)) => Self::discard_resource(resources, state, ResourceType::Sounds, state.get_num(num)),
+            ActionOperation::ShowMem(()) => {
+                match Self::handle_window_with_key(resources, state, resources.get_memory_report(), 255, 255, 255) {
+                    Some(AgiKeyCodes::Escape) | Some(AgiKeyCodes::Enter) => {},
+                    _ => return Some(pc.user_input()),
+                }
+            },
 
             // Everything else
+            ActionOperation::Sound((num,flag)) => state.start_sound(resources,state.get_num(num),flag),
+            ActionOperation::StopSound(()) => state.stop_sound(),
             ActionOperation::If((condition,goto_if_false)) => {
                 let new_pc:LogicExecutionPosition;
                 if !Self::evaluate_condition(resources,state,condition) 
@@ -2196,9 +2511,11 @@ impl Interpretter {
             ActionOperation::SetCel((obj,num)) => { let n=state.get_num(num); state.mut_object(obj).set_cel(n,resources); },
             ActionOperation::DrawPic((var,)) => { 
                 let n = state.get_var(var); 
-                let (pic,pri) = resources.pictures[&usize::from(n)].render().unwrap();
-                state.picture_buffer.copy_from_slice(&pic);
-                state.priority_buffer.copy_from_slice(&pri);
+                if let Some(picture) = resources.get_picture(usize::from(n)) {
+                    let (pic,pri) = picture.render().unwrap();
+                    state.picture_buffer.copy_from_slice(&pic);
+                    state.priority_buffer.copy_from_slice(&pri);
+                }
                 erase_all_add_to_pic(state); 
             },
             ActionOperation::ShowPic(()) => {
@@ -2650,7 +2967,7 @@ impl Interpretter {
                 *state=LogicState::new();
                 state.initialise_rooms(&resources.objects.objects);
                 state.set_var(&VAR_TIME_DELAY,2);
-                state.set_var(&VAR_FREE_PAGES,255);
+                state.set_var(&VAR_FREE_PAGES,resources.get_free_pages());
                 state.set_var(&VAR_SOUND_CHANNEL_COUNT,1);
                 state.set_var(&VAR_COMPUTER_TYPE,0);
                 state.set_var(&VAR_MONITOR_TYPE,3);   // EGA
@@ -2682,7 +2999,10 @@ impl Interpretter {
                     pic[i]=state.picture_buffer[i];
                     pri[i]=state.priority_buffer[i];
                 }
-                let r = resources.pictures[&usize::from(n)].render_onto(&mut pic,&mut pri);
+                let r = match resources.get_picture(usize::from(n)) {
+                    Some(picture) => picture.render_onto(&mut pic,&mut pri),
+                    None => Err(format!("Picture {} is missing or failed to load",n)),
+                };
                 if r.is_ok() {
                     state.picture_buffer.copy_from_slice(&pic);
                     state.priority_buffer.copy_from_slice(&pri);
@@ -2704,6 +3024,16 @@ impl Interpretter {
         Some(pc.next())
     }
  
+    fn load_resource(resources:&GameResources,state:&mut LogicState,resource_type:ResourceType,num:u8) {
+        resources.load(resource_type, num as usize);
+        state.set_var(&VAR_FREE_PAGES,resources.get_free_pages());
+    }
+
+    fn discard_resource(resources:&GameResources,state:&mut LogicState,resource_type:ResourceType,num:u8) {
+        resources.discard(resource_type, num as usize);
+        state.set_var(&VAR_FREE_PAGES,resources.get_free_pages());
+    }
+
     fn quit_check(resources:&GameResources,state:&mut LogicState,code:u8) -> Option<bool> {
         if code == 1 {
             Some(true)
@@ -2967,7 +3297,7 @@ impl Interpretter {
     }
 
     fn show_object(resources: &GameResources, state: &mut LogicState, logic:usize, v:usize) -> bool {
-        let view = &resources.views[&v];
+        let view = &resources.view(v);
         let m = view.get_description();
         let m = Interpretter::decode_message_from_string(state, resources, logic, m); 
         if state.displayed != *m {
@@ -3453,14 +3783,14 @@ pub fn is_left_and_right_edge_in_box(resources:&GameResources,state:&LogicState,
 // Todo cache these in the sprites
 pub fn get_loops<'a>(resources:&'a GameResources,obj:&Sprite) -> &'a Vec<ViewLoop> {
     let v = usize::from(obj.get_view());
-    let view = &resources.views[&v];
+    let view = &resources.view(v);
     view.get_loops()
 }
 
 pub fn get_cells_clamped<'a>(resources:&'a GameResources,obj:&Sprite) -> &'a Vec<ViewCel> {
     let v = usize::from(obj.get_view());
     let mut l = usize::from(obj.get_loop());
-    let view = &resources.views[&v];
+    let view = &resources.view(v);
     let loops = view.get_loops();
     if l>=loops.len() { 
         l=loops.len()-1;
@@ -3627,7 +3957,7 @@ fn add_view_to_pic(resources: &GameResources, state:&mut LogicState, view:u8, cl
     state.mut_object(&obj_num).set_y(y);
 
 
-    let view = &resources.views[&(view as usize)];
+    let view = &resources.view(view as usize);
     let loops = &view.get_loops()[cloop as usize];
     let cel = &loops.get_cels()[cel as usize];
     let w:usize = cel.get_width().into();
@@ -3667,6 +3997,7 @@ fn render_view_to_window(cell: &view::ViewCel, state: &mut LogicState, x:usize,
     }
 }
 
+// Cels of mirrored loops are already flipped by the view, so are drawn as stored
 fn render_sprite(obj_num:&TypeObject, cell: &view::ViewCel, state: &mut LogicState) {
 
     let x = usize::from(state.object(obj_num).get_x());
@@ -3675,13 +4006,12 @@ fn render_sprite(obj_num:&TypeObject, cell: &view::ViewCel, state: &mut LogicSta
     let w = usize::from(cell.get_width());
     let t = cell.get_transparent_colour();
     let d = cell.get_data();
-    let mirror=cell.is_mirror(state.object(obj_num).get_loop());
     if y<h {
         h=y;
     }
     for yy in 0..h {
         for xx in 0..w {
-            let col = if mirror {d[(w-xx-1)+yy*w]} else {d[xx+yy*w] };
+            let col = d[xx+yy*w];
             if col != t {
                 let sx = xx+x;
                 let sy = yy+y-h;
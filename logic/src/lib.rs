@@ -3,7 +3,7 @@ use std::{collections::{HashMap, VecDeque}, hash::Hash, ops};
 use dir_resource::{ResourceDirectoryEntry, ResourcesVersion, ResourceCompression};
 
 use objects::Objects;
-use volume::{Volume, VolumeCache};
+use volume::ResourceStore;
 use words::Words;
 
 use strum_macros::IntoStaticStr;
@@ -435,12 +435,14 @@ impl LogicMessages {
 }
 
 impl LogicResource {
-    pub fn new(volume:&Volume, entry: &ResourceDirectoryEntry, version:&ResourcesVersion) -> Result<LogicResource, &'static str> {
+    pub fn new(store:&mut ResourceStore, entry: &ResourceDirectoryEntry, version:&ResourcesVersion) -> Result<LogicResource, String> {
 
-        let mut t=VolumeCache::new();
-        let data_slice = volume.fetch_data_slice(&mut t,entry).expect("Expected to be able to fetch slice from entry");
-        let slice = data_slice.0;
+        let (data,compression) = store.fetch(entry)?;
+        LogicResource::new_from_slice(&data,compression,version)
+    }
 
+    /// Logic from a payload fetched out of a volume, compression says whether the messages are still encrypted
+    pub fn new_from_slice(slice:&[u8], compression:ResourceCompression, version:&ResourcesVersion) -> Result<LogicResource, String> {
         if slice.len() < 2 {
             let logic_messages = LogicMessages::make_empty();
             let logic_sequence = LogicSequence::make_empty();
@@ -459,7 +461,7 @@ impl LogicResource {
         let logic_slice = &slice[2..text_start+2];
         let text_slice = &slice[text_start+2..];
 
-        let logic_messages = LogicMessages::new(text_slice,data_slice.1).expect("Error : ");
+        let logic_messages = LogicMessages::new(text_slice,compression).expect("Error : ");
         let logic_sequence = LogicSequence::new(logic_slice,version).expect("Error : ");
 
         Ok(LogicResource {logic_sequence, logic_messages})
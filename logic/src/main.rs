@@ -4,7 +4,7 @@ use std::{fs};
 use dir_resource::{ResourceDirectory, ResourceDirectoryEntry, Root, ResourceType};
 use logic::LogicResource;
 use objects::Objects;
-use volume::{Volume, VolumeCache};
+use volume::ResourceStore;
 use words::Words;
 
 fn main() {
@@ -34,29 +34,24 @@ fn main() {
     };
 
     let dir = ResourceDirectory::new(&root, ResourceType::Logic).unwrap();
+    let mut store = ResourceStore::new_from_root(&root).unwrap();
 
     for (index,entry) in dir.into_iter().enumerate() {
         if !entry.empty() {
             println!("{} : V{} P{}",index,entry.volume,entry.position);
-            dump_logic_resource(&root,&entry,index,&items,&words);
+            dump_logic_resource(&root,&mut store,&entry,index,&items,&words);
         }
     }
 
 }
 
-fn dump_logic_resource(root:&Root,entry:&ResourceDirectoryEntry,index:usize,items:&Objects,words:&Words) {
+fn dump_logic_resource(root:&Root,store:&mut ResourceStore,entry:&ResourceDirectoryEntry,index:usize,items:&Objects,words:&Words) {
 
-    let bytes = root.fetch_volume(entry);
+    let (data,_) = store.fetch(entry).unwrap();
 
-    let mut t = VolumeCache::new();
-    let volume = Volume::new(bytes.into_iter()).unwrap();
+    fs::write(format!("../{}-binary.bin",index).as_str(),&data[..]).unwrap();
 
-    let data_slice = volume.fetch_data_slice(&mut t,entry).unwrap();
-    let data = data_slice.0;
-
-    fs::write(format!("../{}-binary.bin",index).as_str(),data).unwrap();
-
-    let logic_resource = LogicResource::new(&volume,entry,root.version()).unwrap();
+    let logic_resource = LogicResource::new(store,entry,root.version()).unwrap();
 
     logic_resource.disassemble(items,words);
 }
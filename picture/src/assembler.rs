@@ -0,0 +1,303 @@
+use volume::agi_picture_compress;
+
+use crate::{PictureResource, PictureCommand, PenStyle};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{decode_picture, PlotPoint, test_pictures};
+
+    fn assert_same_render(data:&[u8],compressed:bool,commands:&[PictureCommand]) {
+        let original = PictureResource::new_from_slice(data,compressed).render().unwrap();
+        let encoded = PictureResource::new_from_commands(commands,compressed).unwrap().render().unwrap();
+        assert!(original.0==encoded.0);
+        assert!(original.1==encoded.1);
+    }
+
+    fn commands_of(data:&[u8]) -> Vec<PictureCommand> {
+        decode_picture(data,false).unwrap().into_iter().map(|(_,c)| c).collect()
+    }
+
+    // Deterministic random pictures using every command equally
+    fn noise_picture(seed:u32) -> Vec<u8> {
+        test_pictures::noise_picture(seed,40,&[0xF0,0xF1,0xF2,0xF3,0xF4,0xF5,0xF6,0xF7,0xF8,0xF9,0xFA])
+    }
+
+    #[test]
+    fn encode_corner_line() {
+        let commands = [PictureCommand::AbsoluteLine(vec![(10,10),(20,10),(20,30),(5,30)])];
+        assert_eq!(encode_picture(&commands,false).unwrap(),vec![0xF5,10,10,20,30,5,0xFF]);
+        let commands = [PictureCommand::AbsoluteLine(vec![(10,10),(10,50),(90,50)])];
+        assert_eq!(encode_picture(&commands,false).unwrap(),vec![0xF4,10,10,50,90,0xFF]);
+    }
+
+    #[test]
+    fn encode_fail_empty_line() {
+        assert!(encode_picture(&[PictureCommand::AbsoluteLine(vec![])],false).is_err());
+        let commands = [PictureCommand::AbsoluteLine(vec![(10,10),(20,10)]),PictureCommand::AbsoluteLine(vec![])];
+        assert!(encode_picture(&commands,false).is_err());
+    }
+
+    #[test]
+    fn encode_relative_line() {
+        let commands = [PictureCommand::AbsoluteLine(vec![(10,10),(13,15),(9,12),(16,5)])];
+        assert_eq!(encode_picture(&commands,false).unwrap(),vec![0xF7,10,10,0x35,0xCB,0x7F,0xFF]);
+        // -7 in x would make the step byte look like a command
+        let commands = [PictureCommand::AbsoluteLine(vec![(10,10),(3,12)])];
+        assert_eq!(encode_picture(&commands,false).unwrap(),vec![0xF6,10,10,3,12,0xFF]);
+    }
+
+    #[test]
+    fn encode_joins_lines() {
+        let commands = [
+            PictureCommand::SetColour(4),
+            PictureCommand::AbsoluteLine(vec![(10,10),(100,100)]),
+            PictureCommand::RelativeLine { start:(100,100), steps:vec![(2,2)] },
+            PictureCommand::Fill(vec![(1,1)]),
+            PictureCommand::Fill(vec![(2,2)]),
+        ];
+        assert_eq!(encode_picture(&commands,false).unwrap(),vec![0xF0,4,0xF6,10,10,100,100,102,102,0xF8,1,1,2,2,0xFF]);
+    }
+
+    #[test]
+    fn encode_mixed_line() {
+        // Splits into a corner run, an absolute jump and relative steps
+        let points = vec![(10,10),(50,10),(50,60),(90,60),(90,100),(30,100),(140,2),(143,5),(146,8),(149,11),(152,14),(155,17),(158,20)];
+        let commands = [PictureCommand::SetColour(1),PictureCommand::AbsoluteLine(points.clone())];
+        let mut absolute = vec![0xF0,1,0xF6];
+        absolute.extend(points.iter().flat_map(|(x,y)| [*x,*y]));
+        absolute.push(0xFF);
+        let encoded = encode_picture(&commands,false).unwrap();
+        assert_eq!(encoded[2..6],[0xF5,10,10,50]);
+        assert_eq!(encoded.len(),absolute.len()-5);
+        assert_same_render(&absolute,false,&commands);
+    }
+
+    #[test]
+    fn encode_plot_texture_follows_pen() {
+        let commands = [
+            PictureCommand::Plot(vec![PlotPoint { texture:0, x:10, y:20 }]),
+            PictureCommand::SetPen(PenStyle { size:2, rectangle:true, splatter:true }),
+            PictureCommand::Plot(vec![PlotPoint { texture:8, x:30, y:40 }]),
+        ];
+        assert_eq!(encode_picture(&commands,false).unwrap(),vec![0xFA,10,20,0xF9,0x32,0xFA,8,30,40,0xFF]);
+    }
+
+    #[test]
+    fn encode_compressed() {
+        let commands = commands_of(&noise_picture(7));
+        let uncompressed = encode_picture(&commands,false).unwrap();
+        assert_eq!(encode_picture(&commands,true).unwrap(),agi_picture_compress(&uncompressed));
+    }
+
+    #[test]
+    fn encode_fail_out_of_range() {
+        assert!(encode_picture(&[PictureCommand::SetColour(16)],false).is_err());
+        assert!(encode_picture(&[PictureCommand::Fill(vec![(0xF0,0)])],false).is_err());
+        assert!(encode_picture(&[PictureCommand::RelativeLine { start:(2,2), steps:vec![(-7,0)] }],false).is_err());
+    }
+
+    #[test]
+    fn encode_round_trip_renders() {
+        for seed in 0..50 {
+            let data = noise_picture(seed);
+            let commands = commands_of(&data);
+            assert_same_render(&data,false,&commands);
+            let compressed = agi_picture_compress(&data);
+            assert_same_render(&compressed,true,&commands);
+            assert!(commands_of(&encode_picture(&commands,false).unwrap()).len()<=commands.len());
+        }
+    }
+}
+
+/// Highest value an argument byte can take, anything above reads as a command
+const MAX_ARGUMENT:i16 = 0xEF;
+
+#[derive(Clone,Copy,PartialEq)]
+enum LineEncoding {
+    CornerX,
+    CornerY,
+    Relative,
+    Absolute,
+}
+
+fn is_argument(n:i16) -> bool {
+    (0..=MAX_ARGUMENT).contains(&n)
+}
+
+fn encode_argument(n:u8) -> Result<u8,String> {
+    if n as i16 > MAX_ARGUMENT {
+        return Err(format!("Picture argument {:02X} would be read as a command",n));
+    }
+    Ok(n)
+}
+
+fn encode_relative(d:i16) -> u8 {
+    if d<0 { 8 | (-d) as u8 } else { d as u8 }
+}
+
+/// Whether the segment from a to b can be the index'th step of a run in this encoding
+fn segment_fits(encoding:LineEncoding,index:usize,a:(i16,i16),b:(i16,i16)) -> bool {
+    let (dx,dy) = (b.0-a.0,b.1-a.1);
+    match encoding {
+        // A step of -7 in x would give a step byte of F?
+        LineEncoding::Relative => (-6..=7).contains(&dx) && (-7..=7).contains(&dy),
+        LineEncoding::Absolute => is_argument(b.0) && is_argument(b.1),
+        LineEncoding::CornerX | LineEncoding::CornerY => {
+            let along_x = (index&1==0) == (encoding==LineEncoding::CornerX);
+            if along_x { dy==0 && is_argument(b.0) } else { dx==0 && is_argument(b.1) }
+        },
+    }
+}
+
+/// Encodes a polyline as the shortest series of F4-F7 commands. Splitting a line is safe, as the next
+/// command just plots again the point the previous one finished on.
+fn encode_line(points:&[(i16,i16)],out:&mut Vec<u8>) -> Result<(),String> {
+    let start = match points.first() {
+        Some(p) => *p,
+        None => return Err("Line has no points".to_string()),
+    };
+    if points.len()==1 {
+        if !is_argument(start.0) || !is_argument(start.1) {
+            return Err(format!("Line point {:?} is outside the picture",start));
+        }
+        out.extend_from_slice(&[0xF6,start.0 as u8,start.1 as u8]);
+        return Ok(());
+    }
+
+    // best[i] is the cheapest way to draw from point i to the end, as (bytes, encoding, end of the first run)
+    let last = points.len()-1;
+    let mut best:Vec<Option<(usize,LineEncoding,usize)>> = vec![None;points.len()];
+    best[last] = Some((0,LineEncoding::Absolute,last));
+    for i in (0..last).rev() {
+        if !is_argument(points[i].0) || !is_argument(points[i].1) {
+            continue;
+        }
+        for encoding in [LineEncoding::CornerX,LineEncoding::CornerY,LineEncoding::Relative,LineEncoding::Absolute] {
+            let step_size = if encoding==LineEncoding::Absolute {2} else {1};
+            for j in i+1..=last {
+                if !segment_fits(encoding,j-i-1,points[j-1],points[j]) {
+                    break;
+                }
+                if let Some((rest,_,_)) = best[j] {
+                    let cost = 3+step_size*(j-i)+rest;
+                    if best[i].is_none_or(|(b,_,_)| cost<b) {
+                        best[i] = Some((cost,encoding,j));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut i = 0;
+    while i<last {
+        let (_,encoding,j) = match best[i] {
+            Some(b) => b,
+            None => return Err(format!("Line point {:?} is outside the picture",points[i])),
+        };
+        let code = match encoding {
+            LineEncoding::CornerX => 0xF5,
+            LineEncoding::CornerY => 0xF4,
+            LineEncoding::Relative => 0xF7,
+            LineEncoding::Absolute => 0xF6,
+        };
+        out.extend_from_slice(&[code,points[i].0 as u8,points[i].1 as u8]);
+        for k in i+1..=j {
+            let (a,b) = (points[k-1],points[k]);
+            match encoding {
+                LineEncoding::CornerX | LineEncoding::CornerY => {
+                    let along_x = ((k-i-1)&1==0) == (encoding==LineEncoding::CornerX);
+                    out.push(if along_x { b.0 as u8 } else { b.1 as u8 });
+                },
+                LineEncoding::Relative => out.push((encode_relative(b.0-a.0)<<4) | encode_relative(b.1-a.1)),
+                LineEncoding::Absolute => out.extend_from_slice(&[b.0 as u8,b.1 as u8]),
+            }
+        }
+        i = j;
+    }
+    Ok(())
+}
+
+/// Assembles picture commands into picture data ending in FF, in the v3 nibble packed form if compressed.
+/// Lines that join up are merged and re-encoded in the most compact form, as are runs of fills and plots.
+pub fn encode_picture(commands:&[PictureCommand],compressed:bool) -> Result<Vec<u8>,String> {
+    let mut out:Vec<u8> = Vec::new();
+    let mut pen = PenStyle::default();
+    let mut index = 0;
+
+    while index<commands.len() {
+        let command = &commands[index];
+        index+=1;
+
+        if let Some(mut points) = command.get_line_points() {
+            while let Some(next) = commands.get(index).and_then(PictureCommand::get_line_points) {
+                if next.is_empty() || next.first()!=points.last() {
+                    break;
+                }
+                points.extend_from_slice(&next[1..]);
+                index+=1;
+            }
+            encode_line(&points,&mut out)?;
+            continue;
+        }
+
+        match command {
+            PictureCommand::SetColour(c) | PictureCommand::SetPriority(c) => {
+                if *c>15 {
+                    return Err(format!("Pen colour {} is out of range",c));
+                }
+                out.push(if matches!(command,PictureCommand::SetColour(_)) {0xF0} else {0xF2});
+                out.push(*c);
+            },
+            PictureCommand::DisableColour => out.push(0xF1),
+            PictureCommand::DisablePriority => out.push(0xF3),
+            PictureCommand::SetPen(style) => {
+                pen = *style;
+                out.extend_from_slice(&[0xF9,style.to_byte()]);
+            },
+            PictureCommand::Fill(points) => {
+                let mut points = points.clone();
+                while let Some(PictureCommand::Fill(next)) = commands.get(index) {
+                    points.extend_from_slice(next);
+                    index+=1;
+                }
+                if !points.is_empty() {
+                    out.push(0xF8);
+                    for (x,y) in points {
+                        out.extend_from_slice(&[encode_argument(x)?,encode_argument(y)?]);
+                    }
+                }
+            },
+            PictureCommand::Plot(points) => {
+                let mut points = points.clone();
+                while let Some(PictureCommand::Plot(next)) = commands.get(index) {
+                    points.extend_from_slice(next);
+                    index+=1;
+                }
+                if !points.is_empty() {
+                    out.push(0xFA);
+                    for point in points {
+                        if pen.splatter {
+                            out.push(encode_argument(point.texture)?);
+                        }
+                        out.extend_from_slice(&[encode_argument(point.x)?,encode_argument(point.y)?]);
+                    }
+                }
+            },
+            PictureCommand::CornerLine { .. } | PictureCommand::AbsoluteLine(_) | PictureCommand::RelativeLine { .. } => {},
+        }
+    }
+    out.push(0xFF);
+
+    if compressed {
+        return Ok(agi_picture_compress(&out));
+    }
+    Ok(out)
+}
+
+impl PictureResource {
+    /// Picture assembled from commands, see encode_picture
+    pub fn new_from_commands(commands:&[PictureCommand],compressed:bool) -> Result<PictureResource,String> {
+        Ok(PictureResource::new_from_slice(&encode_picture(commands,compressed)?,compressed))
+    }
+}
@@ -0,0 +1,69 @@
+use dir_resource::Root;
+use dir_resource::source::{GameFileSource, DirectorySource, ZipSource};
+use helpers::dump_png;
+use picture::*;
+use picture::diff::{diff_roots, CommandChange};
+
+// Compares the pictures of two games (folders or zip archives), e.g. two releases of the same game:
+//   picture_diff <game a> <game b> [output folder] [version a] [version b]
+// Writes <number>-diff.png to the output folder for each picture that differs. The interpreter versions
+// are detected from the game files unless given.
+
+fn open_game<'a>(path:&str,version:Option<&String>) -> Result<Root<'a>, String> {
+    let source:Box<dyn GameFileSource> = if path.to_lowercase().ends_with(".zip") {
+        Box::new(ZipSource::open(path)?)
+    } else {
+        Box::new(DirectorySource::new(path))
+    };
+    match version {
+        Some(version) => Ok(Root::new_from_source(source,version)),
+        None => Ok(Root::detect_source(source)?),
+    }
+}
+
+fn main() -> Result<(), String> {
+
+    let args:Vec<String> = std::env::args().collect();
+    if args.len()<3 {
+        return Err("Usage: picture_diff <game a> <game b> [output folder] [version a] [version b]".to_string());
+    }
+    let output = args.get(3).map(|s| s.as_str()).unwrap_or(".");
+
+    let root_a = open_game(&args[1],args.get(4))?;
+    let root_b = open_game(&args[2],args.get(5))?;
+
+    let diffs = diff_roots(&root_a,&root_b)?;
+    let mut changed = 0;
+    let mut failed = 0;
+    for (index,diff) in &diffs {
+        let diff = match diff {
+            Ok(diff) => diff,
+            Err(e) => {
+                failed+=1;
+                println!("Picture {} : failed - {}",index,e);
+                continue;
+            }
+        };
+        if diff.is_identical() {
+            continue;
+        }
+        changed+=1;
+        println!("Picture {} : {} command changes, {} visual pixels, {} priority pixels",index,diff.commands.len(),diff.get_visual_changes(),diff.get_priority_changes());
+        for change in &diff.commands {
+            match change {
+                CommandChange::Removed { index, command } => println!("  - {} {:?}",index,command),
+                CommandChange::Inserted { index, command } => println!("  + {} {:?}",index,command),
+                CommandChange::Changed { index_a, index_b, before, after } => println!("  ~ {}/{} {:?} -> {:?}",index_a,index_b,before,after),
+            }
+        }
+        let width:u32 = PIC_WIDTH_U8.into();
+        let height:u32 = PIC_HEIGHT_U8.into();
+        dump_png(format!("{}/{}-diff.png",output,index).as_str(),width,height,&diff.to_rgba());
+    }
+    println!("{} of {} shared pictures differ",changed,diffs.len());
+    if failed>0 {
+        println!("{} pictures could not be compared",failed);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,475 @@
+use crate::{PictureCommand, PictureProvenance, CommandSource, PenState, PenStyle, PlotPoint, PIC_WIDTH_U8, PIC_HEIGHT_U8, PIC_WIDTH_USIZE, PIC_HEIGHT_USIZE};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PictureResource, test_pictures};
+
+    // FNV-1a over the visual then priority screens
+    fn render_hash(resource:&PictureResource) -> u64 {
+        let (picture,priority) = resource.render().unwrap();
+        let mut hash = 0xcbf29ce484222325u64;
+        for b in picture.iter().chain(priority.iter()) {
+            hash = (hash ^ (*b as u64)).wrapping_mul(0x100000001b3);
+        }
+        hash
+    }
+
+    // Deterministic random pictures, weighted towards fills over lines with every pen combination
+    fn noise_picture(seed:u32) -> Vec<u8> {
+        test_pictures::noise_picture(seed,60,&[0xF0,0xF1,0xF2,0xF3,0xF4,0xF5,0xF6,0xF7,0xF8,0xF8,0xF8,0xF9,0xFA])
+    }
+
+    // Hashes of the renders from the original queue based fill, which must never change
+    const GOLDEN_HASHES:[u64;24] = [
+        0x6EBE4979CA19D2E2, 0x72FE291D3D88A66A, 0x3A568CCAB62EF207, 0xD2FDFAC24DA3A5ED,
+        0xA28D383D69D1F12D, 0xF3768833404DB203, 0x2D6E3859D579ACCD, 0xC7B52EDE26517A09,
+        0x30222C6553083F34, 0x5D2B171056C5F42D, 0xA28E93CA7E9C21B0, 0xE48039CC3F5C8B44,
+        0xF3148B33EE029A93, 0x6E0D075156A09AD1, 0x7743F466716740F0, 0xC8FD1856FDFE5333,
+        0x8CCD047663CD2212, 0xF707E0BE40B157BA, 0x668E4C2637B63829, 0x2FFEF1CA48BB5265,
+        0xDAF31BEE2B6C798B, 0x502E6C49BD7C0FF4, 0x8FFCE830C48D545D, 0x64AC318E628632C3];
+
+    #[test]
+    fn golden_hashes() {
+        for (seed,hash) in GOLDEN_HASHES.iter().enumerate() {
+            let resource = PictureResource::new_from_slice(&noise_picture(seed as u32),false);
+            assert_eq!(render_hash(&resource),*hash,"picture {}",seed);
+            assert_eq!(render_hash(&resource.to_compressed()),*hash,"compressed picture {}",seed);
+        }
+    }
+
+    #[test]
+    fn canvas_reuse() {
+        let mut canvas = PictureCanvas::new();
+        for seed in [3,5,3] {
+            let resource = PictureResource::new_from_slice(&noise_picture(seed),false);
+            canvas.clear();
+            resource.draw_onto_canvas(&mut canvas).unwrap();
+            let (picture,priority) = resource.render().unwrap();
+            assert!(canvas.picture==picture && canvas.priority==priority);
+        }
+    }
+
+    #[test]
+    fn empty_line_draws_nothing() {
+        let mut canvas = PictureCanvas::new();
+        canvas.draw(CommandSource { index:0, offset:0 },&PictureCommand::SetColour(1));
+        canvas.draw(CommandSource { index:1, offset:2 },&PictureCommand::AbsoluteLine(vec![]));
+        assert!(canvas.picture.iter().all(|c| *c==15));
+    }
+
+    #[test]
+    fn fill_below_picture_ignored() {
+        // Fill points past the bottom row used to index outside the screens
+        let resource = PictureResource::new_from_slice(&[0xF0,0x01,0xF8,0x10,0xB0,0xFF],false);
+        let (picture,_) = resource.render().unwrap();
+        assert!(picture.iter().all(|c| *c==15));
+    }
+}
+
+type Screen = [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE];
+
+/// The visual and priority screens and the pen state that picture commands draw with. A canvas can be cleared
+/// and reused, so rendering many pictures does not allocate per picture.
+pub struct PictureCanvas {
+    pub picture:Screen,
+    pub priority:Screen,
+    pub state:PenState,
+    provenance:Option<PictureProvenance>,
+    fill_stack:Vec<(u8,u8)>,
+}
+
+impl Default for PictureCanvas {
+    fn default() -> PictureCanvas {
+        PictureCanvas::new()
+    }
+}
+
+impl PictureCanvas {
+    /// Blank screens (white visual, priority 4) with the starting pen state
+    pub fn new() -> PictureCanvas {
+        PictureCanvas::new_from_screens(&[15u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],&[4u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE])
+    }
+
+    /// Canvas starting from existing screens, with the starting pen state
+    pub fn new_from_screens(picture:&Screen,priority:&Screen) -> PictureCanvas {
+        PictureCanvas { picture:*picture, priority:*priority, state:PenState::default(), provenance:None, fill_stack:Vec::new() }
+    }
+
+    /// Blanks the screens and resets the pen state (and provenance, if recorded)
+    pub fn clear(&mut self) {
+        self.picture.fill(15);
+        self.priority.fill(4);
+        self.state = PenState::default();
+        if self.provenance.is_some() {
+            self.provenance = Some(PictureProvenance::new());
+        }
+    }
+
+    /// Start recording which command writes each pixel
+    pub fn record_provenance(&mut self) {
+        self.provenance = Some(PictureProvenance::new());
+    }
+
+    pub fn get_provenance(&self) -> Option<&PictureProvenance> {
+        self.provenance.as_ref()
+    }
+
+    /// The recorded provenance, which stops recording
+    pub fn take_provenance(&mut self) -> Option<PictureProvenance> {
+        self.provenance.take()
+    }
+
+    /// Draws commands in order, as decoded by decode_picture
+    pub fn draw_commands(&mut self,commands:&[(usize,PictureCommand)]) {
+        for (index,(offset,command)) in commands.iter().enumerate() {
+            self.draw(CommandSource { index, offset:*offset },command);
+        }
+    }
+
+    /// Draws a single command, source is what provenance records for the pixels it writes
+    pub fn draw(&mut self,source:CommandSource,command:&PictureCommand) {
+        if let Some(provenance) = self.provenance.as_mut() {
+            provenance.current = Some(source);
+        }
+        match command {
+            PictureCommand::CornerLine { start, x_first, moves } => self.corner_line(*start,*x_first,moves),
+            PictureCommand::AbsoluteLine(points) => self.absolute_line(points),
+            PictureCommand::RelativeLine { start, steps } => self.relative_line(*start,steps),
+            PictureCommand::Fill(points) => {
+                for (x,y) in points {
+                    self.fill(*x,*y);
+                }
+            },
+            PictureCommand::Plot(points) => {
+                for point in points {
+                    self.plot_pen(point);
+                }
+            },
+            _ => {},
+        }
+        self.state.apply(command);
+    }
+
+    fn plot(&mut self,x:i16,y:i16) {
+        let width:i16 = PIC_WIDTH_U8.into();
+        let height:i16 = PIC_HEIGHT_U8.into();
+        if x<0 || x>(width-1) || y<0 || y>(height-1) {
+            return;
+        }
+        self.set((width*y+x) as usize);
+    }
+
+    fn set(&mut self,coord:usize) {
+        if self.state.colour_on {
+            self.picture[coord]=self.state.colour_pen;
+        }
+        if self.state.priority_on {
+            self.priority[coord]=self.state.priority_pen;
+        }
+        if let Some(provenance) = self.provenance.as_mut() {
+            provenance.record(coord,self.state.colour_on,self.state.priority_on);
+        }
+    }
+
+    fn plot_pen(&mut self,point:&PlotPoint) {
+        for_each_brush_pixel(self.state.plot_pen,point.texture,point.x.into(),point.y.into(),|x,y| self.plot(x,y));
+    }
+
+    fn line(&mut self,x0:i16,y0:i16,x1:i16,y1:i16) {
+
+        let dx = (x1-x0).abs();
+        let sx = if x0<x1 {1i16} else {-1i16};
+        let dy = -(y1-y0).abs();
+        let sy = if y0<y1 {1i16} else {-1i16};
+        let mut error = dx + dy;
+
+        let mut x=x0;
+        let mut y=y0;
+
+        loop {
+
+            self.plot(x,y);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                if x == x1 {
+                    break;
+                }
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                if y == y1 {
+                    break;
+                }
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn corner_line(&mut self,start:(u8,u8),x_first:bool,moves:&[u8]) {
+        let (mut x0,mut y0) = start;
+        let mut x = x_first;
+        self.plot(x0.into(),y0.into());
+
+        for n in moves {
+            let (mut x1,mut y1) = (x0,y0);
+            if x {
+                x1 = *n;
+            } else {
+                y1 = *n;
+            }
+            x = !x;
+            self.line(x0.into(),y0.into(),x1.into(),y1.into());
+            x0=x1;
+            y0=y1;
+        }
+    }
+
+    fn absolute_line(&mut self,points:&[(u8,u8)]) {
+        // A line without points draws nothing
+        let (mut x0,mut y0) = match points.first() {
+            Some(p) => *p,
+            None => return,
+        };
+        self.plot(x0.into(),y0.into());
+
+        for (x1,y1) in &points[1..] {
+            self.line(x0.into(),y0.into(),(*x1).into(),(*y1).into());
+            x0 = *x1;
+            y0 = *y1;
+        }
+    }
+
+    fn relative_line(&mut self,start:(u8,u8),steps:&[(i8,i8)]) {
+        let mut x0 = start.0 as i16;
+        let mut y0 = start.1 as i16;
+        self.plot(x0,y0);
+
+        for (dx,dy) in steps {
+            let x1 = x0 + *dx as i16;
+            let y1 = y0 + *dy as i16;
+            self.line(x0,y0,x1,y1);
+            x0=x1;
+            y0=y1;
+        }
+    }
+
+    /// Whether a fill can spread into this pixel - white on the visual screen, or priority 4 when only drawing priority
+    fn fillable(&self,coord:usize) -> bool {
+        if self.state.colour_on {
+            self.picture[coord]==15
+        } else {
+            self.priority[coord]==4
+        }
+    }
+
+    /// Scanline flood fill, filling the whole horizontal span around each seed then seeding the rows either side
+    fn fill(&mut self,x:u8,y:u8) {
+        let PenState { colour_pen, priority_pen, colour_on, priority_on, .. } = self.state;
+        if !colour_on && !priority_on {
+            return;
+        }
+        if x>=PIC_WIDTH_U8 || y>=PIC_HEIGHT_U8 {
+            return;
+        }
+        if colour_on && colour_pen==15 {
+            return;
+        }
+        if priority_on && !colour_on && priority_pen==4 {
+            return;
+        }
+
+        let width = PIC_WIDTH_USIZE;
+        let mut stack = std::mem::take(&mut self.fill_stack);
+        stack.clear();
+        stack.push((x,y));
+
+        while let Some((x,y)) = stack.pop() {
+            let row = (y as usize)*width;
+            if !self.fillable(row+x as usize) {
+                continue;
+            }
+            let mut left = x as usize;
+            while left>0 && self.fillable(row+left-1) {
+                left-=1;
+            }
+            let mut right = x as usize;
+            while right<width-1 && self.fillable(row+right+1) {
+                right+=1;
+            }
+            for x in left..=right {
+                self.set(row+x);
+            }
+
+            for next_y in [y.checked_sub(1),y.checked_add(1).filter(|y| *y<PIC_HEIGHT_U8)].into_iter().flatten() {
+                let next_row = (next_y as usize)*width;
+                let mut in_span = false;
+                for x in left..=right {
+                    let fillable = self.fillable(next_row+x);
+                    if fillable && !in_span {
+                        stack.push((x as u8,next_y));
+                    }
+                    in_span = fillable;
+                }
+            }
+        }
+
+        self.fill_stack = stack;
+    }
+}
+
+
+const CIRCLE_SLICE_0:&[u8] = &[1];
+const CIRCLE_SLICE_1:&[u8] = &[
+    0,0,
+    1,1,
+    0,0];
+const CIRCLE_SLICE_2:&[u8] = &[
+    0,1,0,
+    1,1,1,
+    1,1,1,
+    1,1,1,
+    0,1,0];
+const CIRCLE_SLICE_3:&[u8] = &[
+    0,1,1,0,
+    0,1,1,0,
+    1,1,1,1,
+    1,1,1,1,
+    1,1,1,1,
+    0,1,1,0,
+    0,1,1,0];
+const CIRCLE_SLICE_4:&[u8] = &[
+    0,0,1,0,0,
+    0,1,1,1,0,
+    1,1,1,1,1,
+    1,1,1,1,1,
+    1,1,1,1,1,
+    1,1,1,1,1,
+    1,1,1,1,1,
+    0,1,1,1,0,
+    0,0,1,0,0];
+const CIRCLE_SLICE_5:&[u8] = &[
+    0,0,1,1,0,0,
+    0,1,1,1,1,0,
+    0,1,1,1,1,0,
+    0,1,1,1,1,0,
+    1,1,1,1,1,1,
+    1,1,1,1,1,1,
+    1,1,1,1,1,1,
+    0,1,1,1,1,0,
+    0,1,1,1,1,0,
+    0,1,1,1,1,0,
+    0,0,1,1,0,0];
+const CIRCLE_SLICE_6:&[u8] = &[
+    0,0,1,1,1,0,0,
+    0,1,1,1,1,1,0,
+    0,1,1,1,1,1,0,
+    0,1,1,1,1,1,0,
+    1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,
+    0,1,1,1,1,1,0,
+    0,1,1,1,1,1,0,
+    0,1,1,1,1,1,0,
+    0,0,1,1,1,0,0];
+const CIRCLE_SLICE_7:&[u8] = &[
+    0,0,0,1,1,0,0,0,
+    0,0,1,1,1,1,0,0,
+    0,1,1,1,1,1,1,0,
+    0,1,1,1,1,1,1,0,
+    0,1,1,1,1,1,1,0,
+    1,1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,1,
+    1,1,1,1,1,1,1,1,
+    0,1,1,1,1,1,1,0,
+    0,1,1,1,1,1,1,0,
+    0,1,1,1,1,1,1,0,
+    0,0,1,1,1,1,0,0,
+    0,0,0,1,1,0,0,0];
+
+const CIRCLE_SLICE_TABLE:&[&[u8]] = &[CIRCLE_SLICE_0,CIRCLE_SLICE_1,CIRCLE_SLICE_2,CIRCLE_SLICE_3,CIRCLE_SLICE_4,CIRCLE_SLICE_5,CIRCLE_SLICE_6,CIRCLE_SLICE_7];
+
+const TEXTURE_DATA_SLICE:&[u8] = &[
+    0x20, 0x94, 0x02, 0x24, 0x90, 0x82, 0xa4, 0xa2,
+    0x82, 0x09, 0x0a, 0x22, 0x12, 0x10, 0x42, 0x14,
+    0x91, 0x4a, 0x91, 0x11, 0x08, 0x12, 0x25, 0x10,
+    0x22, 0xa8, 0x14, 0x24, 0x00, 0x50, 0x24, 0x04];
+
+const TEXTURE_DATA_BIT_OFFSET:&[u8] = &[
+    0x00, 0x18, 0x30, 0xc4, 0xdc, 0x65, 0xeb, 0x48,
+    0x60, 0xbd, 0x89, 0x04, 0x0a, 0xf4, 0x7d, 0x6d,
+    0x85, 0xb0, 0x8e, 0x95, 0x1f, 0x22, 0x0d, 0xdf,
+    0x2a, 0x78, 0xd5, 0x73, 0x1c, 0xb4, 0x40, 0xa1,
+    0xb9, 0x3c, 0xca, 0x58, 0x92, 0x34, 0xcc, 0xce,
+    0xd7, 0x42, 0x90, 0x0f, 0x8b, 0x7f, 0x32, 0xed,
+    0x5c, 0x9d, 0xc8, 0x99, 0xad, 0x4e, 0x56, 0xa6,
+    0xf7, 0x68, 0xb7, 0x25, 0x82, 0x37, 0x3a, 0x51,
+    0x69, 0x26, 0x38, 0x52, 0x9e, 0x9a, 0x4f, 0xa7,
+    0x43, 0x10, 0x80, 0xee, 0x3d, 0x59, 0x35, 0xcf,
+    0x79, 0x74, 0xb5, 0xa2, 0xb1, 0x96, 0x23, 0xe0,
+    0xbe, 0x05, 0xf5, 0x6e, 0x19, 0xc5, 0x66, 0x49,
+    0xf0, 0xd1, 0x54, 0xa9, 0x70, 0x4b, 0xa4, 0xe2,
+    0xe6, 0xe5, 0xab, 0xe4, 0xd2, 0xaa, 0x4c, 0xe3,
+    0x06, 0x6f, 0xc6, 0x4a, 0x75, 0xa3, 0x97, 0xe1];
+
+fn is_pixel_texture_on(bit_pos:u8) -> (u8,bool) {
+    let byte = bit_pos/8;
+    let bit = 0x80 >> (bit_pos&7);
+    let on = (TEXTURE_DATA_SLICE[byte as usize] & bit) == bit;
+    let ret = bit_pos+1;
+    if ret == 255 {
+        return (0,on);
+    }
+    (ret,on)
+}
+
+/// Calls plot for each pixel the plot pen covers when drawn at x,y with texture t
+pub(crate) fn for_each_brush_pixel(pen:PenStyle,t:u8,x:i16,y:i16,mut plot:impl FnMut(i16,i16)) {
+
+    let w = (pen.size as i16)+1;
+    let h = (pen.size as i16)*2+1;
+    let sx = x-(w/2);
+    let sy = y-h/2;
+
+    let mut bit_pos = TEXTURE_DATA_BIT_OFFSET[(t>>1) as usize];
+
+    let mut on:bool;
+    if !pen.rectangle {
+
+        let circle_pixel = CIRCLE_SLICE_TABLE[pen.size as usize];
+        let mut iter=0;
+        for y in sy..sy+h {
+            for x in sx..sx+w {
+                // check circle from rect shape
+                if circle_pixel[iter]==1 {
+                    (bit_pos,on) = is_pixel_texture_on(bit_pos);
+                    if on || (!pen.splatter) {
+                        plot(x,y);
+                    }
+                }
+                iter+=1;
+            }
+        }
+    } else {
+        // Rectangle renderer 
+        for y in sy..sy+h {
+            for x in sx..sx+w {
+                (bit_pos,on) = is_pixel_texture_on(bit_pos);
+                if on || (!pen.splatter) {
+                    plot(x,y);
+                }
+            }
+        }
+    }
+
+}
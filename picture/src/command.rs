@@ -0,0 +1,309 @@
+use crate::PictureResource;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_every_command() {
+        let data = [
+            0xF0,0x01, 0xF2,0x0A, 0xF6,0x00,0x00,0x9F,0xA7,
+            0xF3, 0xF7,0x10,0x10,0x35,0xCB,
+            0xF1, 0xF4,0x20,0x30,0x40,0x50, 0xF5,0x60,0x60,0x70,
+            0xF9,0x23, 0xFA,0x08,0x30,0x40,
+            0xF8,0x50,0x10,
+            0xFF, 0xF0,0x02];
+        let commands = decode_picture(&data,false).unwrap();
+        let expected = vec![
+            (0,PictureCommand::SetColour(1)),
+            (2,PictureCommand::SetPriority(0x0A)),
+            (4,PictureCommand::AbsoluteLine(vec![(0,0),(0x9F,0xA7)])),
+            (9,PictureCommand::DisablePriority),
+            (10,PictureCommand::RelativeLine { start:(0x10,0x10), steps:vec![(3,5),(-4,-3)] }),
+            (15,PictureCommand::DisableColour),
+            (16,PictureCommand::CornerLine { start:(0x20,0x30), x_first:false, moves:vec![0x40,0x50] }),
+            (21,PictureCommand::CornerLine { start:(0x60,0x60), x_first:true, moves:vec![0x70] }),
+            (25,PictureCommand::SetPen(PenStyle { size:3, rectangle:false, splatter:true })),
+            (27,PictureCommand::Plot(vec![PlotPoint { texture:0x08, x:0x30, y:0x40 }])),
+            (31,PictureCommand::Fill(vec![(0x50,0x10)])),
+        ];
+        assert_eq!(commands,expected);
+    }
+
+    #[test]
+    fn decode_compressed_offsets() {
+        // F0 4, F6 10 10 80 80, FF packed into nibbles
+        let data = [0xF0,0x4F,0x61,0x01,0x08,0x08,0x0F,0xF0];
+        let commands = decode_picture(&data,true).unwrap();
+        assert_eq!(commands,vec![(0,PictureCommand::SetColour(4)),(1,PictureCommand::AbsoluteLine(vec![(0x10,0x10),(0x80,0x80)]))]);
+    }
+
+    #[test]
+    fn decode_fail_truncated() {
+        assert!(decode_picture(&[0xF6,0x10],false).is_err());
+        assert!(decode_picture(&[0xF0],false).is_err());
+        assert!(decode_picture(&[0xF9,0x20,0xFA,0x10,0x10],false).is_err());
+    }
+
+    #[test]
+    fn decode_fail_unknown_code() {
+        assert!(decode_picture(&[0xF0,0x01,0xFB],false).is_err());
+        assert!(decode_picture(&[0x10],false).is_err());
+    }
+}
+
+/// Plot pen set by F9, used by every F9 plot that follows
+#[derive(Clone,Copy,Debug,PartialEq,Eq,Default)]
+pub struct PenStyle {
+    /// 0-7, the brush is size+1 wide and size*2+1 high
+    pub size:u8,
+    /// Square brush, otherwise a circle
+    pub rectangle:bool,
+    /// Only plot the pixels picked out by the texture pattern
+    pub splatter:bool,
+}
+
+/// A single brush plot, texture picks the splatter pattern (0 unless the pen splatters)
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct PlotPoint {
+    pub texture:u8,
+    pub x:u8,
+    pub y:u8,
+}
+
+/// One drawing command of a picture
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum PictureCommand {
+    /// F0 - enables drawing to the visual screen in this colour
+    SetColour(u8),
+    /// F1 - stops drawing to the visual screen
+    DisableColour,
+    /// F2 - enables drawing to the priority screen in this priority
+    SetPriority(u8),
+    /// F3 - stops drawing to the priority screen
+    DisablePriority,
+    /// F4 (y first) / F5 (x first) - lines that alternate between moving along y and x, each move gives the new coordinate
+    CornerLine { start:(u8,u8), x_first:bool, moves:Vec<u8> },
+    /// F6 - lines joining each point to the next (a single point is just plotted)
+    AbsoluteLine(Vec<(u8,u8)>),
+    /// F7 - lines from start, each step moves -7..7 pixels in x and y
+    RelativeLine { start:(u8,u8), steps:Vec<(i8,i8)> },
+    /// F8 - flood fills from each point
+    Fill(Vec<(u8,u8)>),
+    /// F9 - changes the plot pen
+    SetPen(PenStyle),
+    /// FA - plots the pen at each point
+    Plot(Vec<PlotPoint>),
+}
+
+impl PictureCommand {
+    /// Every point a line command visits, in drawing order (None for anything other than a line)
+    pub fn get_line_points(&self) -> Option<Vec<(i16,i16)>> {
+        match self {
+            PictureCommand::AbsoluteLine(points) => Some(points.iter().map(|(x,y)| (*x as i16,*y as i16)).collect()),
+            PictureCommand::RelativeLine { start, steps } => {
+                let mut point = (start.0 as i16,start.1 as i16);
+                let mut points = vec![point];
+                for (dx,dy) in steps {
+                    point = (point.0+*dx as i16,point.1+*dy as i16);
+                    points.push(point);
+                }
+                Some(points)
+            },
+            PictureCommand::CornerLine { start, x_first, moves } => {
+                let mut point = (start.0 as i16,start.1 as i16);
+                let mut points = vec![point];
+                for (i,n) in moves.iter().enumerate() {
+                    if (i%2==0) == *x_first {
+                        point.0 = *n as i16;
+                    } else {
+                        point.1 = *n as i16;
+                    }
+                    points.push(point);
+                }
+                Some(points)
+            },
+            _ => None,
+        }
+    }
+}
+
+impl PenStyle {
+    pub fn new_from_byte(style:u8) -> PenStyle {
+        PenStyle { size:style&7, rectangle:(style&0x10)==0x10, splatter:(style&0x20)==0x20 }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        (self.size&7) | if self.rectangle {0x10} else {0} | if self.splatter {0x20} else {0}
+    }
+}
+
+pub(crate) struct PictureIterator<'a> {
+    picture_data:&'a [u8],
+    position:usize,
+    compressed:bool
+}
+
+impl PictureIterator<'_> {
+    pub fn new(data:&[u8],compressed:bool) -> PictureIterator<'_> {
+        PictureIterator { picture_data: data, position: 0, compressed }
+    }
+
+    /// Offset of the next byte in the picture data (the byte holding its upper nibble, for compressed data)
+    pub fn get_offset(&self) -> usize {
+        self.position/2
+    }
+
+    fn fetch_nibble(&mut self) -> Option<u8> {
+        if self.position/2 >= self.picture_data.len() {
+            return None;
+        }
+        let t = self.picture_data[self.position/2];
+        if self.position&1 == 0 {
+            Some(t>>4)
+        } else {
+            Some(t&0xF)
+        }
+    }
+
+    pub fn next_byte(&mut self) -> Option<u8> {
+        if let Some(first)=self.fetch_nibble() {
+            self.position+=1;
+            if let Some(second)=self.fetch_nibble() {
+                self.position+=1;
+                return Some((first<<4)|second);
+            }
+        }
+        None
+    }
+
+    pub fn next_nibble(&mut self) -> Option<u8> {
+        if !self.compressed {
+            return self.next_byte();
+        }
+        if let Some(n)=self.fetch_nibble() {
+            self.position+=1;
+            return Some(n);
+        }
+        None
+    }
+
+    pub fn peek_byte(&mut self) -> Option<u8> {
+        if let Some(b) = self.next_byte() {
+            self.position-=2;
+            return Some(b);
+        }
+        None
+    }
+
+    /// Next byte if it is an argument rather than the next command
+    fn next_argument(&mut self) -> Option<u8> {
+        match self.peek_byte() {
+            Some(b) if b < 0xF0 => self.next_byte(),
+            _ => None,
+        }
+    }
+
+    fn expect_byte(&mut self,code:u8) -> Result<u8,String> {
+        match self.next_byte() {
+            Some(b) => Ok(b),
+            None => Err(format!("Picture data ends inside command {:02X}",code)),
+        }
+    }
+
+    fn expect_point(&mut self,code:u8) -> Result<(u8,u8),String> {
+        Ok((self.expect_byte(code)?,self.expect_byte(code)?))
+    }
+}
+
+fn decode_relative(rel:u8) -> i8 {
+    if (rel & 8) == 8 {
+        -((rel&7) as i8)
+    } else {
+        (rel&7) as i8
+    }
+}
+
+/// Decodes raw picture data into commands along with the offset of each command's code. Decoding stops at FF
+/// or the end of the data.
+pub fn decode_picture(data:&[u8],compressed:bool) -> Result<Vec<(usize,PictureCommand)>,String> {
+    let mut iter = PictureIterator::new(data,compressed);
+    let mut commands:Vec<(usize,PictureCommand)> = Vec::new();
+    let mut pen = PenStyle::default();
+
+    loop {
+        let offset = iter.get_offset();
+        let code = match iter.next_byte() {
+            Some(0xFF) | None => break,
+            Some(code) => code,
+        };
+        let command = match code {
+            0xF0 | 0xF2 => {
+                let pen = match iter.next_nibble() {
+                    Some(n) => n,
+                    None => return Err(format!("Picture data ends inside command {:02X}",code)),
+                };
+                if code==0xF0 { PictureCommand::SetColour(pen) } else { PictureCommand::SetPriority(pen) }
+            },
+            0xF1 => PictureCommand::DisableColour,
+            0xF3 => PictureCommand::DisablePriority,
+            0xF4 | 0xF5 => {
+                let start = iter.expect_point(code)?;
+                let mut moves:Vec<u8> = Vec::new();
+                while let Some(n) = iter.next_argument() {
+                    moves.push(n);
+                }
+                PictureCommand::CornerLine { start, x_first:code==0xF5, moves }
+            },
+            0xF6 => {
+                let mut points = vec![iter.expect_point(code)?];
+                while let Some(x) = iter.next_argument() {
+                    points.push((x,iter.expect_byte(code)?));
+                }
+                PictureCommand::AbsoluteLine(points)
+            },
+            0xF7 => {
+                let start = iter.expect_point(code)?;
+                let mut steps:Vec<(i8,i8)> = Vec::new();
+                while let Some(rel) = iter.next_argument() {
+                    steps.push((decode_relative(rel>>4),decode_relative(rel&0x0F)));
+                }
+                PictureCommand::RelativeLine { start, steps }
+            },
+            0xF8 => {
+                let mut points:Vec<(u8,u8)> = Vec::new();
+                while let Some(x) = iter.next_argument() {
+                    points.push((x,iter.expect_byte(code)?));
+                }
+                PictureCommand::Fill(points)
+            },
+            0xF9 => {
+                pen = PenStyle::new_from_byte(iter.expect_byte(code)?);
+                PictureCommand::SetPen(pen)
+            },
+            0xFA => {
+                let mut points:Vec<PlotPoint> = Vec::new();
+                while let Some(first) = iter.next_argument() {
+                    let point = if pen.splatter {
+                        PlotPoint { texture:first, x:iter.expect_byte(code)?, y:iter.expect_byte(code)? }
+                    } else {
+                        PlotPoint { texture:0, x:first, y:iter.expect_byte(code)? }
+                    };
+                    points.push(point);
+                }
+                PictureCommand::Plot(points)
+            },
+            _ => return Err(format!("Unhandled control code {:02X}",code)),
+        };
+        commands.push((offset,command));
+    }
+
+    Ok(commands)
+}
+
+impl PictureResource {
+    /// The picture's commands, each with the offset of its code in get_data()
+    pub fn decode(&self) -> Result<Vec<(usize,PictureCommand)>,String> {
+        decode_picture(self.get_data(),self.is_compressed())
+    }
+}
@@ -0,0 +1,249 @@
+use dir_resource::{Root, ResourceDirectory, ResourceDirectoryEntry, ResourceType, ResourceCompression};
+use helpers::conv_rgba;
+use volume::ResourceStore;
+
+use crate::{PictureResource, PictureCommand, PIC_WIDTH_USIZE, PIC_HEIGHT_USIZE};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use volume::VolumeBuilder;
+
+    const BASE:[u8;19] = [0xF0,0x01,0xF6,0x10,0x10,0x50,0x50, 0xF0,0x02,0xF8,0x60,0x10, 0xF1,0xF2,0x05,0xF4,0x08,0x08,0xFF];
+
+    fn commands(data:&[u8]) -> Vec<PictureCommand> {
+        PictureResource::new_from_slice(data,false).decode().unwrap().into_iter().map(|(_,c)| c).collect()
+    }
+
+    #[test]
+    fn diff_same_picture() {
+        let a = PictureResource::new_from_slice(&BASE,false);
+        let diff = diff_pictures(&a,&a.to_compressed()).unwrap();
+        assert!(diff.is_identical());
+        assert_eq!(diff.get_visual_changes(),0);
+    }
+
+    #[test]
+    fn diff_command_changes() {
+        let a = commands(&BASE);
+        // Colour 2 becomes 3, the priority line is dropped and a plot added at the end
+        let b = commands(&[0xF0,0x01,0xF6,0x10,0x10,0x50,0x50, 0xF0,0x03,0xF8,0x60,0x10, 0xF1,0xF2,0x05, 0xFA,0x20,0x20,0xFF]);
+        assert_eq!(diff_commands(&a,&b),vec![
+            CommandChange::Changed { index_a:2, index_b:2, before:PictureCommand::SetColour(2), after:PictureCommand::SetColour(3) },
+            CommandChange::Changed { index_a:6, index_b:6, before:a[6].clone(), after:b[6].clone() },
+        ]);
+        assert_eq!(diff_commands(&a,&a[1..]),vec![CommandChange::Removed { index:0, command:PictureCommand::SetColour(1) }]);
+        assert_eq!(diff_commands(&a[..4],&a),vec![
+            CommandChange::Inserted { index:4, command:a[4].clone() },
+            CommandChange::Inserted { index:5, command:a[5].clone() },
+            CommandChange::Inserted { index:6, command:a[6].clone() },
+        ]);
+    }
+
+    #[test]
+    fn diff_pixels() {
+        let a = PictureResource::new_from_slice(&BASE,false);
+        // The priority line moves, so only priority pixels change
+        let mut data = BASE;
+        data[17] = 0x09;
+        let b = PictureResource::new_from_slice(&data,false);
+        let diff = diff_pictures(&a,&b).unwrap();
+        assert!(!diff.is_identical());
+        assert_eq!(diff.commands.len(),1);
+        assert_eq!(diff.get_visual_changes(),0);
+        assert!(diff.get_priority_changes()>0);
+
+        let rgba = diff.to_rgba();
+        assert_eq!(rgba.len(),PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE*4);
+        let highlighted = rgba.chunks(4).filter(|p| *p==PRIORITY_CHANGE).count();
+        assert_eq!(highlighted,diff.get_priority_changes());
+    }
+
+    #[test]
+    fn diff_two_roots() {
+        let mut changed = BASE;
+        changed[8] = 0x04;
+        let game = |pictures:&[(usize,&[u8])]| {
+            let mut builder = VolumeBuilder::new(false);
+            for (index,data) in pictures {
+                builder.add(ResourceType::Pictures,*index,data).unwrap();
+            }
+            let mut source = dir_resource::source::MemorySource::new("game");
+            for (name,resource_type) in [("LOGDIR",ResourceType::Logic),("PICDIR",ResourceType::Pictures),("VIEWDIR",ResourceType::Views),("SNDDIR",ResourceType::Sounds)] {
+                source.insert(name,builder.directory(resource_type).write_v2().unwrap());
+            }
+            source.insert("VOL.0",builder.get_volumes()[0].clone());
+            Root::new_from_source(Box::new(source),"2.917")
+        };
+        let a = game(&[(1,&BASE),(2,&BASE),(3,&BASE)]);
+        let b = game(&[(2,&BASE),(3,&changed),(4,&BASE)]);
+
+        let diffs = diff_roots(&a,&b).unwrap();
+        assert_eq!(diffs.iter().map(|(n,_)| *n).collect::<Vec<_>>(),vec![2,3]);
+        assert!(diffs[0].1.as_ref().unwrap().is_identical());
+        assert!(diffs[1].1.as_ref().unwrap().get_visual_changes()>0);
+
+        // A truncated picture only fails its own number
+        let broken = game(&[(2,&BASE[..1]),(3,&BASE)]);
+        let diffs = diff_roots(&a,&broken).unwrap();
+        assert_eq!(diffs.len(),2);
+        assert!(diffs[0].1.is_err());
+        assert!(diffs[1].1.as_ref().unwrap().is_identical());
+    }
+}
+
+/// Colour marking pixels whose visual screen differs in to_rgba
+pub const VISUAL_CHANGE:[u8;4] = [255,0,255,255];
+/// Colour marking pixels where only the priority screen differs in to_rgba
+pub const PRIORITY_CHANGE:[u8;4] = [0,255,255,255];
+
+/// A difference between the commands of two pictures, indices are positions in each picture's decode()
+#[derive(Clone,Debug,PartialEq,Eq)]
+pub enum CommandChange {
+    Removed { index:usize, command:PictureCommand },
+    Inserted { index:usize, command:PictureCommand },
+    Changed { index_a:usize, index_b:usize, before:PictureCommand, after:PictureCommand },
+}
+
+/// Differences between two pictures, at the command level and for each pixel of both screens
+pub struct PictureDiff {
+    pub commands:Vec<CommandChange>,
+    /// True for each pixel whose visual colour differs
+    pub visual:Vec<bool>,
+    /// True for each pixel whose priority differs
+    pub priority:Vec<bool>,
+    picture:[u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],
+}
+
+impl PictureDiff {
+    pub fn is_identical(&self) -> bool {
+        self.commands.is_empty() && self.get_visual_changes()==0 && self.get_priority_changes()==0
+    }
+
+    pub fn get_visual_changes(&self) -> usize {
+        self.visual.iter().filter(|c| **c).count()
+    }
+
+    pub fn get_priority_changes(&self) -> usize {
+        self.priority.iter().filter(|c| **c).count()
+    }
+
+    /// The second picture faded, with changed pixels highlighted (see VISUAL_CHANGE and PRIORITY_CHANGE), as 160x168 RGBA
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let faded = conv_rgba(&self.picture);
+        let mut out = Vec::with_capacity(faded.len());
+        for (coord,pixel) in faded.chunks(4).enumerate() {
+            if self.visual[coord] {
+                out.extend_from_slice(&VISUAL_CHANGE);
+            } else if self.priority[coord] {
+                out.extend_from_slice(&PRIORITY_CHANGE);
+            } else {
+                out.extend_from_slice(&[pixel[0]/3+128,pixel[1]/3+128,pixel[2]/3+128,255]);
+            }
+        }
+        out
+    }
+}
+
+/// Emits the removals and insertions between two matching commands, pairing them up as changes where possible
+fn flush_changes(changes:&mut Vec<CommandChange>,a:&[PictureCommand],b:&[PictureCommand],removed:&mut Vec<usize>,inserted:&mut Vec<usize>) {
+    let paired = removed.len().min(inserted.len());
+    for (index_a,index_b) in removed.iter().zip(inserted.iter()) {
+        changes.push(CommandChange::Changed { index_a:*index_a, index_b:*index_b, before:a[*index_a].clone(), after:b[*index_b].clone() });
+    }
+    for index in &removed[paired..] {
+        changes.push(CommandChange::Removed { index:*index, command:a[*index].clone() });
+    }
+    for index in &inserted[paired..] {
+        changes.push(CommandChange::Inserted { index:*index, command:b[*index].clone() });
+    }
+    removed.clear();
+    inserted.clear();
+}
+
+/// Command level differences between two command lists, from their longest common subsequence
+pub fn diff_commands(a:&[PictureCommand],b:&[PictureCommand]) -> Vec<CommandChange> {
+    let prefix = a.iter().zip(b.iter()).take_while(|(x,y)| x==y).count();
+    let suffix = a[prefix..].iter().rev().zip(b[prefix..].iter().rev()).take_while(|(x,y)| x==y).count();
+    let (n,m) = (a.len()-prefix-suffix,b.len()-prefix-suffix);
+
+    // common[i][j] is the length of the longest common subsequence of the middles from i and j on
+    let mut common = vec![0u32;(n+1)*(m+1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i*(m+1)+j] = if a[prefix+i]==b[prefix+j] {
+                common[(i+1)*(m+1)+j+1]+1
+            } else {
+                common[(i+1)*(m+1)+j].max(common[i*(m+1)+j+1])
+            };
+        }
+    }
+
+    let mut changes:Vec<CommandChange> = Vec::new();
+    let mut removed:Vec<usize> = Vec::new();
+    let mut inserted:Vec<usize> = Vec::new();
+    let (mut i,mut j) = (0,0);
+    while i<n || j<m {
+        if i<n && j<m && a[prefix+i]==b[prefix+j] {
+            flush_changes(&mut changes,a,b,&mut removed,&mut inserted);
+            i+=1;
+            j+=1;
+        } else if j==m || (i<n && common[(i+1)*(m+1)+j]>=common[i*(m+1)+j+1]) {
+            removed.push(prefix+i);
+            i+=1;
+        } else {
+            inserted.push(prefix+j);
+            j+=1;
+        }
+    }
+    flush_changes(&mut changes,a,b,&mut removed,&mut inserted);
+    changes
+}
+
+/// Compares two pictures command by command and pixel by pixel
+pub fn diff_pictures(a:&PictureResource,b:&PictureResource) -> Result<PictureDiff, String> {
+    let commands_a:Vec<PictureCommand> = a.decode()?.into_iter().map(|(_,c)| c).collect();
+    let commands_b:Vec<PictureCommand> = b.decode()?.into_iter().map(|(_,c)| c).collect();
+    let (picture_a,priority_a) = a.render()?;
+    let (picture_b,priority_b) = b.render()?;
+
+    Ok(PictureDiff {
+        commands:diff_commands(&commands_a,&commands_b),
+        visual:picture_a.iter().zip(picture_b.iter()).map(|(x,y)| x!=y).collect(),
+        priority:priority_a.iter().zip(priority_b.iter()).map(|(x,y)| x!=y).collect(),
+        picture:picture_b,
+    })
+}
+
+fn fetch_picture(store:&mut ResourceStore,entry:&ResourceDirectoryEntry) -> Result<PictureResource, String> {
+    let (data,compression) = store.fetch(entry)?;
+    Ok(PictureResource::new_from_slice(&data,compression==ResourceCompression::Picture))
+}
+
+/// Picture numbers with their diff, or why that picture could not be compared
+pub type PictureDiffs = Vec<(usize,Result<PictureDiff, String>)>;
+
+/// Diffs every picture number present in both games, e.g. two releases of the same game.
+/// A picture that cannot be read or decoded gives an error for that number only.
+pub fn diff_roots(root_a:&Root,root_b:&Root) -> Result<PictureDiffs, String> {
+    let directory_a = ResourceDirectory::new(root_a,ResourceType::Pictures)?;
+    let directory_b = ResourceDirectory::new(root_b,ResourceType::Pictures)?;
+    let mut store_a = ResourceStore::new_from_root(root_a)?;
+    let mut store_b = ResourceStore::new_from_root(root_b)?;
+
+    let mut diffs:PictureDiffs = Vec::new();
+    for (index,entry_a) in directory_a.into_iter().enumerate() {
+        let entry_b = match directory_b.get(index) {
+            Some(e) if !e.empty() && !entry_a.empty() => e,
+            _ => continue,
+        };
+        let a = fetch_picture(&mut store_a,&entry_a).map_err(|e| format!("First game: {}",e));
+        let b = fetch_picture(&mut store_b,entry_b).map_err(|e| format!("Second game: {}",e));
+        let diff = match (a,b) {
+            (Ok(a),Ok(b)) => diff_pictures(&a,&b),
+            (Err(e),_) | (_,Err(e)) => Err(e),
+        };
+        diffs.push((index,diff));
+    }
+    Ok(diffs)
+}
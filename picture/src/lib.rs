@@ -1,7 +1,137 @@
-use std::collections::VecDeque;
-
 use dir_resource::{ResourceDirectoryEntry, ResourceCompression};
-use volume::{Volume, VolumeCache};
+use volume::{ResourceStore, agi_picture_compress, agi_picture_expand};
+
+pub mod command;
+pub mod assembler;
+pub mod svg;
+pub mod canvas;
+pub mod scaled;
+pub mod diff;
+
+pub use command::{PictureCommand, PenStyle, PlotPoint, decode_picture};
+pub use assembler::encode_picture;
+pub use canvas::PictureCanvas;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Uses every command, with colours that differ in their upper nibble from the packed form
+    const PICTURE:[u8;50] = [
+        0xF0,0x01, 0xF2,0x0A, 0xF6,0x00,0x00,0x9F,0xA7,0x00,0xA7,
+        0xF3, 0xF0,0x0C, 0xF7,0x10,0x10,0x35,0x53,0x22,
+        0xF1, 0xF2,0x05, 0xF4,0x20,0x30,0x40,0x50,
+        0xF0,0x03, 0xF5,0x60,0x60,0x70,0x70,
+        0xF9,0x13, 0xFA,0x30,0x40,0x70,0x80,
+        0xF0,0x0E, 0xF8,0x50,0x10,0x08,0x90,
+        0xFF];
+
+    fn assert_same_render(a:&PictureResource,b:&PictureResource) {
+        let (picture_a,priority_a) = a.render().unwrap();
+        let (picture_b,priority_b) = b.render().unwrap();
+        assert!(picture_a==picture_b);
+        assert!(priority_a==priority_b);
+    }
+
+    #[test]
+    fn compressed_render_matches() {
+        let uncompressed = PictureResource::new_from_slice(&PICTURE,false);
+        let compressed = uncompressed.to_compressed();
+        assert!(compressed.is_compressed());
+        assert!(compressed.get_data().len()<PICTURE.len());
+        assert_same_render(&uncompressed,&compressed);
+    }
+
+    #[test]
+    fn compressed_render_odd_nibbles() {
+        // A single colour change leaves the packed data half a byte short
+        let data = [0xF0,0x04,0xF6,0x10,0x10,0x80,0x80,0xFF];
+        let uncompressed = PictureResource::new_from_slice(&data,false);
+        let compressed = uncompressed.to_compressed();
+        assert_eq!(compressed.get_data(),&[0xF0,0x4F,0x61,0x01,0x08,0x08,0x0F,0xF0]);
+        assert_same_render(&uncompressed,&compressed);
+    }
+
+    #[test]
+    fn render_partial_steps() {
+        let resource = PictureResource::new_from_slice(&PICTURE,false);
+        let count = resource.get_command_count().unwrap();
+        assert_eq!(count,15);
+
+        let (picture,priority,state) = resource.render_partial(0).unwrap();
+        assert!(picture.iter().all(|c| *c==15) && priority.iter().all(|p| *p==4));
+        assert_eq!(state,PenState::default());
+
+        let (_,_,state) = resource.render_partial(3).unwrap();
+        assert_eq!(state,PenState { colour_pen:1, priority_pen:0x0A, colour_on:true, priority_on:true, plot_pen:PenStyle::default() });
+        let (_,_,state) = resource.render_partial(13).unwrap();
+        assert_eq!(state,PenState { colour_pen:3, priority_pen:5, colour_on:true, priority_on:true, plot_pen:PenStyle { size:3, rectangle:true, splatter:false } });
+
+        let (picture,priority,_) = resource.render_partial(count+5).unwrap();
+        let full = resource.render().unwrap();
+        assert!(picture==full.0 && priority==full.1);
+    }
+
+    #[test]
+    fn provenance_names_last_writer() {
+        let resource = PictureResource::new_from_slice(&PICTURE,false);
+        let (picture,priority,provenance) = resource.render_with_provenance().unwrap();
+        assert_eq!(provenance.get_visual_source(0,0),Some(CommandSource { index:2, offset:4 }));
+        assert_eq!(provenance.get_priority_source(0,0),Some(CommandSource { index:2, offset:4 }));
+        assert_eq!(provenance.get_visual_source(0x10,0x10),Some(CommandSource { index:5, offset:14 }));
+        assert!(provenance.visual.iter().any(|s| s.map(|s| s.offset)==Some(44)));
+
+        let partials:Vec<_> = (0..=resource.get_command_count().unwrap()).map(|n| resource.render_partial(n).unwrap().0).collect();
+        for coord in 0..PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE {
+            match provenance.visual[coord] {
+                Some(source) => assert_eq!(partials[source.index+1][coord],picture[coord]),
+                None => assert_eq!(picture[coord],15),
+            }
+            if provenance.priority[coord].is_none() {
+                assert_eq!(priority[coord],4);
+            }
+        }
+    }
+
+    #[test]
+    fn compress_round_trip() {
+        let uncompressed = PictureResource::new_from_slice(&PICTURE,false);
+        let restored = uncompressed.to_compressed().to_uncompressed();
+        assert!(!restored.is_compressed());
+        assert_eq!(restored.get_data(),&PICTURE);
+        assert_eq!(uncompressed.to_uncompressed().get_data(),&PICTURE);
+    }
+}
+
+#[cfg(test)]
+mod test_pictures {
+    /// Deterministic random picture of the given number of commands, each picked from codes (F0-FA).
+    /// Coordinates are kept inside the picture.
+    pub fn noise_picture(seed:u32,length:usize,codes:&[u8]) -> Vec<u8> {
+        let mut seed = seed;
+        let mut next = || { seed = seed.wrapping_mul(1103515245).wrapping_add(12345); (seed>>16) as u8 };
+        let mut data:Vec<u8> = Vec::new();
+        let mut splatter = false;
+        for _ in 0..length {
+            let code = codes[next() as usize%codes.len()];
+            data.push(code);
+            match code {
+                0xF0 | 0xF2 => data.push(next()%16),
+                0xF9 => { let pen = next()&0x37; splatter = (pen&0x20)!=0; data.push(pen); },
+                0xF1 | 0xF3 => {},
+                0xF4 | 0xF5 | 0xF7 => for i in 0..2+next()%8 { data.push(if i%2==0 {next()%160} else {next()%168}); },
+                _ => for _ in 0..1+next()%4 {
+                    if code==0xFA && splatter {
+                        data.push(next()%0xF0);
+                    }
+                    data.extend_from_slice(&[next()%160,next()%168]);
+                },
+            }
+        }
+        data.push(0xFF);
+        data
+    }
+}
 
 pub const PIC_WIDTH_U8:u8 = 160;
 pub const PIC_HEIGHT_U8:u8 = 168;
@@ -15,11 +145,9 @@ pub struct PictureResource
 }
 
 impl PictureResource {
-    pub fn new(volume:&Volume, entry: &ResourceDirectoryEntry) -> Result<PictureResource, String> {
-        let mut t = VolumeCache::new();
-        let data_slice = volume.fetch_data_slice(&mut t,entry)?;
-        let picture_data = data_slice.0.to_vec();
-        let was_compressed=data_slice.1;
+    pub fn new(store:&mut ResourceStore, entry: &ResourceDirectoryEntry) -> Result<PictureResource, String> {
+        let (data,was_compressed) = store.fetch(entry)?;
+        let picture_data = data.to_vec();
         let compressed = match was_compressed {
             ResourceCompression::Picture => true,
             _ => false,
@@ -27,474 +155,148 @@ impl PictureResource {
         Ok(PictureResource { picture_data, compressed })
     }
 
-    pub fn render_onto(&self,picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]) -> Result<(), String> {
-        let mut iter = PictureIterator::new(&self.picture_data,self.compressed);
-        draw_picture(&mut iter,picture,priority)?;
-        Ok(())
-    }
-    pub fn render(&self) -> Result<([u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],[u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]), String> {
-        let mut picture = [15u8;(PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE) as usize];
-        let mut priority =[4u8;(PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE) as usize];
-        let mut iter = PictureIterator::new(&self.picture_data,self.compressed);
-        draw_picture(&mut iter,&mut picture,&mut priority)?;
-        Ok((picture,priority))
+    /// Picture from raw data, compressed data has its F0/F2 colours packed into nibbles (v3)
+    pub fn new_from_slice(data:&[u8],compressed:bool) -> PictureResource {
+        PictureResource { picture_data:data.to_vec(), compressed }
     }
-}
-
-struct PictureIterator<'a> {
-    picture_data:&'a [u8],
-    position:usize,
-    compressed:bool
-}
 
-impl PictureIterator<'_> {
-    pub fn new(data:&[u8],compressed:bool) -> PictureIterator {
-        PictureIterator { picture_data: data, position: 0, compressed }
+    pub fn get_data(&self) -> &[u8] {
+        &self.picture_data
     }
 
-    fn fetch_nibble(&mut self) -> Option<u8> {
-        if self.position/2 >= self.picture_data.len() {
-            return None;
-        }
-        let t = self.picture_data[self.position/2];
-        if self.position&1 == 0 {
-            Some(t>>4)
-        } else {
-            Some(t&0xF)
-        }
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
     }
-    
-    pub fn next_byte(&mut self) -> Option<u8> {
-        if let Some(first)=self.fetch_nibble() {
-            self.position+=1;
-            if let Some(second)=self.fetch_nibble() {
-                self.position+=1;
-                return Some((first<<4)|second);
-            }
+
+    /// The same picture in the nibble packed form used by v3 volumes
+    pub fn to_compressed(&self) -> PictureResource {
+        if self.compressed {
+            return PictureResource::new_from_slice(&self.picture_data,true);
         }
-        None
+        PictureResource { picture_data:agi_picture_compress(&self.picture_data), compressed:true }
     }
 
-    pub fn next_nibble(&mut self) -> Option<u8> {
+    /// The same picture in the byte form used by v2 volumes
+    pub fn to_uncompressed(&self) -> PictureResource {
         if !self.compressed {
-            return self.next_byte();
+            return PictureResource::new_from_slice(&self.picture_data,false);
         }
-        if let Some(n)=self.fetch_nibble() {
-            self.position+=1;
-            return Some(n);
-        }
-        None
+        PictureResource { picture_data:agi_picture_expand(&self.picture_data), compressed:false }
     }
 
-    pub fn peek_byte(&mut self) -> Option<u8> {
-        if let Some(b) = self.next_byte() {
-            self.position-=2;
-            return Some(b);
-        }
-        None
+    pub fn render_onto(&self,picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]) -> Result<(), String> {
+        self.render_partial_onto(usize::MAX,picture,priority)?;
+        Ok(())
     }
-
-}
-
-fn draw_picture(picture_iter:&mut PictureIterator, picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE], priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]) -> Result<(), String> {
-
-    let mut colour_pen=15u8;
-    let mut priority_pen=4u8;
-    let mut colour_on=false;
-    let mut priority_on=false;
-
-    let mut plot_pen_size:u8 = 0;
-    let mut plot_pen_splatter=false;
-    let mut plot_pen_rectangle:bool=true;
-
-    while let Some(b) = picture_iter.next_byte() {
-        match b {
-            0xF0 => { colour_on=true; colour_pen= picture_iter.next_nibble().unwrap(); },
-            0xF1 => { colour_on=false; },
-            0xF2 => { priority_on=true; priority_pen= picture_iter.next_nibble().unwrap(); },
-            0xF3 => { priority_on=false; },
-            0xF4 => { alternate_line(picture,priority,colour_on,priority_on,colour_pen,priority_pen,picture_iter, false); }
-            0xF5 => { alternate_line(picture,priority,colour_on,priority_on,colour_pen,priority_pen,picture_iter, true); }
-            0xF6 => { absolute_line(picture,priority,colour_on,priority_on,colour_pen,priority_pen,picture_iter); },
-            0xF7 => { relative_line(picture,priority,colour_on,priority_on,colour_pen,priority_pen,picture_iter); },
-            0xF8 => { fill(picture,priority,colour_on,priority_on,colour_pen,priority_pen,picture_iter); },
-            0xF9 => { let pstyle = picture_iter.next_byte().unwrap(); plot_pen_size=pstyle&7; plot_pen_rectangle = (pstyle&0x10)==0x10; plot_pen_splatter = (pstyle&0x20)==0x20; }
-            0xFA => { plot_pen(picture,priority,colour_on,priority_on,colour_pen,priority_pen,plot_pen_size,plot_pen_splatter,plot_pen_rectangle,picture_iter); },
-
-            0xFF => break,
-            _ => return Err(format!("Unhandled control code {:02X}",b)),
-        }
+    pub fn render(&self) -> Result<([u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],[u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]), String> {
+        let mut canvas = PictureCanvas::new();
+        self.draw_onto_canvas(&mut canvas)?;
+        Ok((canvas.picture,canvas.priority))
     }
 
-    Ok(())
-}
-
-fn rasterise_plot(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8, x:i16, y:i16) {
-
-    let width:i16 = PIC_WIDTH_U8.into();
-    let height:i16 = PIC_HEIGHT_U8.into();
-    if x<0 || x>(width-1) || y<0 || y>(height-1) {
-        return;
+    /// Draws the whole picture onto a canvas, carrying on from its current screens and pen state
+    pub fn draw_onto_canvas(&self,canvas:&mut PictureCanvas) -> Result<(), String> {
+        canvas.draw_commands(&self.decode()?);
+        Ok(())
     }
 
-    let coord = (width*y+x) as usize;
-    if colour_on {
-        picture[coord]=colour_pen;
-    }
-    if priority_on {
-        priority[coord]=priority_pen;
+    /// Number of drawing commands in the picture, the upper bound for render_partial
+    pub fn get_command_count(&self) -> Result<usize, String> {
+        Ok(self.decode()?.len())
     }
 
-}
-
-const CIRCLE_SLICE_0:&[u8] = &[1];
-const CIRCLE_SLICE_1:&[u8] = &[
-    0,0,
-    1,1,
-    0,0];
-const CIRCLE_SLICE_2:&[u8] = &[
-    0,1,0,
-    1,1,1,
-    1,1,1,
-    1,1,1,
-    0,1,0];
-const CIRCLE_SLICE_3:&[u8] = &[
-    0,1,1,0,
-    0,1,1,0,
-    1,1,1,1,
-    1,1,1,1,
-    1,1,1,1,
-    0,1,1,0,
-    0,1,1,0];
-const CIRCLE_SLICE_4:&[u8] = &[
-    0,0,1,0,0,
-    0,1,1,1,0,
-    1,1,1,1,1,
-    1,1,1,1,1,
-    1,1,1,1,1,
-    1,1,1,1,1,
-    1,1,1,1,1,
-    0,1,1,1,0,
-    0,0,1,0,0];
-const CIRCLE_SLICE_5:&[u8] = &[
-    0,0,1,1,0,0,
-    0,1,1,1,1,0,
-    0,1,1,1,1,0,
-    0,1,1,1,1,0,
-    1,1,1,1,1,1,
-    1,1,1,1,1,1,
-    1,1,1,1,1,1,
-    0,1,1,1,1,0,
-    0,1,1,1,1,0,
-    0,1,1,1,1,0,
-    0,0,1,1,0,0];
-const CIRCLE_SLICE_6:&[u8] = &[
-    0,0,1,1,1,0,0,
-    0,1,1,1,1,1,0,
-    0,1,1,1,1,1,0,
-    0,1,1,1,1,1,0,
-    1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,
-    0,1,1,1,1,1,0,
-    0,1,1,1,1,1,0,
-    0,1,1,1,1,1,0,
-    0,0,1,1,1,0,0];
-const CIRCLE_SLICE_7:&[u8] = &[
-    0,0,0,1,1,0,0,0,
-    0,0,1,1,1,1,0,0,
-    0,1,1,1,1,1,1,0,
-    0,1,1,1,1,1,1,0,
-    0,1,1,1,1,1,1,0,
-    1,1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,1,
-    1,1,1,1,1,1,1,1,
-    0,1,1,1,1,1,1,0,
-    0,1,1,1,1,1,1,0,
-    0,1,1,1,1,1,1,0,
-    0,0,1,1,1,1,0,0,
-    0,0,0,1,1,0,0,0];
-
-const CIRCLE_SLICE_TABLE:&[&[u8]] = &[CIRCLE_SLICE_0,CIRCLE_SLICE_1,CIRCLE_SLICE_2,CIRCLE_SLICE_3,CIRCLE_SLICE_4,CIRCLE_SLICE_5,CIRCLE_SLICE_6,CIRCLE_SLICE_7];
-
-const TEXTURE_DATA_SLICE:&[u8] = &[
-    0x20, 0x94, 0x02, 0x24, 0x90, 0x82, 0xa4, 0xa2,
-    0x82, 0x09, 0x0a, 0x22, 0x12, 0x10, 0x42, 0x14,
-    0x91, 0x4a, 0x91, 0x11, 0x08, 0x12, 0x25, 0x10,
-    0x22, 0xa8, 0x14, 0x24, 0x00, 0x50, 0x24, 0x04];
-
-const TEXTURE_DATA_BIT_OFFSET:&[u8] = &[
-    0x00, 0x18, 0x30, 0xc4, 0xdc, 0x65, 0xeb, 0x48,
-    0x60, 0xbd, 0x89, 0x04, 0x0a, 0xf4, 0x7d, 0x6d,
-    0x85, 0xb0, 0x8e, 0x95, 0x1f, 0x22, 0x0d, 0xdf,
-    0x2a, 0x78, 0xd5, 0x73, 0x1c, 0xb4, 0x40, 0xa1,
-    0xb9, 0x3c, 0xca, 0x58, 0x92, 0x34, 0xcc, 0xce,
-    0xd7, 0x42, 0x90, 0x0f, 0x8b, 0x7f, 0x32, 0xed,
-    0x5c, 0x9d, 0xc8, 0x99, 0xad, 0x4e, 0x56, 0xa6,
-    0xf7, 0x68, 0xb7, 0x25, 0x82, 0x37, 0x3a, 0x51,
-    0x69, 0x26, 0x38, 0x52, 0x9e, 0x9a, 0x4f, 0xa7,
-    0x43, 0x10, 0x80, 0xee, 0x3d, 0x59, 0x35, 0xcf,
-    0x79, 0x74, 0xb5, 0xa2, 0xb1, 0x96, 0x23, 0xe0,
-    0xbe, 0x05, 0xf5, 0x6e, 0x19, 0xc5, 0x66, 0x49,
-    0xf0, 0xd1, 0x54, 0xa9, 0x70, 0x4b, 0xa4, 0xe2,
-    0xe6, 0xe5, 0xab, 0xe4, 0xd2, 0xaa, 0x4c, 0xe3,
-    0x06, 0x6f, 0xc6, 0x4a, 0x75, 0xa3, 0x97, 0xe1];
-
-fn is_pixel_texture_on(bit_pos:u8) -> (u8,bool) {
-    let byte = bit_pos/8;
-    let bit = 0x80 >> (bit_pos&7);
-    let on = (TEXTURE_DATA_SLICE[byte as usize] & bit) == bit;
-    let ret = bit_pos+1;
-    if ret == 255 {
-        return (0,on);
+    /// Draws only the first count commands, returning the pen state they leave behind
+    pub fn render_partial_onto(&self,count:usize,picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]) -> Result<PenState, String> {
+        let commands = self.decode()?;
+        let mut canvas = PictureCanvas::new_from_screens(picture,priority);
+        canvas.draw_commands(&commands[..count.min(commands.len())]);
+        *picture = canvas.picture;
+        *priority = canvas.priority;
+        Ok(canvas.state)
     }
-    (ret,on)
-}
-
-fn rasterise_plot_pen(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8,plot_pen_size:u8,plot_pen_splatter:bool,plot_pen_rectangle:bool,t:u8,x:i16,y:i16) {
 
-    let w = (plot_pen_size as i16)+1;
-    let h = (plot_pen_size as i16)*2+1;
-    let sx = x-(w/2);
-    let sy = y-h/2;
-
-    let mut bit_pos = TEXTURE_DATA_BIT_OFFSET[(t>>1) as usize];
-
-    let mut on:bool;
-    if !plot_pen_rectangle {
-
-        let circle_pixel = CIRCLE_SLICE_TABLE[plot_pen_size as usize];
-        let mut iter=0;
-        for y in sy..sy+h {
-            for x in sx..sx+w {
-                // check circle from rect shape
-                if circle_pixel[iter]==1 {
-                    (bit_pos,on) = is_pixel_texture_on(bit_pos);
-                    if on || (!plot_pen_splatter) {
-                        rasterise_plot(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x, y);
-                    }
-                }
-                iter+=1;
-            }
-        }
-    } else {
-        // Rectangle renderer 
-        for y in sy..sy+h {
-            for x in sx..sx+w {
-                (bit_pos,on) = is_pixel_texture_on(bit_pos);
-                if on || (!plot_pen_splatter) {
-                    rasterise_plot(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x, y);
-                }
-            }
+    /// Renders the picture along with which command last wrote each pixel
+    pub fn render_with_provenance(&self) -> Result<([u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],[u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],PictureProvenance), String> {
+        let mut canvas = PictureCanvas::new();
+        canvas.record_provenance();
+        self.draw_onto_canvas(&mut canvas)?;
+        match canvas.take_provenance() {
+            Some(provenance) => Ok((canvas.picture,canvas.priority,provenance)),
+            None => Err("Picture provenance was not recorded".to_string()),
         }
     }
 
-}
-
-
-fn rasterise_line(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8, x0:i16, y0:i16, x1:i16, y1:i16) {
-
-    let dx = (x1-x0).abs();
-    let sx = if x0<x1 {1i16} else {-1i16};
-    let dy = -(y1-y0).abs();
-    let sy = if y0<y1 {1i16} else {-1i16};
-    let mut error = dx + dy;
-
-    let mut x=x0;
-    let mut y=y0;
-
-    loop {
-
-        rasterise_plot(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x, y);
-
-        if x == x1 && y == y1 {
-            break;
-        }
-        let e2 = 2 * error;
-        if e2 >= dy {
-            if x == x1 {
-                break;
-            }
-            error += dy;
-            x += sx;
-        }
-        if e2 <= dx {
-            if y == y1 {
-                break;
-            }
-            error += dx;
-            y += sy;
-        }
+    pub fn render_partial(&self,count:usize) -> Result<([u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],[u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],PenState), String> {
+        let mut picture = [15u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE];
+        let mut priority =[4u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE];
+        let state = self.render_partial_onto(count,&mut picture,&mut priority)?;
+        Ok((picture,priority,state))
     }
-
 }
 
-fn rasterise_fill(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8, x:u8, y:u8) {
-
-    let mut queue:VecDeque<(u8,u8)> = VecDeque::new();
-
-    if x>159 || y>199 {
-        return;
-    }
-
-    if colour_on && colour_pen==15 {
-        return;
-    }
-    if priority_on && !colour_on && priority_pen==4 {
-        return;
-    }
-
-    queue.push_back((x,y));
-
-    while !queue.is_empty() {
-
-        let (x,y) = queue.pop_front().unwrap();
-
-        let vec_coord = y as usize;
-        let vec_coord = vec_coord * (PIC_WIDTH_U8 as usize);
-        let vec_coord: usize = vec_coord + x as usize;
-
-        if colour_on && picture[vec_coord]!=15 {
-            continue;
-        }
-
-        if priority_on && !colour_on && priority[vec_coord]!=4 {
-            continue;
-        }
-
-        if colour_on {
-            picture[vec_coord]=colour_pen;
-        }
-        if priority_on {
-            priority[vec_coord]=priority_pen;
-        }
-
-        if x<(PIC_WIDTH_U8-1)  { queue.push_back((x+1,y)); }
-        if x>0          { queue.push_back((x-1,y)); }
-        if y<(PIC_HEIGHT_U8-1) { queue.push_back((x,y+1)); }
-        if y>0          { queue.push_back((x,y-1)); }
-    }
-
+/// Drawing state of the renderer between commands
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct PenState {
+    pub colour_pen:u8,
+    pub priority_pen:u8,
+    pub colour_on:bool,
+    pub priority_on:bool,
+    pub plot_pen:PenStyle,
 }
 
-fn alternate_line(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8,picture_iter:&mut PictureIterator, startx:bool) {
-
-    let mut x0 = picture_iter.next_byte().unwrap();
-    let mut y0 = picture_iter.next_byte().unwrap();
-
-    let mut x=startx;
-    let mut x1= x0;
-    let mut y1= y0;
-
-    rasterise_plot(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x0.into(), y0.into());
-
-    while let Some(b) = picture_iter.peek_byte() {
-        if b >= 0xF0 {
-            return;
-        }
-
-        let n = picture_iter.next_byte().unwrap();
-
-        if x {
-            x1 = n;
-        } else {
-            y1 = n;
-        }
-        x= !x;
-
-        rasterise_line(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x0.into(), y0.into(), x1.into(), y1.into());
-
-        x0=x1;
-        y0=y1;
+impl Default for PenState {
+    fn default() -> PenState {
+        PenState { colour_pen:15, priority_pen:4, colour_on:false, priority_on:false, plot_pen:PenStyle::default() }
     }
 }
 
-
-fn absolute_line(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8,picture_iter:&mut PictureIterator) {
-
-    let mut x0 = picture_iter.next_byte().unwrap();
-    let mut y0 = picture_iter.next_byte().unwrap();
-
-    rasterise_plot(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x0.into(), y0.into());
-
-    while let Some(b) = picture_iter.peek_byte() {
-        if b >= 0xF0 {
-            return;
+impl PenState {
+    /// Updates the state for a pen command, drawing commands leave it unchanged
+    pub fn apply(&mut self,command:&PictureCommand) {
+        match command {
+            PictureCommand::SetColour(c) => { self.colour_on=true; self.colour_pen = *c; },
+            PictureCommand::DisableColour => { self.colour_on=false; },
+            PictureCommand::SetPriority(p) => { self.priority_on=true; self.priority_pen = *p; },
+            PictureCommand::DisablePriority => { self.priority_on=false; },
+            PictureCommand::SetPen(pen) => { self.plot_pen = *pen; },
+            _ => {},
         }
-        let x1 = picture_iter.next_byte().unwrap();
-        let y1 = picture_iter.next_byte().unwrap();
-
-        rasterise_line(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x0.into(), y0.into(),x1.into(),y1.into());
-
-        x0=x1;
-        y0=y1;
     }
 }
 
-fn decode_relative(rel:u8) -> i16 {
-    if (rel & 8) == 8 {
-        0i16-((rel&7) as i16)
-    } else {
-        (rel&7) as i16
-    }
+/// The command that wrote a pixel, as its index in decode() and the offset of its code in the picture data
+#[derive(Clone,Copy,Debug,PartialEq,Eq)]
+pub struct CommandSource {
+    pub index:usize,
+    pub offset:usize,
 }
 
-fn relative_line(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8,picture_iter:&mut PictureIterator) {
-
-    let mut x0 = picture_iter.next_byte().unwrap() as i16;
-    let mut y0 = picture_iter.next_byte().unwrap() as i16;
-
-    rasterise_plot(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x0,y0);
-
-    while let Some(b) = picture_iter.peek_byte() {
-        if b >= 0xF0 {
-            return;
-        }
-        let rel = picture_iter.next_byte().unwrap();
-        let x1 = x0 + decode_relative(rel>>4);
-        let y1 = y0 + decode_relative(rel&0x0F);
-
-        rasterise_line(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x0, y0, x1, y1);
-
-        x0=x1;
-        y0=y1;
-    }
+/// For every pixel of the visual and priority screens, the command that last wrote it (None if never drawn)
+pub struct PictureProvenance {
+    pub visual:Vec<Option<CommandSource>>,
+    pub priority:Vec<Option<CommandSource>>,
+    current:Option<CommandSource>,
 }
 
+impl PictureProvenance {
+    fn new() -> PictureProvenance {
+        PictureProvenance { visual:vec![None;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE], priority:vec![None;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE], current:None }
+    }
 
-fn fill(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8,picture_iter:&mut PictureIterator) {
-
-    while let Some(b) = picture_iter.peek_byte() {
-        if b >= 0xF0 {
-            return;
-        }
-        let x = picture_iter.next_byte().unwrap();
-        let y = picture_iter.next_byte().unwrap();
-
-        if colour_on || priority_on {
-            rasterise_fill(picture, priority, colour_on, priority_on, colour_pen, priority_pen, x, y);
-        }
+    pub fn get_visual_source(&self,x:u8,y:u8) -> Option<CommandSource> {
+        self.visual[(y as usize)*PIC_WIDTH_USIZE+(x as usize)]
     }
-}
 
-fn plot_pen(picture:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],priority:&mut [u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE],colour_on:bool,priority_on:bool,colour_pen:u8,priority_pen:u8,plot_pen_size:u8,plot_pen_splatter:bool,plot_pen_rectangle:bool,picture_iter:&mut PictureIterator) {
+    pub fn get_priority_source(&self,x:u8,y:u8) -> Option<CommandSource> {
+        self.priority[(y as usize)*PIC_WIDTH_USIZE+(x as usize)]
+    }
 
-    while let Some(b) = picture_iter.peek_byte() {
-        if b >= 0xF0 {
-            return;
+    fn record(&mut self,coord:usize,colour_on:bool,priority_on:bool) {
+        if colour_on {
+            self.visual[coord]=self.current;
         }
-        let mut t = 0u8;
-        if plot_pen_splatter {
-            t = picture_iter.next_byte().unwrap();
+        if priority_on {
+            self.priority[coord]=self.current;
         }
-        let x = picture_iter.next_byte().unwrap();
-        let y = picture_iter.next_byte().unwrap();
-
-        rasterise_plot_pen(picture, priority, colour_on, priority_on, colour_pen, priority_pen, plot_pen_size, plot_pen_splatter, plot_pen_rectangle, t, x.into(), y.into());
     }
-
 }
@@ -3,7 +3,7 @@ use std::fs;
 use helpers::*;
 use dir_resource::{ResourceDirectory, ResourceDirectoryEntry, Root, ResourceType};
 use picture::*;
-use volume::{Volume, VolumeCache};
+use volume::ResourceStore;
 
 
 fn main() {
@@ -14,30 +14,23 @@ fn main() {
     //let root = Root::new("../images/Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.149");
     let root = Root::new("../images/Space Quest II V2.0F/","2.936");
     let dir = ResourceDirectory::new(&root, ResourceType::Pictures).unwrap();
+    let mut store = ResourceStore::new_from_root(&root).unwrap();
 
     for (index,entry) in dir.into_iter().enumerate() {
         if !entry.empty() {
             println!("{} : V{} P{}",index,entry.volume,entry.position);
-            dump_picture_resource(&root,&entry, index);
+            dump_picture_resource(&mut store,&entry, index);
         }
     }
 
 }
 
-fn dump_picture_resource(root:&Root,entry:&ResourceDirectoryEntry, index:usize) {
+fn dump_picture_resource(store:&mut ResourceStore,entry:&ResourceDirectoryEntry, index:usize) {
 
-    let bytes = root.fetch_volume(entry);
+    let (data,_) = store.fetch(entry).unwrap();
+    fs::write(format!("../{}-binary.bin",index).as_str(),&data[..]).unwrap();
 
-    let mut t = VolumeCache::new();
-    let volume = Volume::new(bytes.into_iter()).unwrap();
-
-    let data_slice = volume.fetch_data_slice(&mut t,entry).unwrap();
-    let volume_iter = data_slice.0.iter();
-
-    let data:Vec<u8> = volume_iter.cloned().collect();
-    fs::write(format!("../{}-binary.bin",index).as_str(),data).unwrap();
-
-    let picture_resource = match PictureResource::new(&volume,entry) {
+    let picture_resource = match PictureResource::new(store,entry) {
         Ok(b) => b,
         Err(s) => panic!("Failed due to : {}", s),
     };
@@ -58,4 +51,6 @@ fn dump_picture_resource(root:&Root,entry:&ResourceDirectoryEntry, index:usize)
 
     dump_png(format!("../{}-priority.png",index).as_str(),width*2,height,&rgba);
 
+    fs::write(format!("../{}-picture.svg",index).as_str(),picture_resource.to_svg().unwrap()).unwrap();
+
 }
@@ -0,0 +1,249 @@
+use crate::{PictureResource, PictureCommand, PictureCanvas, CommandSource, PenStyle, PlotPoint, PIC_WIDTH_USIZE, PIC_HEIGHT_USIZE};
+use crate::canvas::for_each_brush_pixel;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upscale(picture:&[u8],factor:usize) -> Vec<u8> {
+        let mut out = vec![0u8;picture.len()*factor*factor];
+        for y in 0..PIC_HEIGHT_USIZE*factor {
+            for x in 0..PIC_WIDTH_USIZE*factor {
+                out[y*PIC_WIDTH_USIZE*factor+x] = picture[(y/factor)*PIC_WIDTH_USIZE+x/factor];
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn scaled_factor_one_is_native() {
+        let data = [0xF0,0x02,0xF2,0x06,0xF6,0x10,0x10,0x80,0x40,0x20,0x90,0x10,0x10,0xF8,0x40,0x40,0xF9,0x03,0xFA,0x60,0x60,0xFF];
+        let resource = PictureResource::new_from_slice(&data,false);
+        let (picture,priority) = resource.render().unwrap();
+        let (scaled,scaled_priority) = resource.render_scaled(1).unwrap();
+        assert!(scaled==picture.to_vec() && scaled_priority==priority);
+    }
+
+    #[test]
+    fn scaled_axis_aligned_matches_upscale() {
+        // A box and a rectangular brush, both drawn exactly at any scale, with fills inside and out
+        let data = [0xF0,0x01,0xF2,0x05,0xF5,0x10,0x10,0x80,0x60,0x10,0x10,0xF9,0x12,0xFA,0x30,0x30,
+            0xF0,0x04,0xF8,0x20,0x20,0xF0,0x0A,0xF3,0xF8,0x05,0x05,0xFF];
+        let resource = PictureResource::new_from_slice(&data,false);
+        let (picture,priority) = resource.render().unwrap();
+        for factor in [2,4] {
+            let (scaled,scaled_priority) = resource.render_scaled(factor).unwrap();
+            assert_eq!(scaled.len(),PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE*factor*factor);
+            assert!(scaled==upscale(&picture,factor));
+            assert!(scaled_priority==priority);
+        }
+    }
+
+    #[test]
+    fn scaled_fill_keeps_regions() {
+        // A triangle of diagonal lines, filled inside and outside
+        let data = [0xF0,0x00,0xF6,0x10,0x90,0x50,0x08,0x90,0x90,0x10,0x90,0xF0,0x0C,0xF8,0x50,0x60,0xF0,0x02,0xF8,0x02,0x02,0xFF];
+        let resource = PictureResource::new_from_slice(&data,false);
+        let (picture,_) = resource.render().unwrap();
+        let factor = 4;
+        let (scaled,_) = resource.render_scaled(factor).unwrap();
+        assert!(scaled!=upscale(&picture,factor));
+
+        let width = PIC_WIDTH_USIZE*factor;
+        for y in 0..PIC_HEIGHT_USIZE {
+            for x in 0..PIC_WIDTH_USIZE {
+                let native = picture[y*PIC_WIDTH_USIZE+x];
+                let centre = scaled[(y*factor+factor/2)*width+x*factor+factor/2];
+                if native==12 || native==2 {
+                    // Away from the lines each fill keeps its own colour
+                    assert!(centre==native || centre==0,"{},{}",x,y);
+                }
+            }
+        }
+        assert!(!scaled.contains(&15));
+        assert!(scaled.contains(&12) && scaled.contains(&2));
+    }
+
+    #[test]
+    fn scaled_fail_bad_factor() {
+        let resource = PictureResource::new_from_slice(&[0xFF],false);
+        assert!(resource.render_scaled(0).is_err());
+        assert!(resource.render_scaled(9).is_err());
+    }
+}
+
+/// Largest factor render_scaled accepts
+pub const MAX_SCALE_FACTOR:usize = 8;
+
+/// Visual screen drawn at factor times the native resolution
+struct ScaledScreen {
+    factor:usize,
+    width:usize,
+    height:usize,
+    pixels:Vec<u8>,
+    fill_stack:Vec<usize>,
+}
+
+impl ScaledScreen {
+    fn new(factor:usize) -> ScaledScreen {
+        let (width,height) = (PIC_WIDTH_USIZE*factor,PIC_HEIGHT_USIZE*factor);
+        ScaledScreen { factor, width, height, pixels:vec![15u8;width*height], fill_stack:Vec::new() }
+    }
+
+    fn set(&mut self,x:i32,y:i32,colour:u8) {
+        if x>=0 && y>=0 && (x as usize)<self.width && (y as usize)<self.height {
+            self.pixels[(y as usize)*self.width+(x as usize)] = colour;
+        }
+    }
+
+    /// A native sized pixel with its top left corner at x,y
+    fn block(&mut self,x:i32,y:i32,colour:u8) {
+        let f = self.factor as i32;
+        for by in y..y+f {
+            for bx in x..x+f {
+                self.set(bx,by,colour);
+            }
+        }
+    }
+
+    /// Line between the centres of two native pixels, traced at full resolution with a native sized pen
+    fn line(&mut self,from:(i16,i16),to:(i16,i16),colour:u8) {
+        let f = self.factor as i32;
+        let (x0,y0) = (from.0 as i32*f,from.1 as i32*f);
+        let (x1,y1) = (to.0 as i32*f,to.1 as i32*f);
+
+        let dx = (x1-x0).abs();
+        let sx = if x0<x1 {1} else {-1};
+        let dy = -(y1-y0).abs();
+        let sy = if y0<y1 {1} else {-1};
+        let mut error = dx + dy;
+        let (mut x,mut y) = (x0,y0);
+
+        loop {
+            self.block(x,y,colour);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn brush(&mut self,pen:PenStyle,point:&PlotPoint,colour:u8) {
+        let f = self.factor as i32;
+        if pen.splatter {
+            // The splatter texture is a pixel pattern, so it stays at native resolution
+            let mut pixels:Vec<(i16,i16)> = Vec::new();
+            for_each_brush_pixel(pen,point.texture,point.x.into(),point.y.into(),|x,y| pixels.push((x,y)));
+            for (x,y) in pixels {
+                self.block(x as i32*f,y as i32*f,colour);
+            }
+            return;
+        }
+
+        let w = (pen.size as i32)+1;
+        let h = (pen.size as i32)*2+1;
+        let sx = (point.x as i32-w/2)*f;
+        let sy = (point.y as i32-h/2)*f;
+        let (cx,cy) = (sx as f32+(w*f) as f32/2.0,sy as f32+(h*f) as f32/2.0);
+        let (rx,ry) = ((w*f) as f32/2.0,(h*f) as f32/2.0);
+        for y in sy..sy+h*f {
+            for x in sx..sx+w*f {
+                let (ex,ey) = ((x as f32+0.5-cx)/rx,(y as f32+0.5-cy)/ry);
+                if pen.rectangle || ex*ex+ey*ey<=1.0 {
+                    self.set(x,y,colour);
+                }
+            }
+        }
+    }
+
+    /// Fills the white pixels over the native pixels a fill wrote, spreading into the edges of the surrounding
+    /// native lines but never into native pixels left white, so each region keeps its native shape
+    fn fill(&mut self,native:&[u8],sources:&[Option<CommandSource>],index:usize,colour:u8) {
+        let f = self.factor;
+        let width = self.width;
+        let fillable = |pixels:&[u8],coord:usize| pixels[coord]==15 && native[(coord/width/f)*PIC_WIDTH_USIZE+(coord%width)/f]!=15;
+
+        let mut stack = std::mem::take(&mut self.fill_stack);
+        stack.clear();
+        for (coord,source) in sources.iter().enumerate() {
+            if source.map(|s| s.index)==Some(index) {
+                let (x,y) = (coord%PIC_WIDTH_USIZE,coord/PIC_WIDTH_USIZE);
+                stack.extend((0..f*f).map(|i| (y*f+i/f)*width+x*f+i%f));
+            }
+        }
+
+        while let Some(coord) = stack.pop() {
+            if !fillable(&self.pixels,coord) {
+                continue;
+            }
+            self.pixels[coord] = colour;
+            let (x,y) = (coord%width,coord/width);
+            if x>0 { stack.push(coord-1); }
+            if x<width-1 { stack.push(coord+1); }
+            if y>0 { stack.push(coord-width); }
+            if y<self.height-1 { stack.push(coord+width); }
+        }
+        self.fill_stack = stack;
+    }
+}
+
+impl PictureResource {
+    /// Renders the visual screen at factor times the native resolution (row major, 160*factor wide), redrawing
+    /// lines and brushes at that resolution rather than enlarging pixels. Fills cover the same regions as at
+    /// native resolution. The priority screen is returned at native resolution, as game logic uses it.
+    pub fn render_scaled(&self,factor:usize) -> Result<(Vec<u8>,[u8;PIC_WIDTH_USIZE*PIC_HEIGHT_USIZE]), String> {
+        if factor==0 || factor>MAX_SCALE_FACTOR {
+            return Err(format!("Picture scale factor {} is not between 1 and {}",factor,MAX_SCALE_FACTOR));
+        }
+        if factor==1 {
+            let (picture,priority) = self.render()?;
+            return Ok((picture.to_vec(),priority));
+        }
+
+        let commands = self.decode()?;
+        let mut canvas = PictureCanvas::new();
+        canvas.record_provenance();
+        let mut screen = ScaledScreen::new(factor);
+
+        for (index,(offset,command)) in commands.iter().enumerate() {
+            let state = canvas.state;
+            canvas.draw(CommandSource { index, offset:*offset },command);
+            if !state.colour_on {
+                continue;
+            }
+            let colour = state.colour_pen;
+            if let Some(points) = command.get_line_points() {
+                if points.len()==1 {
+                    screen.line(points[0],points[0],colour);
+                }
+                for segment in points.windows(2) {
+                    screen.line(segment[0],segment[1],colour);
+                }
+                continue;
+            }
+            match command {
+                PictureCommand::Plot(points) => {
+                    for point in points {
+                        screen.brush(state.plot_pen,point,colour);
+                    }
+                },
+                PictureCommand::Fill(_) => {
+                    if let Some(provenance) = canvas.get_provenance() {
+                        screen.fill(&canvas.picture,&provenance.visual,index,colour);
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        Ok((screen.pixels,canvas.priority))
+    }
+}
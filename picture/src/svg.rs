@@ -0,0 +1,154 @@
+use helpers::{conv_rgba, conv_greyscale};
+
+use crate::{PictureResource, PictureCommand, PenState, PenStyle, PlotPoint, CommandSource, PIC_WIDTH_USIZE, PIC_HEIGHT_USIZE};
+use crate::canvas::for_each_brush_pixel;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PICTURE:[u8;30] = [
+        0xF0,0x01, 0xF2,0x0A, 0xF6,0x00,0x00,0x9F,0xA7,0x00,0xA7,
+        0xF3, 0xF0,0x03, 0xF9,0x13, 0xFA,0x30,0x40,
+        0xF0,0x0E, 0xF8,0x50,0x10,
+        0xF9,0x07, 0xFA,0x70,0x20,
+        0xFF];
+
+    fn layer<'a>(svg:&'a str,id:&str) -> &'a str {
+        let start = svg.find(&format!("<g id=\"{}\"",id)).unwrap();
+        let end = start+svg[start..].find("</g>").unwrap();
+        &svg[start..end]
+    }
+
+    #[test]
+    fn svg_layers() {
+        let svg = PictureResource::new_from_slice(&PICTURE,false).to_svg().unwrap();
+        assert!(svg.starts_with("<svg "));
+        let visual = layer(&svg,"visual");
+        let priority = layer(&svg,"priority");
+        assert!(visual.contains("<polyline points=\"0.5,0.5 159.5,167.5 0.5,167.5\" stroke=\"#0000ab\""));
+        assert!(priority.contains("<polyline points=\"0.5,0.5 159.5,167.5 0.5,167.5\" stroke=\"#a0a0a0\""));
+        assert!(visual.contains("<rect x=\"46\" y=\"61\" width=\"4\" height=\"7\" fill=\"#00abab\"/>"));
+        assert!(visual.contains("<ellipse cx=\"112\" cy=\"32.5\" rx=\"4\" ry=\"7.5\" fill=\"#ffff7f\"/>"));
+        assert!(visual.contains("<path d=\"M1 0h159v1h-159zM2 1h158v1h-158z"));
+        assert_eq!(priority.matches("<polyline").count(),1);
+        assert!(!priority.contains("<rect x="));
+    }
+
+    #[test]
+    fn svg_splatter_pixels() {
+        let data = [0xF0,0x02,0xF9,0x27,0xFA,0x10,0x50,0x40,0xFF];
+        let resource = PictureResource::new_from_slice(&data,false);
+        let svg = resource.to_svg().unwrap();
+        let (_,_,provenance) = resource.render_with_provenance().unwrap();
+        let drawn = provenance.visual.iter().filter(|s| s.is_some()).count();
+        assert!(drawn>0);
+        assert_eq!(layer(&svg,"visual").matches("h1v1h-1z").count(),drawn);
+    }
+}
+
+fn visual_colour(colour:u8) -> String {
+    let rgba = conv_rgba(&[colour]);
+    format!("#{:02x}{:02x}{:02x}",rgba[0],rgba[1],rgba[2])
+}
+
+fn priority_colour(priority:u8) -> String {
+    let rgba = conv_greyscale(&vec![priority]);
+    format!("#{:02x}{:02x}{:02x}",rgba[0],rgba[1],rgba[2])
+}
+
+fn line_element(points:&[(i16,i16)],colour:&str) -> String {
+    if points.len()==1 {
+        return format!("<rect x=\"{}\" y=\"{}\" width=\"1\" height=\"1\" fill=\"{}\"/>\n",points[0].0,points[0].1,colour);
+    }
+    let points:Vec<String> = points.iter().map(|(x,y)| format!("{},{}",*x as f32+0.5,*y as f32+0.5)).collect();
+    format!("<polyline points=\"{}\" stroke=\"{}\" fill=\"none\" stroke-width=\"1\" stroke-linecap=\"square\"/>\n",points.join(" "),colour)
+}
+
+fn brush_element(pen:PenStyle,point:&PlotPoint,colour:&str) -> String {
+    let (x,y) = (point.x as i16,point.y as i16);
+    if pen.splatter {
+        let mut path = String::new();
+        for_each_brush_pixel(pen,point.texture,x,y,|x,y| {
+            if (0..PIC_WIDTH_USIZE as i16).contains(&x) && (0..PIC_HEIGHT_USIZE as i16).contains(&y) {
+                path.push_str(&format!("M{} {}h1v1h-1z",x,y));
+            }
+        });
+        if path.is_empty() {
+            return path;
+        }
+        return format!("<path d=\"{}\" fill=\"{}\"/>\n",path,colour);
+    }
+    let w = (pen.size as i16)+1;
+    let h = (pen.size as i16)*2+1;
+    let (sx,sy) = (x-w/2,y-h/2);
+    if pen.rectangle {
+        format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",sx,sy,w,h,colour)
+    } else {
+        format!("<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\" fill=\"{}\"/>\n",sx as f32+w as f32/2.0,sy as f32+h as f32/2.0,w as f32/2.0,h as f32/2.0,colour)
+    }
+}
+
+/// The pixels a fill left behind in one layer, as a path of horizontal runs
+fn region_element(sources:&[Option<CommandSource>],index:usize,colour:&str) -> String {
+    let mut path = String::new();
+    for y in 0..PIC_HEIGHT_USIZE {
+        let row = &sources[y*PIC_WIDTH_USIZE..(y+1)*PIC_WIDTH_USIZE];
+        let mut x = 0;
+        while x<PIC_WIDTH_USIZE {
+            if row[x].map(|s| s.index)!=Some(index) {
+                x+=1;
+                continue;
+            }
+            let start = x;
+            while x<PIC_WIDTH_USIZE && row[x].map(|s| s.index)==Some(index) {
+                x+=1;
+            }
+            path.push_str(&format!("M{} {}h{}v1h-{}z",start,y,x-start,x-start));
+        }
+    }
+    if path.is_empty() {
+        return path;
+    }
+    format!("<path d=\"{}\" fill=\"{}\"/>\n",path,colour)
+}
+
+fn command_elements(command:&PictureCommand,index:usize,pen:PenStyle,colour:&str,sources:&[Option<CommandSource>]) -> String {
+    if let Some(points) = command.get_line_points() {
+        return line_element(&points,colour);
+    }
+    match command {
+        PictureCommand::Fill(_) => region_element(sources,index,colour),
+        PictureCommand::Plot(points) => points.iter().map(|p| brush_element(pen,p,colour)).collect(),
+        _ => String::new(),
+    }
+}
+
+impl PictureResource {
+    /// The picture as SVG, drawing lines as polylines, plots as brush shapes and fills as the regions they
+    /// filled in the render. The visual and priority screens are separate groups, with the priority group hidden.
+    pub fn to_svg(&self) -> Result<String, String> {
+        let commands = self.decode()?;
+        let (_,_,provenance) = self.render_with_provenance()?;
+
+        let mut visual = String::new();
+        let mut priority = String::new();
+        let mut state = PenState::default();
+        for (index,(_,command)) in commands.iter().enumerate() {
+            if state.colour_on {
+                visual.push_str(&command_elements(command,index,state.plot_pen,&visual_colour(state.colour_pen),&provenance.visual));
+            }
+            if state.priority_on {
+                priority.push_str(&command_elements(command,index,state.plot_pen,&priority_colour(state.priority_pen),&provenance.priority));
+            }
+            state.apply(command);
+        }
+
+        let (width,height) = (PIC_WIDTH_USIZE,PIC_HEIGHT_USIZE);
+        let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" preserveAspectRatio=\"none\" shape-rendering=\"crispEdges\">\n",width*4,height*2,width,height);
+        svg.push_str(&format!("<g id=\"visual\">\n<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n{}</g>\n",width,height,visual_colour(15),visual));
+        svg.push_str(&format!("<g id=\"priority\" display=\"none\">\n<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>\n{}</g>\n",width,height,priority_colour(4),priority));
+        svg.push_str("</svg>\n");
+        Ok(svg)
+    }
+}
@@ -2,102 +2,61 @@
 use std::time::Duration;
 use glow::HasContext;
 use helpers::{conv_rgba, double_pic_width, conv_rgba_transparent};
-use interpretter::{Interpretter, LogicExecutionPosition, AgiKeyCodes, get_cells_clamped, pri_slice_for_baseline, VAR_CURRENT_ROOM, SCREEN_WIDTH_USIZE, SCREEN_HEIGHT_USIZE};
+use interpretter::{Interpretter, AudioSink, LogicExecutionPosition, AgiKeyCodes, get_cells_clamped, pri_slice_for_baseline, VAR_CURRENT_ROOM, SCREEN_WIDTH_USIZE, SCREEN_HEIGHT_USIZE};
 use logic::*;
+use dir_resource::Root;
+use dir_resource::games::{identify_source, GameDescriptor, GameQuirk};
+use dir_resource::source::{GameFileSource, DirectorySource, ZipSource};
 
 
 use picture::{PIC_HEIGHT_USIZE, PIC_WIDTH_USIZE};
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 use sdl2::event::Event;
 use sdl2::keyboard::{Keycode, Mod};
 use imgui::*;
 
 
-const XMAS:bool=false;
-const DP1:bool=false;
-const KQ1:bool=false;
-const KQ2:bool=false;
-const KQ3:bool=false;
-const KQ4:bool=false;
-const LL1:bool=false;
-const SQ1:bool=true;
-const SQ2:bool=false;
-const SQ2_F:bool=false;
-const GR:bool =false;
-const BC:bool =false;
-const MH1:bool = false;
-const TMP1:bool = false;
+// Game folder (or zip archive) used when none is given on the command line
+const DEFAULT_GAME:&str = "../images/Space Quest- The Sarien Encounter v1.0X (1986)(Sierra On-Line, Inc.) [Adventure]/";
 
 fn main() -> Result<(), String> {
 
-    let mut interpretter:Interpretter;
-
-    if XMAS {
-        interpretter=Interpretter::new("../images/AGI-XMAS/","2.272").unwrap();
-        //interpretter.set_breakpoint(4,44,true);
-    } else if DP1 {
-        interpretter=Interpretter::new("../images/agi_demo_pack_1/","2.915").unwrap();
-        //interpretter.set_breakpoint(161,1,true);
-        interpretter.set_breakpoint_on_instruction(&ActionOperation::ClearTextRect((type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),)), false);
-    } else if KQ1 {
-        //interpretter=Interpretter::new("../images/King's Quest v2.0F (AGI 2.425) (1987)(Sierra On-Line, Inc.) [Adventure]/","2.425").unwrap();
-        interpretter=Interpretter::new("../images/King's Quest v1.0U (1986)(Sierra On-Line, Inc.) [Adventure][!]/","2.272").unwrap();
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,145), false);
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,233), false);
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,181), false);
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,251), false);
-    } else if KQ2 {
-        interpretter=Interpretter::new("../images/King's Quest II- Romancing the Throne v2.1 (1987)(Sierra On-Line, Inc.) [Adventure]/","2.411").unwrap();
-
-    } else if KQ3 {
-        interpretter=Interpretter::new("../images/King's Quest III- To Heir is Human v2.14 (1988)(Sierra On-Line, Inc.) [Adventure]/","2.936").unwrap();
-    } else if KQ4 {
-        interpretter=Interpretter::new("../images/King's Quest IV- The Perils of Rosella v2.0 (AGI Engine) (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.086").unwrap();
-    } else if LL1 {
-        interpretter=Interpretter::new("../images/Leisure Suit Larry in the Land of the Lounge Lizards (1987)(Sierra On-Line, Inc.) [Adventure]/","2.440").unwrap();
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(2,151), false);
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(3,151), false);
-        //interpretter.breakpoints.insert(LogicExecutionPosition::new(6,151), false);
-        //interpretter.set_breakpoint_on_instruction(&ActionOperation::Draw((type_object_from_u8(1),)), false);
-        //interpretter.set_breakpoint_on_instruction(&ActionOperation::AddToPic((type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),)), false);
-        //interpretter.set_breakpoint_on_instruction(&ActionOperation::MenuInput(()), false);
-        //interpretter.set_breakpoint(0,153,true);
-
-        //cheat bypass questions
-        //interpretter.state.set_flag(&TypeFlag::from(110),true);
-    } else if SQ1 {
-        interpretter=Interpretter::new("../images/Space Quest- The Sarien Encounter v1.0X (1986)(Sierra On-Line, Inc.) [Adventure]/","2.089").unwrap();
-        //interpretter.set_breakpoint(5,54,false);
-        //interpretter.set_breakpoint_on_instruction(&ActionOperation::Draw((type_object_from_u8(1),)), false);
-    } else if SQ2 {
-        interpretter=Interpretter::new("../images/Space Quest II- Chapter II - Vohaul's Revenge v2.0C (1987)(Sierra On-Line, Inc.) [Adventure]/","2.917").unwrap();
-        interpretter.set_breakpoint(2,147,true);
-    } else if SQ2_F {
-        interpretter=Interpretter::new("../images/Space Quest II V2.0F/","2.936").unwrap();
-        //interpretter.set_breakpoint(6,126,true);
-    } else if GR {
-        interpretter=Interpretter::new("../images/Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.149").unwrap();
-        interpretter.set_breakpoint(1,1,true);
-    } else if BC {
-        interpretter=Interpretter::new("../images/Black Cauldron, The v2.10 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.098").unwrap();
-        interpretter.set_breakpoint(0,0,true);
-    } else if MH1 {
-        interpretter=Interpretter::new("../images/Manhunter- New York v1.22 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.102").unwrap();
-        //interpretter.set_breakpoint(153,150,true);
-    } else if TMP1 {
-        interpretter=Interpretter::new("../images/TexMcPhilip1QuestForThePapacy/","3.002.102").unwrap();
+    // ragi [game folder or zip] [interpreter version]
+    let base_path = std::env::args().nth(1).unwrap_or(DEFAULT_GAME.to_string());
+    let source:Box<dyn GameFileSource> = if base_path.to_lowercase().ends_with(".zip") {
+        Box::new(ZipSource::open(&base_path)?)
     } else {
-        panic!("NO GAME SET");
-    }
-
+        Box::new(DirectorySource::new(&base_path))
+    };
+    let game = identify_source(source.as_ref())?;
+    let folder = source.name().to_string();
+    let version = match std::env::args().nth(2) {
+        Some(version) => version,
+        None => match game.version {
+            Some(version) => version.to_string(),
+            None => return Err(format!("Unknown interpreter version for {}, give it after the game folder",game.title)),
+        },
+    };
+    println!("{} ({:?}, AGI {}){}",game.title,game.platform,version,if game.is_known() {""} else {" - unknown release"});
+
+    let root = Root::new_from_source(source,&version);
+    let mut interpretter = Interpretter::new_from_root(&root)?;
+    configure_game(&mut interpretter,&game,&folder);
 
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
+    // Without an audio device sounds still run (and set their flags), they just aren't heard
+    match SdlAudioSink::open(&sdl_context) {
+        Ok(sink) => interpretter.set_audio_sink(Box::new(sink)),
+        Err(error) => println!("No audio device, running without sound ({})",error),
+    }
+
     let gl_attr = video_subsystem.gl_attr();
     gl_attr.set_context_version(3, 3);
     gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
 
-    let window = video_subsystem.window("R.A.G.I", 640*2+400, 400*2)
+    let window = video_subsystem.window(&format!("R.A.G.I - {}",game.title), 640*2+400, 400*2)
         .position_centered()
         .resizable()
         .opengl()
@@ -125,6 +84,7 @@ fn main() -> Result<(), String> {
     let mut resume=false;
     let mut step=false;
     let mut pause=false;
+    let mut reported_load_errors=0;
     'running: loop {
         unsafe {
             gl.clear_color(0.0,0.3,0.3,1.0);
@@ -159,6 +119,12 @@ fn main() -> Result<(), String> {
             pause=just_paused;
         }
 
+        // Resources load as the game uses them, so report any that failed since the last frame
+        for error in interpretter.resources.load_errors.borrow().iter().skip(reported_load_errors) {
+            println!("Failed to load {}",error);
+            reported_load_errors+=1;
+        }
+
         resume=false;
         step=false;
 
@@ -261,7 +227,7 @@ fn main() -> Result<(), String> {
             if (live_debug_view || pause) && !interpretter.state.stack.is_empty() {
                 let top_of_stack = &interpretter.state.stack[interpretter.state.stack.len()-1];
                 let file = top_of_stack.get_logic();
-                let logic = interpretter.resources.logic.get(&file);
+                let logic = interpretter.resources.get_logic(file);
                 if !logic.is_none() {
                     if let Some(_t) = ui.begin_table_with_flags("logic_table",2,TableFlags::RESIZABLE|TableFlags::SCROLL_Y|TableFlags::SCROLL_X|TableFlags::NO_KEEP_COLUMNS_VISIBLE) {
                         for (g,s) in logic.unwrap().get_disassembly_iterator(&interpretter.resources.words, &interpretter.resources.objects) {
@@ -342,6 +308,92 @@ fn main() -> Result<(), String> {
 }
 
 
+// Setup for the release's known quirks, then debugging setup for the games being worked on, keyed by game folder
+fn configure_game(interpretter:&mut Interpretter,game:&GameDescriptor,folder:&str) {
+    for quirk in &game.quirks {
+        match quirk {
+            GameQuirk::Demo => {},
+            GameQuirk::AgeQuiz => {
+                //cheat bypass questions
+                //interpretter.state.set_flag(&TypeFlag::from(110),true);
+            },
+        }
+    }
+
+    match folder {
+        "AGI-XMAS" => {
+            //interpretter.set_breakpoint(4,44,true);
+        },
+        "agi_demo_pack_1" => {
+            //interpretter.set_breakpoint(161,1,true);
+            interpretter.set_breakpoint_on_instruction(&ActionOperation::ClearTextRect((type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),)), false);
+        },
+        "King's Quest v1.0U (1986)(Sierra On-Line, Inc.) [Adventure][!]" => {
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,145), false);
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,233), false);
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,181), false);
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(53,251), false);
+        },
+        "Leisure Suit Larry in the Land of the Lounge Lizards (1987)(Sierra On-Line, Inc.) [Adventure]" => {
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(2,151), false);
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(3,151), false);
+            //interpretter.breakpoints.insert(LogicExecutionPosition::new(6,151), false);
+            //interpretter.set_breakpoint_on_instruction(&ActionOperation::Draw((type_object_from_u8(1),)), false);
+            //interpretter.set_breakpoint_on_instruction(&ActionOperation::AddToPic((type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),type_num_from_u8(0),)), false);
+            //interpretter.set_breakpoint_on_instruction(&ActionOperation::MenuInput(()), false);
+            //interpretter.set_breakpoint(0,153,true);
+        },
+        "Space Quest- The Sarien Encounter v1.0X (1986)(Sierra On-Line, Inc.) [Adventure]" => {
+            //interpretter.set_breakpoint(5,54,false);
+            //interpretter.set_breakpoint_on_instruction(&ActionOperation::Draw((type_object_from_u8(1),)), false);
+        },
+        "Space Quest II- Chapter II - Vohaul's Revenge v2.0C (1987)(Sierra On-Line, Inc.) [Adventure]" => {
+            interpretter.set_breakpoint(2,147,true);
+        },
+        "Space Quest II V2.0F" => {
+            //interpretter.set_breakpoint(6,126,true);
+        },
+        "Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]" => {
+            interpretter.set_breakpoint(1,1,true);
+        },
+        "Black Cauldron, The v2.10 (1988)(Sierra On-Line, Inc.) [Adventure]" => {
+            interpretter.set_breakpoint(0,0,true);
+        },
+        "Manhunter- New York v1.22 (1988)(Sierra On-Line, Inc.) [Adventure]" => {
+            //interpretter.set_breakpoint(153,150,true);
+        },
+        _ => {},
+    }
+}
+
+struct SdlAudioSink {
+    queue:AudioQueue<i16>,
+}
+
+impl SdlAudioSink {
+    fn open(sdl_context:&sdl2::Sdl) -> Result<SdlAudioSink,String> {
+        let audio_subsystem = sdl_context.audio()?;
+        let desired_spec = AudioSpecDesired { freq: Some(22050), channels: Some(1), samples: None };
+        let queue = audio_subsystem.open_queue::<i16, _>(None, &desired_spec)?;
+        queue.resume();
+        Ok(SdlAudioSink { queue })
+    }
+}
+
+impl AudioSink for SdlAudioSink {
+    fn get_sample_rate(&self) -> u32 {
+        self.queue.spec().freq as u32
+    }
+
+    fn get_channels(&self) -> usize {
+        self.queue.spec().channels as usize
+    }
+
+    fn queue_samples(&mut self,samples:&[i16]) {
+        let _ = self.queue.queue_audio(samples);
+    }
+}
+
 pub fn map_keycodes(code:Keycode,modifiers:Mod) -> Option<AgiKeyCodes> {
     if modifiers == Mod::LALTMOD || modifiers == Mod::RALTMOD {
         match code {
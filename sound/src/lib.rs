@@ -0,0 +1,641 @@
+use dir_resource::ResourceDirectoryEntry;
+use std::rc::Rc;
+use volume::ResourceStore;
+
+use synth::SOUND_TICKS_PER_SECOND;
+
+pub mod midi;
+pub mod synth;
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn note(duration:u16,divider:u16,attenuation:u8) -> [u8;5] {
+        [duration as u8,(duration>>8) as u8,((divider>>4)&0x3F) as u8,0x80|((divider&0xF) as u8),0x90|attenuation]
+    }
+
+    fn build(voices:&[&[[u8;5]]]) -> Vec<u8> {
+        let mut header:Vec<u8> = Vec::new();
+        let mut body:Vec<u8> = Vec::new();
+        for voice in voices {
+            let offset = 8 + body.len();
+            header.extend_from_slice(&[offset as u8,(offset>>8) as u8]);
+            for n in voice.iter() {
+                body.extend_from_slice(n);
+            }
+            body.extend_from_slice(&[0xFF,0xFF]);
+        }
+        header.extend(body);
+        header
+    }
+
+    #[test]
+    fn construct_ok_single_note() {
+        let data = build(&[&[note(10,0x1FF,2)],&[],&[],&[]]);
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        assert_eq!(sound.get_voices().len(),SOUND_VOICES);
+        let notes = sound.get_voices()[0].get_notes();
+        assert_eq!(notes.len(),1);
+        assert_eq!(notes[0],SoundNote { duration:10, frequency_divider:0x1FF, attenuation:2 });
+        assert!(sound.get_voices()[1].get_notes().is_empty());
+    }
+
+    #[test]
+    fn voice_duration_sums_notes() {
+        let data = build(&[&[note(10,1,0),note(20,2,15)],&[],&[],&[note(5,4,0)]]);
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        assert_eq!(sound.get_voices()[0].get_duration(),30);
+        assert!(sound.get_voices()[0].get_notes()[1].is_silent());
+        assert_eq!(sound.get_duration(),30);
+    }
+
+    #[test]
+    fn amiga_sound_loads_as_pcjr() {
+        // Amiga releases keep the v2 files and the PC voice layout, they load and play the same way
+        let data = build(&[&[note(10,0x1FF,2)],&[note(10,0x0FE,4)],&[],&[note(5,0x004,0)]]);
+        let mut builder = volume::VolumeBuilder::new(false);
+        let entry = builder.add(dir_resource::ResourceType::Sounds,3,&data).unwrap();
+        let mut source = dir_resource::source::MemorySource::new("amiga");
+        source.insert("LOGDIR",Vec::new());
+        source.insert("SNDDIR",builder.directory(dir_resource::ResourceType::Sounds).write_v2().unwrap());
+        source.insert("VOL.0",builder.get_volumes()[0].clone());
+        let root = dir_resource::Root::new_from_source(Box::new(source),"2.082");
+
+        let mut store = ResourceStore::new_from_root(&root).unwrap();
+        let sound = SoundResource::new(&mut store,&entry).unwrap();
+        assert_eq!(sound.get_format(),SoundFormat::PcJr);
+        assert_eq!(sound.get_voices()[1].get_notes()[0],SoundNote { duration:10, frequency_divider:0x0FE, attenuation:4 });
+        assert!(synth::render_pcm(&sound,22050,1).iter().any(|s| *s!=0));
+    }
+
+    #[test]
+    fn construct_fail_truncated_header() {
+        assert!(SoundResource::new_from_slice(&[8,0,8,0]).is_err());
+    }
+
+    #[test]
+    fn construct_fail_unterminated_voice() {
+        let mut data = build(&[&[note(10,1,0)],&[],&[],&[]]);
+        data.truncate(8+5);
+        assert!(SoundResource::new_from_slice(&data).is_err());
+    }
+
+    fn iigs_sample(samples:&[u8]) -> Vec<u8> {
+        let instrument_size = IIGS_INSTRUMENT_HEADER_SIZE+2*IIGS_WAVE_SIZE;
+        let mut data = vec![1u8,0,0x3C,0,0xFF,0,instrument_size as u8,0,samples.len() as u8,0];
+        data.extend_from_slice(&[0x7F,0x10,0x00]);
+        data.extend_from_slice(&[0u8;(IIGS_ENVELOPE_SEGMENTS-1)*3]);
+        data.extend_from_slice(&[1,0,2,0,0,0,1,1]);
+        data.extend_from_slice(&[0x7F,0,0,0,0x00,0x01]);
+        data.extend_from_slice(&[0x7F,0,0,0,0x00,0x02]);
+        data.extend_from_slice(samples);
+        data
+    }
+
+    #[test]
+    fn construct_ok_iigs_sample() {
+        let sound = SoundResource::new_from_slice(&iigs_sample(&[0x80,0xC0,0x40,0x00,0x80])).unwrap();
+        assert_eq!(sound.get_format(),SoundFormat::IIgsSample);
+        assert!(sound.get_voices().is_empty());
+        let sample = sound.get_sample().unwrap();
+        assert_eq!(sample.get_pitch(),0x3C);
+        assert_eq!(sample.get_volume(),0xFF);
+        assert_eq!(sample.get_data(),&vec![0x80,0xC0,0x40]);
+        let instrument = sample.get_instrument();
+        assert_eq!(instrument.envelope[0],IIgsEnvelopeSegment { breakpoint:0x7F, increment:0x10 });
+        assert_eq!(instrument.pitch_bend_range,2);
+        assert_eq!(instrument.waves_a.len(),1);
+        assert_eq!(instrument.waves_b[0].relative_pitch,0x0200);
+        assert_eq!(sound.get_duration(),1);
+    }
+
+    #[test]
+    fn iigs_sample_rate_follows_pitch() {
+        // Waves A holds a +1 semitone tune, so the rate is a semitone above the pitch
+        let mut data = iigs_sample(&[0x80;16]);
+        data[2] = IIGS_SAMPLE_REFERENCE_KEY-1;
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        assert!((sound.get_sample().unwrap().get_sample_rate()-SOUND_IIGS_SAMPLE_RATE as f64).abs()<0.001);
+        data[2] = IIGS_SAMPLE_REFERENCE_KEY+11;
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        assert!((sound.get_sample().unwrap().get_sample_rate()-2.0*SOUND_IIGS_SAMPLE_RATE as f64).abs()<0.001);
+    }
+
+    #[test]
+    fn iigs_wave_layout() {
+        let wave = IIgsWave { top_key:0x7F, address:0x02, size:0x18, mode:0, relative_pitch:0xFF00 };
+        assert_eq!(wave.get_offset(),0x200);
+        assert_eq!(wave.get_size(),0x800);
+        assert!((wave.get_tune()+1.0).abs()<0.001);
+        let waves = [IIgsWave { top_key:40, ..wave },IIgsWave { top_key:80, ..wave }];
+        assert_eq!(wave_for_key(&waves,20).unwrap().top_key,40);
+        assert_eq!(wave_for_key(&waves,60).unwrap().top_key,80);
+        assert_eq!(wave_for_key(&waves,100).unwrap().top_key,80);
+        assert!(wave_for_key(&[],60).is_none());
+    }
+
+    #[test]
+    fn iigs_bank_programs() {
+        let sound = SoundResource::new_from_slice(&iigs_sample(&[0x80,0xC0,0x40])).unwrap();
+        let mut bank = IIgsInstrumentBank::new();
+        assert!(bank.is_empty());
+        assert_eq!(bank.add_sample(sound.get_sample().unwrap()),0);
+        assert_eq!(&bank.get(0).unwrap().1[..],&[0x80,0xC0,0x40]);
+        assert!(bank.get(1).is_none());
+    }
+
+    #[test]
+    fn iigs_bank_from_table() {
+        let sample = iigs_sample(&[]);
+        let instrument = &sample[IIGS_SAMPLE_HEADER_SIZE..];
+        let table = [instrument,instrument].concat();
+        let bank = IIgsInstrumentBank::new_from_slices(&table,&[0x80;0x100]).unwrap();
+        assert_eq!(bank.len(),2);
+        assert_eq!(bank.get(1).unwrap().0.waves_b[0].relative_pitch,0x0200);
+        assert_eq!(bank.get(1).unwrap().1.len(),0x100);
+        assert!(IIgsInstrumentBank::new_from_slices(&table[..table.len()-1],&[]).is_err());
+    }
+
+    #[test]
+    fn construct_fail_truncated_iigs_sample() {
+        let mut data = iigs_sample(&[0x80;16]);
+        data.truncate(data.len()-1);
+        assert!(SoundResource::new_from_slice(&data).is_err());
+    }
+
+    #[test]
+    fn construct_ok_iigs_midi() {
+        let data = [2u8,0, 0,0xC0,5, 0,0x90,60,100, 30,62,90, 0xF8, 15,0x80,60,0, 0xFC];
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        assert_eq!(sound.get_format(),SoundFormat::IIgsMidi);
+        let events = sound.get_midi_events();
+        assert_eq!(events.len(),4);
+        assert_eq!(events[2],IIgsMidiEvent { delta:30, status:0x90, data:[62,90] });
+        assert!(events[2].is_note_on());
+        assert!(events[3].is_note_off());
+        assert_eq!(sound.get_duration(),45);
+    }
+
+    #[test]
+    fn construct_fail_unterminated_iigs_midi() {
+        assert!(SoundResource::new_from_slice(&[2u8,0,0,0x90,60,100]).is_err());
+    }
+
+}
+
+/// Number of voices in a PC/PCjr sound resource (3 tone + 1 noise)
+pub const SOUND_VOICES:usize = 4;
+/// Index of the noise voice within a PC/PCjr sound resource
+pub const SOUND_NOISE_VOICE:usize = 3;
+/// Attenuation value that silences a voice
+pub const SOUND_ATTENUATION_OFF:u8 = 0x0F;
+/// Playback rate of an Apple IIgs sampled sound whose pitch is IIGS_SAMPLE_REFERENCE_KEY
+pub const SOUND_IIGS_SAMPLE_RATE:u32 = 8000;
+/// Midi key (middle C) a IIgs sample plays at its recorded rate for, other pitches resample it
+pub const IIGS_SAMPLE_REFERENCE_KEY:u8 = 60;
+
+const SOUND_END_OF_VOICE:u16 = 0xFFFF;
+
+const SOUND_TYPE_IIGS_SAMPLE:u16 = 1;
+const SOUND_TYPE_IIGS_MIDI:u16 = 2;
+
+const IIGS_SAMPLE_HEADER_SIZE:usize = 10;
+/// Number of segments in a IIgs instrument's volume envelope
+pub const IIGS_ENVELOPE_SEGMENTS:usize = 8;
+const IIGS_INSTRUMENT_HEADER_SIZE:usize = IIGS_ENVELOPE_SEGMENTS*3 + 8;
+const IIGS_WAVE_SIZE:usize = 6;
+const IIGS_SAMPLE_END:u8 = 0x00;     // the Ensoniq DOC halts on a zero sample
+
+const IIGS_MIDI_END:u8 = 0xFC;
+const IIGS_MIDI_TIMER_SYNC:u8 = 0xF8;
+
+/// Which of the AGI sound formats a resource was stored in
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum SoundFormat {
+    /// 3 tone + 1 noise voices for the SN76496 (PC, PCjr, Tandy). The Amiga and other ports ship their
+    /// sounds in this layout too, they are deliberately played as PCjr sounds rather than emulating each port.
+    PcJr,
+    /// A single 8 bit sample played on the Ensoniq DOC
+    IIgsSample,
+    /// A midi event stream played with IIgs instruments
+    IIgsMidi,
+}
+
+/// A single note in a sound voice, durations are in 1/60th of a second
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct SoundNote {
+    pub duration:u16,
+    /// 10 bit SN76496 tone divider, for the noise voice the low 3 bits hold the noise control instead
+    pub frequency_divider:u16,
+    /// 0 is loudest, 15 is silent (2dB steps)
+    pub attenuation:u8,
+}
+
+pub struct SoundVoice {
+    notes:Vec<SoundNote>,
+}
+
+/// A step of an instrument's volume envelope, moving towards breakpoint (0-127) by increment/65535 of
+/// full volume every 1/100th of a second
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct IIgsEnvelopeSegment {
+    pub breakpoint:u8,
+    pub increment:u16,
+}
+
+/// A wave an Ensoniq oscillator plays for keys up to top_key, read from the instrument's wave memory
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct IIgsWave {
+    pub top_key:u8,
+    pub address:u8,
+    pub size:u8,
+    pub mode:u8,
+    pub relative_pitch:u16,
+}
+
+/// Ensoniq instrument definition that precedes the sample data, the envelope holds at release_segment
+/// until the key is released. Each key plays a wave from waves_a and one from waves_b together.
+#[derive(Clone,Debug,PartialEq)]
+pub struct IIgsInstrument {
+    pub envelope:[IIgsEnvelopeSegment;IIGS_ENVELOPE_SEGMENTS],
+    pub release_segment:u8,
+    pub priority_increment:u8,
+    pub pitch_bend_range:u8,
+    pub vibrato_depth:u8,
+    pub vibrato_speed:u8,
+    pub waves_a:Vec<IIgsWave>,
+    pub waves_b:Vec<IIgsWave>,
+}
+
+pub struct IIgsSample {
+    pitch:u8,
+    volume:u8,
+    instrument:IIgsInstrument,
+    data:Vec<u8>,
+}
+
+/// Instruments the program changes of IIgs midi sounds select from, each with the wave memory its waves
+/// are read from. The games keep these outside the sound resources, so a frontend supplies them; programs
+/// missing from the bank play through a built in instrument.
+#[derive(Default)]
+pub struct IIgsInstrumentBank {
+    instruments:Vec<(IIgsInstrument,Rc<[u8]>)>,
+}
+
+/// A channel message from a IIgs midi stream, delta is the wait in 1/60th of a second before it plays
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct IIgsMidiEvent {
+    pub delta:u8,
+    pub status:u8,
+    pub data:[u8;2],
+}
+
+pub struct SoundResource {
+    format:SoundFormat,
+    voices:Vec<SoundVoice>,
+    sample:Option<IIgsSample>,
+    midi:Vec<IIgsMidiEvent>,
+}
+
+impl SoundNote {
+    pub fn is_silent(&self) -> bool {
+        self.attenuation==SOUND_ATTENUATION_OFF
+    }
+
+    /// True if the noise control selects white noise (rather than periodic)
+    pub fn is_white_noise(&self) -> bool {
+        (self.frequency_divider&4)==4
+    }
+
+    /// Noise shift rate 0-2 are fixed rates, 3 takes its rate from tone voice 2
+    pub fn get_noise_rate(&self) -> u8 {
+        (self.frequency_divider&3) as u8
+    }
+}
+
+impl SoundVoice {
+    pub fn get_notes(&self) -> &Vec<SoundNote> {
+        &self.notes
+    }
+
+    pub fn get_duration(&self) -> u32 {
+        self.notes.iter().map(|n| n.duration as u32).sum()
+    }
+}
+
+impl IIgsWave {
+    /// Start of the wave within the wave memory
+    pub fn get_offset(&self) -> usize {
+        (self.address as usize)<<8
+    }
+
+    /// Length of the wave in bytes (256 to 32768)
+    pub fn get_size(&self) -> usize {
+        0x100<<((self.size>>3)&7)
+    }
+
+    /// Tuning in semitones (8.8 fixed point)
+    pub fn get_tune(&self) -> f64 {
+        (self.relative_pitch as i16) as f64/256.0
+    }
+}
+
+/// The wave used for a key, the first whose top key is at or above it (or the last one)
+pub fn wave_for_key(waves:&[IIgsWave],key:u8) -> Option<&IIgsWave> {
+    waves.iter().find(|w| w.top_key>=key).or(waves.last())
+}
+
+impl IIgsSample {
+    pub fn get_pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    pub fn get_volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn get_instrument(&self) -> &IIgsInstrument {
+        &self.instrument
+    }
+
+    /// Unsigned 8 bit samples (0x80 is silence), played back at get_sample_rate
+    pub fn get_data(&self) -> &Vec<u8> {
+        &self.data
+    }
+
+    /// Rate the data plays at, SOUND_IIGS_SAMPLE_RATE moved by the pitch (and the tune of its wave) from middle C
+    pub fn get_sample_rate(&self) -> f64 {
+        let tune = wave_for_key(&self.instrument.waves_a,self.pitch).map(|w| w.get_tune()).unwrap_or_default();
+        let semitones = (self.pitch as f64)-(IIGS_SAMPLE_REFERENCE_KEY as f64)+tune;
+        (SOUND_IIGS_SAMPLE_RATE as f64)*2f64.powf(semitones/12.0)
+    }
+}
+
+impl IIgsInstrumentBank {
+    pub fn new() -> IIgsInstrumentBank {
+        IIgsInstrumentBank { instruments:Vec::new() }
+    }
+
+    /// Parses a table of instrument definitions stored back to back (as the IIgs interpreter keeps them),
+    /// all reading their waves from the same wave memory (the SIERRASTANDARD file)
+    pub fn new_from_slices(instruments:&[u8],wave_data:&[u8]) -> Result<IIgsInstrumentBank, String> {
+        let mut bank = IIgsInstrumentBank::new();
+        let wave_data:Rc<[u8]> = wave_data.into();
+        let mut position = 0;
+        while position < instruments.len() {
+            if bank.len() > u8::MAX as usize {
+                return Err("IIgs instrument table holds more than 256 instruments".to_string());
+            }
+            let instrument = parse_iigs_instrument(&instruments[position..])?;
+            position += IIGS_INSTRUMENT_HEADER_SIZE + (instrument.waves_a.len()+instrument.waves_b.len())*IIGS_WAVE_SIZE;
+            bank.add(instrument,wave_data.clone());
+        }
+        Ok(bank)
+    }
+
+    /// Adds an instrument as the next program, returns its program number
+    pub fn add(&mut self,instrument:IIgsInstrument,wave_data:Rc<[u8]>) -> u8 {
+        self.instruments.push((instrument,wave_data));
+        (self.instruments.len()-1) as u8
+    }
+
+    /// Adds the instrument of a sampled sound, with the sample as its wave memory
+    pub fn add_sample(&mut self,sample:&IIgsSample) -> u8 {
+        self.add(sample.instrument.clone(),sample.data.as_slice().into())
+    }
+
+    pub fn get(&self,program:u8) -> Option<&(IIgsInstrument,Rc<[u8]>)> {
+        self.instruments.get(program as usize)
+    }
+
+    pub fn len(&self) -> usize {
+        self.instruments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instruments.is_empty()
+    }
+}
+
+impl IIgsMidiEvent {
+    pub fn get_channel(&self) -> u8 {
+        self.status&0x0F
+    }
+
+    pub fn is_note_on(&self) -> bool {
+        self.status&0xF0==0x90 && self.data[1]!=0
+    }
+
+    pub fn is_note_off(&self) -> bool {
+        self.status&0xF0==0x80 || (self.status&0xF0==0x90 && self.data[1]==0)
+    }
+}
+
+fn read_u16(slice:&[u8],position:usize) -> Option<u16> {
+    let bytes = slice.get(position..position+2)?;
+    Some(u16::from_le_bytes([bytes[0],bytes[1]]))
+}
+
+fn parse_voices(slice:&[u8]) -> Result<Vec<SoundVoice>, String> {
+
+    if slice.len() < SOUND_VOICES*2 {
+        return Err(format!("Sound header truncated, expected {} bytes, got {}",SOUND_VOICES*2,slice.len()));
+    }
+
+    let mut voices:Vec<SoundVoice> = Vec::with_capacity(SOUND_VOICES);
+    for v in 0..SOUND_VOICES {
+        let mut position = read_u16(slice,v*2).unwrap() as usize;
+
+        let mut notes:Vec<SoundNote> = Vec::new();
+        loop {
+            let duration = match read_u16(slice,position) {
+                Some(d) => d,
+                None => return Err(format!("Voice {} ran past the end of the sound resource",v)),
+            };
+            if duration == SOUND_END_OF_VOICE {
+                break;
+            }
+
+            let note = match slice.get(position+2..position+5) {
+                Some(n) => n,
+                None => return Err(format!("Voice {} note {} is truncated",v,notes.len())),
+            };
+            position+=5;
+
+            let frequency_divider:u16 = (note[0]&0x3F).into();
+            let frequency_divider = frequency_divider<<4;
+            let frequency_divider = frequency_divider + ((note[1]&0x0F) as u16);
+            let attenuation = note[2]&0x0F;
+
+            notes.push(SoundNote { duration, frequency_divider, attenuation });
+        }
+        voices.push(SoundVoice { notes });
+    }
+
+    Ok(voices)
+}
+
+fn parse_iigs_wave(slice:&[u8]) -> IIgsWave {
+    IIgsWave { top_key:slice[0], address:slice[1], size:slice[2], mode:slice[3], relative_pitch:u16::from_le_bytes([slice[4],slice[5]]) }
+}
+
+fn parse_iigs_instrument(slice:&[u8]) -> Result<IIgsInstrument, String> {
+
+    if slice.len() < IIGS_INSTRUMENT_HEADER_SIZE {
+        return Err(format!("IIgs instrument truncated, expected {} bytes, got {}",IIGS_INSTRUMENT_HEADER_SIZE,slice.len()));
+    }
+
+    let mut envelope = [IIgsEnvelopeSegment { breakpoint:0, increment:0 };IIGS_ENVELOPE_SEGMENTS];
+    for (i,segment) in envelope.iter_mut().enumerate() {
+        segment.breakpoint = slice[i*3];
+        segment.increment = u16::from_le_bytes([slice[i*3+1],slice[i*3+2]]);
+    }
+
+    let p = IIGS_ENVELOPE_SEGMENTS*3;
+    let count_a = slice[p+6] as usize;
+    let count_b = slice[p+7] as usize;
+    let waves_end = IIGS_INSTRUMENT_HEADER_SIZE + (count_a+count_b)*IIGS_WAVE_SIZE;
+    let waves = match slice.get(IIGS_INSTRUMENT_HEADER_SIZE..waves_end) {
+        Some(w) => w,
+        None => return Err(format!("IIgs instrument wave list truncated, expected {} waves",count_a+count_b)),
+    };
+    let mut waves = waves.chunks_exact(IIGS_WAVE_SIZE).map(parse_iigs_wave);
+
+    Ok(IIgsInstrument {
+        envelope,
+        release_segment:slice[p],
+        priority_increment:slice[p+1],
+        pitch_bend_range:slice[p+2],
+        vibrato_depth:slice[p+3],
+        vibrato_speed:slice[p+4],
+        waves_a:waves.by_ref().take(count_a).collect(),
+        waves_b:waves.collect(),
+    })
+}
+
+fn parse_iigs_sample(slice:&[u8]) -> Result<IIgsSample, String> {
+
+    if slice.len() < IIGS_SAMPLE_HEADER_SIZE {
+        return Err(format!("IIgs sample header truncated, expected {} bytes, got {}",IIGS_SAMPLE_HEADER_SIZE,slice.len()));
+    }
+
+    let pitch = slice[2];
+    let volume = slice[4];
+    let instrument_size = read_u16(slice,6).unwrap() as usize;
+    let sample_size = read_u16(slice,8).unwrap() as usize;
+    let instrument_start = IIGS_SAMPLE_HEADER_SIZE;
+    let sample_start = instrument_start+instrument_size;
+
+    let instrument = match slice.get(instrument_start..sample_start) {
+        Some(i) => parse_iigs_instrument(i)?,
+        None => return Err(format!("IIgs instrument truncated, expected {} bytes",instrument_size)),
+    };
+    let data = match slice.get(sample_start..sample_start+sample_size) {
+        Some(d) => d,
+        None => return Err(format!("IIgs sample data truncated, expected {} bytes, got {}",sample_size,slice.len().saturating_sub(sample_start))),
+    };
+    let data = data.iter().take_while(|s| **s!=IIGS_SAMPLE_END).copied().collect();
+
+    Ok(IIgsSample { pitch, volume, instrument, data })
+}
+
+fn parse_iigs_midi(slice:&[u8]) -> Result<Vec<IIgsMidiEvent>, String> {
+
+    let mut events:Vec<IIgsMidiEvent> = Vec::new();
+    let mut running_status:Option<u8> = None;
+    let mut position = 2;
+    loop {
+        let delta = match slice.get(position) {
+            Some(&IIGS_MIDI_END) => break,
+            Some(&IIGS_MIDI_TIMER_SYNC) => { position+=1; continue; },
+            Some(d) => *d,
+            None => return Err(format!("IIgs midi stream ran past the end of the sound resource after {} events",events.len())),
+        };
+        position+=1;
+
+        let status = match slice.get(position) {
+            Some(s) if s&0x80 == 0x80 => { position+=1; *s },
+            Some(_) if running_status.is_some() => running_status.unwrap(),
+            Some(_) => return Err(format!("IIgs midi event {} has no status",events.len())),
+            None => return Err(format!("IIgs midi event {} is truncated",events.len())),
+        };
+        let length = match status&0xF0 {
+            0x80 | 0x90 | 0xA0 | 0xB0 | 0xE0 => 2,
+            0xC0 | 0xD0 => 1,
+            _ => return Err(format!("IIgs midi event {} has unsupported status {:02X}",events.len(),status)),
+        };
+        let mut data = [0u8;2];
+        match slice.get(position..position+length) {
+            Some(d) => data[..length].copy_from_slice(d),
+            None => return Err(format!("IIgs midi event {} is truncated",events.len())),
+        }
+        position+=length;
+        running_status = Some(status);
+
+        events.push(IIgsMidiEvent { delta, status, data });
+    }
+
+    Ok(events)
+}
+
+impl SoundResource {
+    pub fn new(store:&mut ResourceStore, entry: &ResourceDirectoryEntry) -> Result<SoundResource, String> {
+        let (data,_) = store.fetch(entry)?;
+        SoundResource::new_from_slice(&data)
+    }
+
+    /// Detects the format from the first word - a IIgs type (1 or 2), otherwise the offset of voice 0
+    pub fn new_from_slice(slice:&[u8]) -> Result<SoundResource, String> {
+
+        if slice.is_empty() {
+            return Ok(SoundResource { format:SoundFormat::PcJr, voices:Vec::new(), sample:None, midi:Vec::new() });
+        }
+
+        let format = match read_u16(slice,0) {
+            Some(SOUND_TYPE_IIGS_SAMPLE) => SoundFormat::IIgsSample,
+            Some(SOUND_TYPE_IIGS_MIDI) => SoundFormat::IIgsMidi,
+            _ => SoundFormat::PcJr,
+        };
+
+        let mut sound = SoundResource { format, voices:Vec::new(), sample:None, midi:Vec::new() };
+        match format {
+            SoundFormat::PcJr => sound.voices = parse_voices(slice)?,
+            SoundFormat::IIgsSample => sound.sample = Some(parse_iigs_sample(slice)?),
+            SoundFormat::IIgsMidi => sound.midi = parse_iigs_midi(slice)?,
+        }
+
+        Ok(sound)
+    }
+
+    pub fn get_format(&self) -> SoundFormat {
+        self.format
+    }
+
+    /// Note voices, empty for IIgs sounds
+    pub fn get_voices(&self) -> &Vec<SoundVoice> {
+        &self.voices
+    }
+
+    pub fn get_sample(&self) -> Option<&IIgsSample> {
+        self.sample.as_ref()
+    }
+
+    pub fn get_midi_events(&self) -> &Vec<IIgsMidiEvent> {
+        &self.midi
+    }
+
+    /// Length of the sound (the longest voice for note formats), in 1/60th of a second
+    pub fn get_duration(&self) -> u32 {
+        match self.format {
+            SoundFormat::PcJr => self.voices.iter().map(|v| v.get_duration()).max().unwrap_or_default(),
+            SoundFormat::IIgsSample => match &self.sample {
+                Some(sample) => ((sample.data.len() as f64)*(SOUND_TICKS_PER_SECOND as f64)/sample.get_sample_rate()).ceil() as u32,
+                None => 0,
+            },
+            SoundFormat::IIgsMidi => self.midi.iter().map(|e| e.delta as u32).sum(),
+        }
+    }
+
+}
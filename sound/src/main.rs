@@ -0,0 +1,55 @@
+use std::fs;
+
+use dir_resource::{ResourceDirectory, ResourceDirectoryEntry, Root, ResourceType};
+use helpers::dump_wav;
+use sound::SoundResource;
+use sound::midi::export_midi;
+use sound::synth::render_pcm;
+use volume::ResourceStore;
+
+fn main() {
+
+    //let root = Root::new("../images/Space Quest- The Sarien Encounter v1.0X (1986)(Sierra On-Line, Inc.) [Adventure]/","2.089");
+    let root = Root::new("../images/Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.149");
+    let dir = ResourceDirectory::new(&root, ResourceType::Sounds).unwrap();
+    let mut store = ResourceStore::new_from_root(&root).unwrap();
+
+    for (index,entry) in dir.into_iter().enumerate() {
+        if !entry.empty() {
+            println!("{} : V{} P{}",index,entry.volume,entry.position);
+            dump_sound_resource(&mut store,&entry, index);
+        }
+    }
+
+}
+
+fn dump_sound_resource(store:&mut ResourceStore,entry:&ResourceDirectoryEntry, index:usize) {
+
+    let sound = match SoundResource::new(store, entry) {
+        Ok(b) => b,
+        Err(s) => panic!("Failed due to : {}", s),
+    };
+
+    println!("{}-{:?} Duration : {} ticks",index, sound.get_format(), sound.get_duration());
+    if let Some(sample) = sound.get_sample() {
+        println!("  Sample : {} bytes, pitch {} volume {}",sample.get_data().len(),sample.get_pitch(),sample.get_volume());
+    }
+    for e in sound.get_midi_events() {
+        println!("    {:3} : {:02X} {:02X} {:02X}",e.delta,e.status,e.data[0],e.data[1]);
+    }
+    for (v_index,v) in sound.get_voices().iter().enumerate() {
+        println!("  Voice {} : {} notes, {} ticks",v_index,v.get_notes().len(),v.get_duration());
+        for n in v.get_notes() {
+            println!("    {:5} : divider {:04X} attenuation {:X}",n.duration,n.frequency_divider,n.attenuation);
+        }
+    }
+
+    let pcm = render_pcm(&sound, 22050, 1);
+    dump_wav(format!("../{}-sound.wav",index).as_str(), 22050, 1, &pcm);
+
+    match export_midi(&sound) {
+        Ok(midi) => fs::write(format!("../{}-sound.mid",index).as_str(),midi).unwrap(),
+        Err(s) => println!("  No midi export : {}",s),
+    }
+
+}
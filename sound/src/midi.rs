@@ -0,0 +1,253 @@
+use crate::{SoundResource, SoundFormat, SoundVoice, SoundNote, IIgsMidiEvent, SOUND_NOISE_VOICE};
+use crate::synth::{SN76496_CLOCK, SOUND_TICKS_PER_SECOND};
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn divider_maps_to_a440() {
+        // 3579545/(32*254) = 440.4Hz
+        let (note,bend) = divider_to_midi(254);
+        assert_eq!(note,69);
+        assert!(bend>MIDI_PITCH_BEND_CENTRE && bend<MIDI_PITCH_BEND_CENTRE+100);
+    }
+
+    #[test]
+    fn divider_maps_to_octave_below() {
+        let (note,_) = divider_to_midi(508);
+        assert_eq!(note,57);
+    }
+
+    #[test]
+    fn attenuation_maps_to_velocity() {
+        assert_eq!(attenuation_to_velocity(0),127);
+        assert!(attenuation_to_velocity(14)>0);
+        assert!(attenuation_to_velocity(1)<127);
+    }
+
+    #[test]
+    fn export_writes_header_and_tracks() {
+        let mut data = vec![8u8,0,15,0,17,0,19,0];
+        data.extend_from_slice(&[30,0,0x0F,0x8E,0x90]);
+        data.extend_from_slice(&[0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF]);
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        let midi = export_midi(&sound).unwrap();
+        assert_eq!(&midi[0..4],b"MThd");
+        assert_eq!(&midi[8..14],&[0,1,0,5,0,MIDI_TICKS_PER_QUARTER as u8]);
+        assert_eq!(midi.windows(4).filter(|w| w==b"MTrk").count(),5);
+    }
+
+    #[test]
+    fn export_iigs_midi_events() {
+        let data = [2u8,0, 0,0xC1,5, 0,0x91,60,100, 30,0x81,60,0, 0xFC];
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        let midi = export_midi(&sound).unwrap();
+        assert_eq!(&midi[8..14],&[0,1,0,2,0,MIDI_TICKS_PER_QUARTER as u8]);
+        assert_eq!(midi.windows(4).filter(|w| w==b"MTrk").count(),2);
+        let events = [0,0xC1,5, 0,0x91,60,100, 30,0x81,60,0, 0,0xFF,0x2F,0];
+        assert!(midi.ends_with(&events));
+    }
+
+    #[test]
+    fn export_fails_for_iigs_sample() {
+        let mut data = vec![1u8,0,60,0,0xFF,0,32,0,1,0];
+        data.extend_from_slice(&[0u8;32]);
+        data.push(0x80);
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        assert!(export_midi(&sound).is_err());
+    }
+
+    #[test]
+    fn variable_length_quantity() {
+        let mut out:Vec<u8> = Vec::new();
+        write_variable_length(&mut out,0x3FFF);
+        assert_eq!(out,vec![0xFF,0x7F]);
+        out.clear();
+        write_variable_length(&mut out,0);
+        assert_eq!(out,vec![0]);
+    }
+}
+
+/// 120bpm with 30 ticks per quarter note gives 60 ticks a second, the same unit as sound note durations
+pub const MIDI_TICKS_PER_QUARTER:u16 = (SOUND_TICKS_PER_SECOND/2) as u16;
+pub const MIDI_PITCH_BEND_CENTRE:u16 = 0x2000;
+/// Pitch bend range (in semitones) programmed into each tone channel
+pub const MIDI_PITCH_BEND_RANGE:u8 = 2;
+pub const MIDI_PERCUSSION_CHANNEL:u8 = 9;
+
+const MIDI_MICROSECONDS_PER_QUARTER:u32 = 500000;
+const MIDI_PROGRAM_SQUARE_LEAD:u8 = 80;
+
+// General MIDI percussion used for the noise voice, indexed by shift rate (0-3)
+const MIDI_PERIODIC_NOISE_DRUMS:[u8;4] = [35,36,41,45];   // Bass drums and low toms
+const MIDI_WHITE_NOISE_DRUMS:[u8;4] = [42,38,40,49];      // Hi-hat, snares, crash
+
+/// Nearest midi note for a SN76496 tone divider, along with the pitch bend that corrects for the difference
+pub fn divider_to_midi(divider:u16) -> (u8,u16) {
+    let divider = if divider==0 {0x400} else {divider};
+    let frequency = (SN76496_CLOCK as f64) / (32.0 * (divider as f64));
+    let exact = 69.0 + 12.0*(frequency/440.0).log2();
+    let note = exact.round().clamp(0.0,127.0);
+    let semitones = (exact-note).clamp(-(MIDI_PITCH_BEND_RANGE as f64),MIDI_PITCH_BEND_RANGE as f64);
+    let bend = (MIDI_PITCH_BEND_CENTRE as f64) + semitones*(MIDI_PITCH_BEND_CENTRE as f64)/(MIDI_PITCH_BEND_RANGE as f64);
+    (note as u8,(bend.round() as u16).min(0x3FFF))
+}
+
+/// Velocity for an attenuation level, following the 2dB per step volume curve
+pub fn attenuation_to_velocity(attenuation:u8) -> u8 {
+    if attenuation>=15 {
+        return 0;
+    }
+    let amplitude = 10f64.powf(-(attenuation as f64)*2.0/20.0);
+    ((127.0*amplitude).round() as u8).max(1)
+}
+
+fn write_variable_length(out:&mut Vec<u8>,value:u32) {
+    let mut bytes = [0u8;4];
+    let mut count = 0;
+    let mut value = value;
+    loop {
+        bytes[count] = (value&0x7F) as u8;
+        count+=1;
+        value>>=7;
+        if value==0 {
+            break;
+        }
+    }
+    for i in (0..count).rev() {
+        out.push(if i!=0 {bytes[i]|0x80} else {bytes[i]});
+    }
+}
+
+struct MidiTrack {
+    data:Vec<u8>,
+    pending_delta:u32,
+}
+
+impl MidiTrack {
+    fn new() -> MidiTrack {
+        MidiTrack { data:Vec::new(), pending_delta:0 }
+    }
+
+    fn event(&mut self,bytes:&[u8]) {
+        write_variable_length(&mut self.data,self.pending_delta);
+        self.pending_delta=0;
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn wait(&mut self,ticks:u32) {
+        self.pending_delta+=ticks;
+    }
+
+    fn end(mut self,out:&mut Vec<u8>) {
+        self.event(&[0xFF,0x2F,0x00]);
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(self.data.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.data);
+    }
+}
+
+fn tone_track(voice:&SoundVoice,channel:u8) -> MidiTrack {
+    let mut track = MidiTrack::new();
+
+    track.event(&[0xC0|channel,MIDI_PROGRAM_SQUARE_LEAD]);
+    // Program the pitch bend range (RPN 0)
+    track.event(&[0xB0|channel,101,0]);
+    track.event(&[0xB0|channel,100,0]);
+    track.event(&[0xB0|channel,6,MIDI_PITCH_BEND_RANGE]);
+    track.event(&[0xB0|channel,38,0]);
+
+    let mut current_bend = MIDI_PITCH_BEND_CENTRE;
+    for note in voice.get_notes() {
+        let velocity = attenuation_to_velocity(note.attenuation);
+        if velocity==0 || note.duration==0 {
+            track.wait(note.duration.into());
+            continue;
+        }
+        let (key,bend) = divider_to_midi(note.frequency_divider);
+        if bend!=current_bend {
+            track.event(&[0xE0|channel,(bend&0x7F) as u8,(bend>>7) as u8]);
+            current_bend=bend;
+        }
+        track.event(&[0x90|channel,key,velocity]);
+        track.wait(note.duration.into());
+        track.event(&[0x80|channel,key,0]);
+    }
+    track
+}
+
+fn noise_drum(note:&SoundNote) -> u8 {
+    if note.is_white_noise() {
+        MIDI_WHITE_NOISE_DRUMS[note.get_noise_rate() as usize]
+    } else {
+        MIDI_PERIODIC_NOISE_DRUMS[note.get_noise_rate() as usize]
+    }
+}
+
+fn noise_track(voice:&SoundVoice) -> MidiTrack {
+    let mut track = MidiTrack::new();
+    let channel = MIDI_PERCUSSION_CHANNEL;
+    for note in voice.get_notes() {
+        let velocity = attenuation_to_velocity(note.attenuation);
+        if velocity==0 || note.duration==0 {
+            track.wait(note.duration.into());
+            continue;
+        }
+        let key = noise_drum(note);
+        track.event(&[0x90|channel,key,velocity]);
+        track.wait(note.duration.into());
+        track.event(&[0x80|channel,key,0]);
+    }
+    track
+}
+
+/// IIgs midi deltas are already in 1/60th of a second, so the events copy across unchanged
+fn iigs_track(events:&[IIgsMidiEvent]) -> MidiTrack {
+    let mut track = MidiTrack::new();
+    for event in events {
+        track.wait(event.delta.into());
+        match event.status&0xF0 {
+            0xC0 | 0xD0 => track.event(&[event.status,event.data[0]]),
+            _ => track.event(&[event.status,event.data[0],event.data[1]]),
+        }
+    }
+    track
+}
+
+/// Converts a sound resource into a format 1 Standard MIDI File, a tempo track followed by one track per voice
+/// (or a single track of events for IIgs midi). IIgs samples have no notes to export.
+pub fn export_midi(sound:&SoundResource) -> Result<Vec<u8>, String> {
+
+    let tracks:Vec<MidiTrack> = match sound.get_format() {
+        SoundFormat::PcJr => sound.get_voices().iter().enumerate().map(|(index,voice)| {
+            if index==SOUND_NOISE_VOICE {
+                noise_track(voice)
+            } else {
+                tone_track(voice,index as u8)
+            }
+        }).collect(),
+        SoundFormat::IIgsMidi => vec![iigs_track(sound.get_midi_events())],
+        SoundFormat::IIgsSample => return Err("IIgs sampled sounds can not be exported as midi".to_string()),
+    };
+
+    let mut out:Vec<u8> = Vec::new();
+
+    out.extend_from_slice(b"MThd");
+    out.extend_from_slice(&6u32.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&((tracks.len()+1) as u16).to_be_bytes());
+    out.extend_from_slice(&MIDI_TICKS_PER_QUARTER.to_be_bytes());
+
+    let mut tempo = MidiTrack::new();
+    let t = MIDI_MICROSECONDS_PER_QUARTER.to_be_bytes();
+    tempo.event(&[0xFF,0x51,0x03,t[1],t[2],t[3]]);
+    tempo.end(&mut out);
+
+    for track in tracks {
+        track.end(&mut out);
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,764 @@
+use crate::{SoundResource, SoundFormat, SoundNote, IIgsMidiEvent, IIgsInstrument, IIgsInstrumentBank, IIgsEnvelopeSegment, IIgsWave, wave_for_key,
+    SOUND_VOICES, SOUND_NOISE_VOICE, SOUND_ATTENUATION_OFF, IIGS_ENVELOPE_SEGMENTS};
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+    use std::rc::Rc;
+    use crate::{SOUND_IIGS_SAMPLE_RATE, IIGS_SAMPLE_REFERENCE_KEY};
+
+    fn single_note(duration:u16,divider:u16,attenuation:u8) -> SoundResource {
+        let mut data = vec![8u8,0,15,0,17,0,19,0];
+        data.extend_from_slice(&[duration as u8,(duration>>8) as u8,((divider>>4)&0x3F) as u8,0x80|((divider&0xF) as u8),0x90|attenuation]);
+        data.extend_from_slice(&[0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF]);
+        SoundResource::new_from_slice(&data).unwrap()
+    }
+
+    #[test]
+    fn render_length_matches_duration() {
+        let sound = single_note(60,0x100,0);
+        let pcm = render_pcm(&sound,6000,2);
+        assert_eq!(pcm.len(),6000*2);
+    }
+
+    #[test]
+    fn render_is_deterministic() {
+        let sound = single_note(30,0x0FE,3);
+        assert_eq!(render_pcm(&sound,22050,1),render_pcm(&sound,22050,1));
+    }
+
+    #[test]
+    fn render_channels_interleaved() {
+        let sound = single_note(6,0x0FE,0);
+        let pcm = render_pcm(&sound,22050,2);
+        assert!(pcm.chunks_exact(2).all(|f| f[0]==f[1]));
+        assert!(pcm.iter().any(|s| *s!=0));
+    }
+
+    #[test]
+    fn render_silent_note() {
+        let sound = single_note(6,0x0FE,SOUND_ATTENUATION_OFF);
+        assert!(render_pcm(&sound,22050,1).iter().all(|s| *s==0));
+    }
+
+    #[test]
+    fn render_tick_matches_render_pcm() {
+        let sound = single_note(3,0x0FE,0);
+        let mut renderer = SoundRenderer::new(22050);
+        let mut pcm:Vec<i16> = Vec::new();
+        while !renderer.is_finished() {
+            renderer.render_tick(&sound,&mut pcm,1);
+        }
+        assert_eq!(renderer.get_ticks(),3);
+        assert_eq!(pcm,render_pcm(&sound,22050,1));
+    }
+
+    #[test]
+    fn render_disabled_voices() {
+        let sound = single_note(6,0x0FE,0);
+        let mut renderer = SoundRenderer::new(22050);
+        renderer.set_voice_count(0);
+        let mut out = [0i16;64];
+        assert_eq!(renderer.render(&sound,&mut out,1),0);
+        assert!(renderer.is_finished());
+    }
+
+    fn iigs_sample(pitch:u8,samples:&[u8]) -> SoundResource {
+        // Instrument with an attack reaching full volume in 1/100th second, then held
+        let mut data = vec![1u8,0,pitch,0,0xFF,0,32,0,samples.len() as u8,(samples.len()>>8) as u8];
+        data.extend_from_slice(&[0x7F,0xFF,0xFF]);
+        data.extend_from_slice(&[0u8;(IIGS_ENVELOPE_SEGMENTS-1)*3]);
+        data.extend_from_slice(&[1,0,0,0,0,0,0,0]);
+        data.extend_from_slice(samples);
+        SoundResource::new_from_slice(&data).unwrap()
+    }
+
+    #[test]
+    fn render_iigs_sample() {
+        let samples:Vec<u8> = (0..120).map(|i| if i&1==0 {0xC0} else {0x40}).collect();
+        let sound = iigs_sample(IIGS_SAMPLE_REFERENCE_KEY,&samples);
+        assert_eq!(sound.get_duration(),1);
+        let pcm = render_pcm(&sound,SOUND_IIGS_SAMPLE_RATE,1);
+        assert_eq!(pcm.len(),(SOUND_IIGS_SAMPLE_RATE/SOUND_TICKS_PER_SECOND) as usize+1);
+        assert!(pcm[0]>0 && pcm[1]<0);
+
+        // The envelope's attack takes 80 samples at 8kHz, then holds at full volume
+        assert!(pcm[0]< -pcm[1] && -pcm[1]<pcm[2]);
+        assert_eq!(pcm[100],-pcm[101]);
+    }
+
+    #[test]
+    fn render_iigs_sample_pitch() {
+        let samples:Vec<u8> = vec![0xC0;160];
+        let native = iigs_sample(IIGS_SAMPLE_REFERENCE_KEY,&samples);
+        let octave_up = iigs_sample(IIGS_SAMPLE_REFERENCE_KEY+12,&samples);
+        assert_eq!(native.get_duration(),2);
+        assert_eq!(octave_up.get_duration(),1);
+        let native = render_pcm(&native,SOUND_IIGS_SAMPLE_RATE,1);
+        let octave_up = render_pcm(&octave_up,SOUND_IIGS_SAMPLE_RATE,1);
+        assert_eq!(native.iter().filter(|s| **s!=0).count(),160);
+        assert_eq!(octave_up.iter().filter(|s| **s!=0).count(),80);
+    }
+
+    fn midi(events:&[u8]) -> SoundResource {
+        let mut data = vec![2u8,0];
+        data.extend_from_slice(events);
+        data.push(0xFC);
+        SoundResource::new_from_slice(&data).unwrap()
+    }
+
+    fn render_ticks(renderer:&mut SoundRenderer,sound:&SoundResource,ticks:usize) -> Vec<i16> {
+        let mut pcm:Vec<i16> = Vec::new();
+        for _ in 0..ticks {
+            renderer.render_tick(sound,&mut pcm,1);
+        }
+        pcm
+    }
+
+    fn bank_instrument(wave:u8,release:u16) -> IIgsInstrument {
+        let mut envelope = [IIgsEnvelopeSegment { breakpoint:0, increment:0 };IIGS_ENVELOPE_SEGMENTS];
+        envelope[0] = IIgsEnvelopeSegment { breakpoint:0x7F, increment:0xFFFF };
+        envelope[1] = IIgsEnvelopeSegment { breakpoint:0, increment:release };
+        let wave = IIgsWave { top_key:0x7F, address:wave, size:0, mode:0, relative_pitch:0 };
+        IIgsInstrument { envelope, release_segment:1, priority_increment:0, pitch_bend_range:12, vibrato_depth:0, vibrato_speed:0, waves_a:vec![wave], waves_b:Vec::new() }
+    }
+
+    fn bank() -> IIgsInstrumentBank {
+        // Program 0 is a square wave, program 1 a quieter one with a slow release, both one page of wave memory
+        let mut waves:Vec<u8> = (0..256).map(|i| if i<128 {0xFF} else {0x01}).collect();
+        waves.extend((0..256).map(|i| if i<128 {0xA0} else {0x60}));
+        let mut bank = IIgsInstrumentBank::new();
+        let waves:Rc<[u8]> = waves.into();
+        bank.add(bank_instrument(0,0xFFFF),waves.clone());
+        bank.add(bank_instrument(1,0x0400),waves);
+        bank
+    }
+
+    #[test]
+    fn render_iigs_midi_programs() {
+        let sound = midi(&[0,0xC0,1, 0,0x90,69,127, 2,0x80,69,0, 0,0xC0,0, 0,0x90,69,127, 2,0x80,69,0]);
+        let mut renderer = SoundRenderer::new(22050);
+        renderer.set_instrument_bank(bank());
+        let quiet = render_ticks(&mut renderer,&sound,2);
+        let loud = render_ticks(&mut renderer,&sound,2);
+        let peak = |pcm:&[i16]| pcm.iter().map(|s| (*s as i32).abs()).max().unwrap();
+        assert!(peak(&loud)>3*peak(&quiet));
+
+        // Without a bank the built in instrument plays both programs
+        let first = midi(&[0,0xC0,1, 0,0x90,69,127, 2,0x80,69,0]);
+        let second = midi(&[0,0xC0,0, 0,0x90,69,127, 2,0x80,69,0]);
+        assert_eq!(render_pcm(&first,22050,1),render_pcm(&second,22050,1));
+    }
+
+    #[test]
+    fn render_iigs_midi_release() {
+        // Program 1 releases slowly, so the key is still heard after its note off
+        let sound = midi(&[0,0xC0,1, 0,0x90,69,127, 1,0x80,69,0, 10,0xC0,1]);
+        let mut renderer = SoundRenderer::new(22050);
+        renderer.set_instrument_bank(bank());
+        let pcm = render_ticks(&mut renderer,&sound,3);
+        assert!(pcm[pcm.len()*2/3..].iter().any(|s| *s!=0));
+
+        // Program 0 releases in 1/100th second
+        let sound = midi(&[0,0x90,69,127, 1,0x80,69,0, 10,0xC0,0]);
+        let mut renderer = SoundRenderer::new(22050);
+        renderer.set_instrument_bank(bank());
+        let pcm = render_ticks(&mut renderer,&sound,3);
+        assert!(pcm[pcm.len()*2/3..].iter().all(|s| *s==0));
+    }
+
+    #[test]
+    fn render_iigs_midi_pitch_bend() {
+        // Bending up a whole octave (the instrument's range) doubles the rate the square wave flips
+        let flips = |pcm:&[i16]| pcm.windows(2).filter(|w| (w[0]>0)!=(w[1]>0)).count();
+        let plain = midi(&[0,0x90,57,127, 6,0x80,57,0]);
+        let bent = midi(&[0,0xE0,0x7F,0x7F, 0,0x90,57,127, 6,0x80,57,0]);
+        let mut renderer = SoundRenderer::new(22050);
+        renderer.set_instrument_bank(bank());
+        let plain = flips(&render_ticks(&mut renderer,&plain,6));
+        let mut renderer = SoundRenderer::new(22050);
+        renderer.set_instrument_bank(bank());
+        let bent = flips(&render_ticks(&mut renderer,&bent,6));
+        assert!(bent>plain*19/10 && bent<plain*21/10,"{} {}",plain,bent);
+    }
+
+    #[test]
+    fn render_iigs_midi_follows_deltas() {
+        let data = [2u8,0, 2,0x90,69,127, 3,0x80,69,0, 0xFC];
+        let sound = SoundResource::new_from_slice(&data).unwrap();
+        let mut renderer = SoundRenderer::new(22050);
+        let mut pcm:Vec<i16> = Vec::new();
+        renderer.render_tick(&sound,&mut pcm,1);
+        renderer.render_tick(&sound,&mut pcm,1);
+        assert!(pcm.iter().all(|s| *s==0));
+        renderer.render_tick(&sound,&mut pcm,1);
+        assert!(pcm.iter().any(|s| *s!=0));
+        while !renderer.is_finished() {
+            renderer.render_tick(&sound,&mut pcm,1);
+        }
+        assert_eq!(renderer.get_ticks(),sound.get_duration());
+    }
+}
+
+/// Input clock of the PCjr/Tandy SN76496
+pub const SN76496_CLOCK:u32 = 3579545;
+/// Sound resource note durations are counted in 1/60th of a second
+pub const SOUND_TICKS_PER_SECOND:u32 = 60;
+
+const TONE_CHANNELS:usize = 3;
+const NOISE_INITIAL:u16 = 0x4000;
+
+// Output level per attenuation step (2dB each), scaled so four channels at full volume fit in an i16
+const VOLUME_TABLE:[i16;16] = [8191,6506,5168,4105,3261,2590,2057,1634,1298,1031,819,651,517,411,326,0];
+
+/// Software emulation of the SN76496 - 3 square wave tone channels and a noise channel
+pub struct Sn76496 {
+    sample_rate:u32,
+    clock_remainder:u32,
+    tone_period:[u16;TONE_CHANNELS],
+    tone_counter:[i32;TONE_CHANNELS],
+    tone_output:[bool;TONE_CHANNELS],
+    noise_control:u8,
+    noise_counter:i32,
+    noise_toggle:bool,
+    noise_shift:u16,
+    attenuation:[u8;SOUND_VOICES],
+}
+
+impl Sn76496 {
+    pub fn new(sample_rate:u32) -> Sn76496 {
+        let mut chip = Sn76496 {
+            sample_rate,
+            clock_remainder:0,
+            tone_period:[0;TONE_CHANNELS],
+            tone_counter:[0;TONE_CHANNELS],
+            tone_output:[false;TONE_CHANNELS],
+            noise_control:0,
+            noise_counter:0,
+            noise_toggle:false,
+            noise_shift:NOISE_INITIAL,
+            attenuation:[SOUND_ATTENUATION_OFF;SOUND_VOICES],
+        };
+        chip.reset();
+        chip
+    }
+
+    /// Silences all channels and returns the chip to its power on state
+    pub fn reset(&mut self) {
+        self.clock_remainder=0;
+        self.tone_period=[0x400;TONE_CHANNELS];
+        self.tone_counter=[0;TONE_CHANNELS];
+        self.tone_output=[false;TONE_CHANNELS];
+        self.noise_control=0;
+        self.noise_counter=0;
+        self.noise_toggle=false;
+        self.noise_shift=NOISE_INITIAL;
+        self.attenuation=[SOUND_ATTENUATION_OFF;SOUND_VOICES];
+    }
+
+    pub fn set_tone(&mut self,channel:usize,divider:u16,attenuation:u8) {
+        // A divider of 0 behaves as the largest possible period
+        self.tone_period[channel] = if divider&0x3FF == 0 {0x400} else {divider&0x3FF};
+        self.attenuation[channel] = attenuation&0xF;
+    }
+
+    pub fn set_noise(&mut self,control:u8,attenuation:u8) {
+        // Writing the noise control register resets the shift register
+        self.noise_control = control&7;
+        self.noise_shift = NOISE_INITIAL;
+        self.attenuation[SOUND_NOISE_VOICE] = attenuation&0xF;
+    }
+
+    pub fn set_attenuation(&mut self,channel:usize,attenuation:u8) {
+        self.attenuation[channel] = attenuation&0xF;
+    }
+
+    fn noise_period(&self) -> i32 {
+        match self.noise_control&3 {
+            3 => self.tone_period[TONE_CHANNELS-1] as i32,
+            n => 16<<n,
+        }
+    }
+
+    fn clock_noise(&mut self) {
+        let feedback = if self.noise_control&4 == 4 {
+            (self.noise_shift ^ (self.noise_shift>>1)) & 1
+        } else {
+            self.noise_shift & 1
+        };
+        self.noise_shift = (self.noise_shift>>1) | (feedback<<14);
+    }
+
+    /// Advances the chip by one output sample and returns the mixed level
+    pub fn next_sample(&mut self) -> i16 {
+
+        // Counters run at clock/16, work out how many of those elapse this sample
+        self.clock_remainder += SN76496_CLOCK;
+        let divisor = self.sample_rate*16;
+        let steps = (self.clock_remainder / divisor) as i32;
+        self.clock_remainder %= divisor;
+
+        let mut mix:i16 = 0;
+        for c in 0..TONE_CHANNELS {
+            self.tone_counter[c]-=steps;
+            while self.tone_counter[c] <= 0 {
+                self.tone_counter[c]+=self.tone_period[c] as i32;
+                self.tone_output[c] = !self.tone_output[c];
+            }
+            let level = VOLUME_TABLE[self.attenuation[c] as usize];
+            mix += if self.tone_output[c] {level} else {-level};
+        }
+
+        self.noise_counter-=steps;
+        while self.noise_counter <= 0 {
+            self.noise_counter+=self.noise_period();
+            self.noise_toggle = !self.noise_toggle;
+            if self.noise_toggle {
+                self.clock_noise();
+            }
+        }
+        let level = VOLUME_TABLE[self.attenuation[SOUND_NOISE_VOICE] as usize];
+        mix += if self.noise_shift&1 == 1 {level} else {-level};
+
+        mix
+    }
+}
+
+const WAVETABLE_FRACTION_BITS:u32 = 16;
+const WAVETABLE_VOICES:usize = 16;
+const MIDI_CHANNELS:usize = 16;
+const MIDI_KEY_A440:f64 = 69.0;
+const MIDI_PITCH_BEND_CENTRE:i32 = 0x2000;
+const ENVELOPE_STEPS_PER_SECOND:f64 = 100.0;
+
+// One cycle of a ramp, the wave of the built in instrument used for programs missing from the bank
+const WAVEFORM_RAMP:[u8;32] = [
+    0x80,0x88,0x90,0x98,0xA0,0xA8,0xB0,0xB8,0xC0,0xC8,0xD0,0xD8,0xE0,0xE8,0xF0,0xF8,
+    0x01,0x08,0x10,0x18,0x20,0x28,0x30,0x38,0x40,0x48,0x50,0x58,0x60,0x68,0x70,0x78];
+
+/// Instrument used when a program is missing from the bank, a ramp wave with a fast attack and release
+fn builtin_instrument() -> IIgsInstrument {
+    let mut envelope = [IIgsEnvelopeSegment { breakpoint:0, increment:0 };IIGS_ENVELOPE_SEGMENTS];
+    envelope[0] = IIgsEnvelopeSegment { breakpoint:0x7F, increment:0xFFFF };
+    envelope[1] = IIgsEnvelopeSegment { breakpoint:0, increment:0x2000 };
+    let wave = IIgsWave { top_key:0x7F, address:0, size:0, mode:0, relative_pitch:0 };
+    IIgsInstrument { envelope, release_segment:1, priority_increment:0, pitch_bend_range:2, vibrato_depth:0, vibrato_speed:0, waves_a:vec![wave], waves_b:Vec::new() }
+}
+
+/// Volume envelope of a playing key, following an instrument's segments
+#[derive(Clone,Copy)]
+struct Envelope {
+    segment:usize,
+    level:f64,
+    released:bool,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope { segment:0, level:0.0, released:false }
+    }
+
+    fn release(&mut self,release_segment:u8) {
+        self.released=true;
+        self.segment = self.segment.max(release_segment as usize);
+    }
+
+    fn is_finished(&self) -> bool {
+        self.segment>=IIGS_ENVELOPE_SEGMENTS
+    }
+
+    /// Moves by one output sample, holding at the release segment until the key is released
+    fn advance(&mut self,instrument:&IIgsInstrument,sample_rate:u32) {
+        if self.segment==instrument.release_segment as usize && !self.released {
+            return;
+        }
+        let segment = match instrument.envelope.get(self.segment) {
+            Some(s) => s,
+            None => { self.segment=IIGS_ENVELOPE_SEGMENTS; return; },
+        };
+        let target = ((segment.breakpoint&0x7F) as f64)/127.0;
+        let step = (segment.increment as f64)/65535.0*ENVELOPE_STEPS_PER_SECOND/(sample_rate as f64);
+        self.level = if self.level<target {(self.level+step).min(target)} else {(self.level-step).max(target)};
+        if self.level==target || step==0.0 {
+            self.level=target;
+            self.segment+=1;
+        }
+    }
+}
+
+/// One wave being read by an oscillator, a range of the instrument's wave memory
+#[derive(Clone,Copy)]
+struct Oscillator {
+    offset:usize,
+    size:usize,
+    tune:f64,
+    phase:u64,
+    step:u64,
+}
+
+#[derive(Clone)]
+struct WavetableVoice {
+    channel:u8,
+    key:u8,
+    velocity:u8,
+    program:Option<u8>,     // None plays the built in instrument
+    oscillators:Vec<Oscillator>,
+    envelope:Envelope,
+}
+
+/// Plays IIgs midi - a key selects a wave from each of the instrument's two wave lists, read at the key's pitch
+/// (each wave is one cycle), and shaped by the instrument's envelope. Program changes pick the instrument from
+/// the bank and pitch bends move playing keys by up to the instrument's bend range.
+pub struct Wavetable {
+    sample_rate:u32,
+    bank:IIgsInstrumentBank,
+    builtin:IIgsInstrument,
+    programs:[u8;MIDI_CHANNELS],
+    bends:[i32;MIDI_CHANNELS],
+    voices:Vec<WavetableVoice>,
+}
+
+impl Wavetable {
+    pub fn new(sample_rate:u32) -> Wavetable {
+        Wavetable {
+            sample_rate,
+            bank:IIgsInstrumentBank::new(),
+            builtin:builtin_instrument(),
+            programs:[0;MIDI_CHANNELS],
+            bends:[0;MIDI_CHANNELS],
+            voices:Vec::new(),
+        }
+    }
+
+    pub fn set_instrument_bank(&mut self,bank:IIgsInstrumentBank) {
+        self.bank=bank;
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.programs=[0;MIDI_CHANNELS];
+        self.bends=[0;MIDI_CHANNELS];
+        self.voices.clear();
+    }
+
+    fn instrument(&self,program:Option<u8>) -> (&IIgsInstrument,&[u8]) {
+        match program.and_then(|p| self.bank.get(p)) {
+            Some((instrument,waves)) => (instrument,waves),
+            None => (&self.builtin,&WAVEFORM_RAMP),
+        }
+    }
+
+    /// Phase step of an oscillator for a voice's key, tune and channel bend
+    fn oscillator_step(&self,voice:&WavetableVoice,oscillator:&Oscillator) -> u64 {
+        let (instrument,_) = self.instrument(voice.program);
+        let bend = (self.bends[voice.channel as usize] as f64)/(MIDI_PITCH_BEND_CENTRE as f64)*(instrument.pitch_bend_range as f64);
+        let key = (voice.key as f64)+oscillator.tune+bend;
+        let frequency = 440.0*2f64.powf((key-MIDI_KEY_A440)/12.0);
+        (frequency*(oscillator.size as f64)*((1u64<<WAVETABLE_FRACTION_BITS) as f64)/(self.sample_rate as f64)) as u64
+    }
+
+    fn retune(&mut self,channel:u8) {
+        for v in 0..self.voices.len() {
+            if self.voices[v].channel!=channel {
+                continue;
+            }
+            for o in 0..self.voices[v].oscillators.len() {
+                let step = self.oscillator_step(&self.voices[v],&self.voices[v].oscillators[o]);
+                self.voices[v].oscillators[o].step=step;
+            }
+        }
+    }
+
+    fn key_on(&mut self,channel:u8,key:u8,velocity:u8) {
+        self.key_off(channel,key);
+        let program = Some(self.programs[channel as usize]).filter(|p| self.bank.get(*p).is_some());
+        let (instrument,waves) = self.instrument(program);
+        let oscillators:Vec<Oscillator> = [&instrument.waves_a,&instrument.waves_b].iter()
+            .filter_map(|list| wave_for_key(list,key))
+            .map(|w| {
+                let offset = if program.is_some() {w.get_offset()} else {0};
+                let size = if program.is_some() {w.get_size()} else {waves.len()};
+                Oscillator { offset, size:size.min(waves.len().saturating_sub(offset)), tune:w.get_tune(), phase:0, step:0 }
+            })
+            .filter(|o| o.size>0)
+            .collect();
+        let mut voice = WavetableVoice { channel, key, velocity:velocity.min(127), program, oscillators, envelope:Envelope::new() };
+        for o in 0..voice.oscillators.len() {
+            voice.oscillators[o].step = self.oscillator_step(&voice,&voice.oscillators[o]);
+        }
+
+        // Out of voices, the oldest released key (or failing that the oldest key) makes way
+        if self.voices.len()>=WAVETABLE_VOICES {
+            let oldest = self.voices.iter().position(|v| v.envelope.released).unwrap_or(0);
+            self.voices.remove(oldest);
+        }
+        self.voices.push(voice);
+    }
+
+    fn key_off(&mut self,channel:u8,key:u8) {
+        for v in 0..self.voices.len() {
+            if self.voices[v].channel==channel && self.voices[v].key==key && !self.voices[v].envelope.released {
+                let release_segment = self.instrument(self.voices[v].program).0.release_segment;
+                self.voices[v].envelope.release(release_segment);
+            }
+        }
+    }
+
+    pub fn play_event(&mut self,event:&IIgsMidiEvent) {
+        let channel = event.get_channel();
+        match event.status&0xF0 {
+            _ if event.is_note_on() => self.key_on(channel,event.data[0],event.data[1]),
+            _ if event.is_note_off() => self.key_off(channel,event.data[0]),
+            0xC0 => self.programs[channel as usize] = event.data[0],
+            0xE0 => {
+                self.bends[channel as usize] = (((event.data[1] as i32)<<7) | (event.data[0] as i32))-MIDI_PITCH_BEND_CENTRE;
+                self.retune(channel);
+            },
+            _ => {},
+        }
+    }
+
+    pub fn next_sample(&mut self) -> i16 {
+        let mut mix:i32 = 0;
+        for v in 0..self.voices.len() {
+            let (instrument,waves) = match self.voices[v].program.and_then(|p| self.bank.get(p)) {
+                Some((instrument,waves)) => (instrument,&waves[..]),
+                None => (&self.builtin,&WAVEFORM_RAMP[..]),
+            };
+            let voice = &mut self.voices[v];
+            voice.envelope.advance(instrument,self.sample_rate);
+
+            let mut level:i32 = 0;
+            for oscillator in voice.oscillators.iter_mut() {
+                let index = oscillator.offset+((oscillator.phase>>WAVETABLE_FRACTION_BITS) as usize % oscillator.size);
+                // A zero byte stops the DOC oscillator, treat it as the centre line
+                level += match waves[index] {
+                    0 => 0,
+                    s => (s as i32)-0x80,
+                };
+                oscillator.phase = (oscillator.phase+oscillator.step) % ((oscillator.size as u64)<<WAVETABLE_FRACTION_BITS);
+            }
+            let oscillators = voice.oscillators.len().max(1) as f64;
+            let amplitude = voice.envelope.level*(voice.velocity as f64)/127.0;
+            mix += ((level as f64)/oscillators*amplitude*(VOLUME_TABLE[0] as f64)/128.0) as i32;
+        }
+        self.voices.retain(|v| !v.envelope.is_finished());
+        mix.clamp(i16::MIN as i32,i16::MAX as i32) as i16
+    }
+}
+
+#[derive(Clone,Copy)]
+struct VoicePosition {
+    next_note:usize,
+    remaining:u32,
+    done:bool,
+}
+
+/// Plays a sound resource a tick (1/60th second) at a time, PC sounds through an Sn76496,
+/// IIgs midi through a Wavetable and IIgs samples resampled for their pitch with their instrument's envelope
+pub struct SoundRenderer {
+    chip:Sn76496,
+    wavetable:Wavetable,
+    sample_position:u64,
+    sample_envelope:Envelope,
+    next_event:usize,
+    event_wait:u32,
+    voices:[VoicePosition;SOUND_VOICES],
+    voice_count:usize,
+    sample_rate:u32,
+    tick_remainder:u32,
+    tick_pending:bool,
+    ticks:u32,
+    finished:bool,
+}
+
+impl SoundRenderer {
+    pub fn new(sample_rate:u32) -> SoundRenderer {
+        SoundRenderer {
+            chip:Sn76496::new(sample_rate),
+            wavetable:Wavetable::new(sample_rate),
+            sample_position:0,
+            sample_envelope:Envelope::new(),
+            next_event:0,
+            event_wait:0,
+            voices:[VoicePosition { next_note:0, remaining:0, done:false };SOUND_VOICES],
+            voice_count:SOUND_VOICES,
+            sample_rate,
+            tick_remainder:0,
+            tick_pending:true,
+            ticks:0,
+            finished:false,
+        }
+    }
+
+    /// Restricts playback to the first n voices (a PC speaker only plays voice 0)
+    pub fn set_voice_count(&mut self,n:usize) {
+        self.voice_count = n.min(SOUND_VOICES);
+    }
+
+    pub fn get_sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Number of whole ticks played so far
+    pub fn get_ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Instruments for the program changes of IIgs midi sounds, see IIgsInstrumentBank
+    pub fn set_instrument_bank(&mut self,bank:IIgsInstrumentBank) {
+        self.wavetable.set_instrument_bank(bank);
+    }
+
+    fn play_note(&mut self,v:usize,note:&SoundNote) {
+        if v==SOUND_NOISE_VOICE {
+            self.chip.set_noise((note.frequency_divider&7) as u8,note.attenuation);
+        } else {
+            self.chip.set_tone(v,note.frequency_divider,note.attenuation);
+        }
+    }
+
+    fn load_notes(&mut self,sound:&SoundResource) {
+        let voices = sound.get_voices();
+        for v in 0..SOUND_VOICES {
+            if self.voices[v].done || self.voices[v].remaining!=0 {
+                continue;
+            }
+            if v>=self.voice_count || v>=voices.len() {
+                self.voices[v].done=true;
+                continue;
+            }
+            let notes = voices[v].get_notes();
+            while self.voices[v].remaining==0 && self.voices[v].next_note<notes.len() {
+                let note = &notes[self.voices[v].next_note];
+                self.voices[v].next_note+=1;
+                self.voices[v].remaining=note.duration.into();
+                self.play_note(v,note);
+            }
+            if self.voices[v].remaining==0 {
+                self.voices[v].done=true;
+                self.chip.set_attenuation(v,SOUND_ATTENUATION_OFF);
+            }
+        }
+        self.finished = self.voices.iter().all(|p| p.done);
+    }
+
+    fn load_events(&mut self,sound:&SoundResource) {
+        let events = sound.get_midi_events();
+        if self.ticks==0 && self.next_event==0 {
+            self.event_wait = events.first().map(|e| e.delta as u32).unwrap_or_default();
+        }
+        while self.event_wait==0 && self.next_event<events.len() {
+            self.wavetable.play_event(&events[self.next_event]);
+            self.next_event+=1;
+            self.event_wait = events.get(self.next_event).map(|e| e.delta as u32).unwrap_or_default();
+        }
+        self.finished = self.next_event>=events.len();
+    }
+
+    fn load(&mut self,sound:&SoundResource) {
+        match sound.get_format() {
+            SoundFormat::PcJr => self.load_notes(sound),
+            SoundFormat::IIgsMidi => self.load_events(sound),
+            SoundFormat::IIgsSample => self.finished = self.ticks>=sound.get_duration(),
+        }
+    }
+
+    fn next_sample(&mut self,sound:&SoundResource) -> i16 {
+        match sound.get_format() {
+            SoundFormat::PcJr => self.chip.next_sample(),
+            SoundFormat::IIgsMidi => self.wavetable.next_sample(),
+            SoundFormat::IIgsSample => {
+                let sample = sound.get_sample().unwrap();
+                let index = (self.sample_position>>WAVETABLE_FRACTION_BITS) as usize;
+                self.sample_position += (sample.get_sample_rate()*((1u64<<WAVETABLE_FRACTION_BITS) as f64)/(self.sample_rate as f64)) as u64;
+                self.sample_envelope.advance(sample.get_instrument(),self.sample_rate);
+                match sample.get_data().get(index) {
+                    // Unsigned 8 bit centred on 0x80, scaled to the same peak as four full volume voices
+                    Some(s) => {
+                        let level = ((*s as f64)-128.0)*(sample.get_volume() as f64)/255.0*self.sample_envelope.level;
+                        (level*(VOLUME_TABLE[0] as f64)*(SOUND_VOICES as f64)/128.0) as i16
+                    },
+                    None => 0,
+                }
+            },
+        }
+    }
+
+    fn end_tick(&mut self) {
+        for position in self.voices.iter_mut() {
+            position.remaining = position.remaining.saturating_sub(1);
+        }
+        self.event_wait = self.event_wait.saturating_sub(1);
+        self.ticks+=1;
+        self.tick_pending=true;
+    }
+
+    /// Advances by a whole tick without producing any samples (for when audio output is unavailable)
+    pub fn skip_tick(&mut self,sound:&SoundResource) {
+        if self.tick_pending {
+            self.load(sound);
+            self.tick_pending=false;
+        }
+        if !self.finished {
+            self.end_tick();
+            self.load(sound);
+            self.tick_pending=false;
+        }
+    }
+
+    /// Renders exactly one tick worth of interleaved frames onto the end of out
+    pub fn render_tick(&mut self,sound:&SoundResource,out:&mut Vec<i16>,channels:usize) {
+        let channels = channels.max(1);
+        let tick = self.ticks;
+        let mut frame = vec![0i16;channels];
+        while self.ticks==tick && self.render(sound,&mut frame,channels)==1 {
+            out.extend_from_slice(&frame);
+        }
+        if self.tick_pending {
+            self.load(sound);
+            self.tick_pending=false;
+        }
+    }
+
+    /// Fills out with interleaved frames of `channels` samples, returns the number of frames written
+    /// (less than requested once the sound has finished)
+    pub fn render(&mut self,sound:&SoundResource,out:&mut [i16],channels:usize) -> usize {
+        let mut frames=0;
+        for frame in out.chunks_exact_mut(channels.max(1)) {
+            if self.tick_pending {
+                self.load(sound);
+                self.tick_pending=false;
+            }
+            if self.finished {
+                break;
+            }
+            let sample = self.next_sample(sound);
+            frame.fill(sample);
+            frames+=1;
+
+            self.tick_remainder+=SOUND_TICKS_PER_SECOND;
+            if self.tick_remainder>=self.sample_rate {
+                self.tick_remainder-=self.sample_rate;
+                self.end_tick();
+            }
+        }
+        frames
+    }
+}
+
+/// Renders an entire sound resource to interleaved PCM
+pub fn render_pcm(sound:&SoundResource,sample_rate:u32,channels:usize) -> Vec<i16> {
+    let channels = channels.max(1);
+    let frames = (sound.get_duration() as u64)*(sample_rate as u64)/(SOUND_TICKS_PER_SECOND as u64);
+    let mut pcm = vec![0i16;(frames as usize+1)*channels];
+    let mut renderer = SoundRenderer::new(sample_rate);
+    let written = renderer.render(sound,&mut pcm,channels);
+    pcm.truncate(written*channels);
+    pcm
+}
@@ -1,5 +1,128 @@
 use dir_resource::ResourceDirectoryEntry;
-use volume::{Volume, VolumeCache};
+use volume::ResourceStore;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Loops 0 and 1 share their cel (loop 1 is the mirror), loop 2 has its own, description at the end
+    const VIEW:[u8;33] = [0x01,0x01,0x03,0x1E,0x00, 0x0B,0x00,0x0B,0x00,0x16,0x00,
+        0x01,0x03,0x00, 0x03,0x02,0x85,0x12,0x00,0x51,0x21,0x00,
+        0x01,0x03,0x00, 0x02,0x01,0x0F,0x32,0x00,
+        b'H',b'i',0x00];
+
+    // Laid out as Sierra's views are: the description ahead of the loops, mirrored walking loops (with the second
+    // pair's data owned by loop 3) sharing their data ahead of the description, and empty views
+    const VIEW_DESCRIPTION_FIRST:[u8;33] = [
+        0x01,0x01,0x01,0x07,0x00,0x0E,0x00,0x41,0x20,0x72,0x6F,0x63,0x6B,0x00,0x02,0x05,
+        0x00,0x0D,0x00,0x03,0x02,0x0F,0x12,0x00,0xF1,0x22,0x00,0x14,0x01,0x0F,0x4F,0x43,
+        0x00,
+    ];
+    const VIEW_MIRRORED_LOOPS:[u8;113] = [
+        0x01,0x00,0x04,0x65,0x00,0x0D,0x00,0x0D,0x00,0x30,0x00,0x30,0x00,0x02,0x05,0x00,
+        0x14,0x00,0x04,0x03,0x80,0x01,0x62,0x00,0x51,0x61,0x51,0x61,0x00,0x61,0x01,0x61,
+        0x00,0x04,0x03,0x80,0x52,0x61,0x00,0x01,0x61,0x51,0x61,0x00,0x61,0x52,0x61,0x00,
+        0x03,0x07,0x00,0x15,0x00,0x25,0x00,0x04,0x03,0xB0,0x02,0x61,0x00,0x61,0x51,0x61,
+        0x00,0x61,0x02,0x61,0x00,0x04,0x03,0xB0,0x01,0x51,0x01,0x51,0x00,0x51,0x62,0x51,
+        0x00,0x61,0x52,0x61,0x00,0x04,0x03,0xB0,0x61,0x51,0x01,0x51,0x00,0x03,0x51,0x00,
+        0x01,0x51,0x61,0x51,0x00,0x57,0x61,0x6C,0x6B,0x69,0x6E,0x67,0x20,0x6D,0x61,0x6E,
+        0x00,
+    ];
+
+    const VIEW_EMPTY:[u8;5] = [0x01,0x01,0x00,0x00,0x00];
+    const VIEW_PLACEHOLDER:[u8;3] = [0x01,0x01,0x00];
+
+    #[test]
+    fn view_round_trip() {
+        let view = ViewResource::new_from_slice(&VIEW).unwrap();
+        assert_eq!(view.get_description(),"Hi");
+        assert_eq!(view.get_loops()[0].get_mirror_of(),None);
+        assert_eq!(view.get_loops()[1].get_mirror_of(),Some(0));
+        assert_eq!(view.get_loops()[0].get_cels()[0].get_data(),&vec![1,1,5,5,2,5]);
+        assert_eq!(view.get_cel(1,0).unwrap().get_data(),&vec![5,1,1,5,2,5]);
+        assert!(view.get_cel(3,0).is_none());
+        assert_eq!(view.write().unwrap(),VIEW.to_vec());
+    }
+
+    #[test]
+    fn view_round_trip_shared_loops() {
+        // As VIEW, but the cel flags lack the mirror bit so loops 0 and 1 just share the cel
+        let mut data = VIEW;
+        data[16] = 0x05;
+        let view = ViewResource::new_from_slice(&data).unwrap();
+        assert_eq!(view.get_loops()[0].get_mirror_of(),None);
+        assert_eq!(view.get_loops()[1].get_mirror_of(),None);
+        assert_eq!(view.write().unwrap(),data.to_vec());
+    }
+
+    #[test]
+    fn view_shared_loop_not_flipped() {
+        let mut data = VIEW;
+        data[16] = 0x05;
+        let view = ViewResource::new_from_slice(&data).unwrap();
+        assert_eq!(view.get_cel(0,0).unwrap().get_data(),&vec![1,1,5,5,2,5]);
+        assert_eq!(view.get_cel(1,0).unwrap().get_data(),&vec![1,1,5,5,2,5]);
+    }
+
+    #[test]
+    fn view_round_trip_fixtures() {
+        for data in [&VIEW[..],&VIEW_DESCRIPTION_FIRST,&VIEW_MIRRORED_LOOPS,&VIEW_EMPTY,&VIEW_PLACEHOLDER] {
+            assert_eq!(ViewResource::new_from_slice(data).unwrap().write().unwrap(),data.to_vec());
+        }
+        let view = ViewResource::new_from_slice(&VIEW_MIRRORED_LOOPS).unwrap();
+        let mirrors:Vec<Option<u8>> = view.get_loops().iter().map(|l| l.get_mirror_of()).collect();
+        assert_eq!(mirrors,vec![None,Some(0),Some(3),None]);
+        assert_eq!(view.get_description(),"Walking man");
+    }
+
+    #[test]
+    fn view_round_trip_games() {
+        // Every view of the games in ../images (when present) writes back unchanged
+        let games = match std::fs::read_dir("../images") {
+            Ok(games) => games,
+            Err(_) => return,
+        };
+        for game in games.filter_map(|g| g.ok()) {
+            let path = format!("{}/",game.path().display());
+            let root = match dir_resource::Root::detect(&path) {
+                Ok(root) => root,
+                Err(_) => continue,
+            };
+            let dir = dir_resource::ResourceDirectory::new(&root,dir_resource::ResourceType::Views).unwrap();
+            let mut store = ResourceStore::new_from_root(&root).unwrap();
+            for (index,entry) in (&dir).into_iter().enumerate().filter(|(_,e)| !e.empty()) {
+                let (data,_) = store.fetch(entry).unwrap();
+                let view = ViewResource::new_from_slice(&data).unwrap();
+                assert_eq!(view.write().unwrap(),data.to_vec(),"{} view {}",path,index);
+            }
+        }
+    }
+
+    #[test]
+    fn view_write_from_loops() {
+        let mut row = vec![4u8;20];
+        row.extend_from_slice(&[7,7,9,7]);
+        let cel = ViewCel::new(24,1,7,row.clone()).unwrap();
+        let view = ViewResource::new_from_loops("",vec![ViewLoop::new(vec![cel]),ViewLoop::new_mirror(0)]).unwrap();
+        let data = view.write().unwrap();
+
+        // Runs longer than 15 are split and the transparent pixel after the 9 is dropped
+        assert_eq!(data,vec![0x01,0x01,0x02,0x00,0x00, 0x09,0x00,0x09,0x00,
+            0x01,0x03,0x00, 0x18,0x01,0x87,0x4F,0x45,0x72,0x91,0x00]);
+        let view = ViewResource::new_from_slice(&data).unwrap();
+        assert_eq!(view.get_cel(0,0).unwrap().get_data(),&row);
+        row.reverse();
+        assert_eq!(view.get_cel(1,0).unwrap().get_data(),&row);
+    }
+
+    #[test]
+    fn view_fail_bad_input() {
+        assert!(ViewCel::new(2,2,0,vec![0;3]).is_err());
+        assert!(ViewCel::new(1,1,0,vec![16]).is_err());
+        assert!(ViewResource::new_from_loops("",vec![ViewLoop::new_mirror(1),ViewLoop::new_mirror(0)]).is_err());
+        assert!(ViewResource::new_from_loops("",vec![ViewLoop::new_mirror(3)]).is_err());
+    }
+}
 
 pub struct ViewCel {
     width:u8,
@@ -9,25 +132,41 @@ pub struct ViewCel {
 }
 
 pub struct ViewLoop {
-    cels:Vec<ViewCel>
+    cels:Vec<ViewCel>,
+    mirror_of:Option<u8>,
+    // Loop whose cels this loop reuses unflipped (shared data without the mirror flag)
+    shares:Option<u8>,
 }
 pub struct ViewResource {
     description:String,
     loops:Vec<ViewLoop>,
+    header:[u8;2],
+    description_first:bool,
+    // Data of a view too short to hold a loop table, written back as it was
+    placeholder:Option<Vec<u8>>,
 }
 
 impl ViewCel {
+    /// Cel from unpacked pixels, one colour (0-15) per byte row by row
+    pub fn new(width:u8,height:u8,transparent_colour:u8,data:Vec<u8>) -> Result<ViewCel, String> {
+        if data.len()!=(width as usize)*(height as usize) {
+            return Err(format!("Cel data is {} pixels, expected {}x{}",data.len(),width,height));
+        }
+        if transparent_colour>15 || data.iter().any(|c| *c>15) {
+            return Err("Cel colours must be 0-15".to_string());
+        }
+        Ok(ViewCel { width, height, flags:transparent_colour, data })
+    }
+
     pub fn get_transparent_colour(&self) -> u8 {
         self.flags&0xF
     }
 
-    pub fn is_mirror(&self,cloop:u8) -> bool {
-        if self.flags&0x80 == 0x80 {
-            if ((self.flags&0x70)>>5) != cloop {
-                return true;
-            }
-        }
-        false
+    /// Copy of the cel flipped horizontally
+    fn mirrored(&self) -> ViewCel {
+        let width = (self.width as usize).max(1);
+        let data = self.data.chunks(width).flat_map(|row| row.iter().rev().copied()).collect();
+        ViewCel { width:self.width, height:self.height, flags:self.flags, data }
     }
 
     pub fn get_width(&self) -> u8 {
@@ -44,23 +183,64 @@ impl ViewCel {
 }
 
 impl ViewLoop {
+    pub fn new(cels:Vec<ViewCel>) -> ViewLoop {
+        ViewLoop { cels, mirror_of:None, shares:None }
+    }
+
+    /// Loop drawn as loop source flipped horizontally, sharing its cels when written
+    pub fn new_mirror(source:u8) -> ViewLoop {
+        ViewLoop { cels:Vec::new(), mirror_of:Some(source), shares:None }
+    }
+
     pub fn get_cels(&self) -> &Vec<ViewCel> {
         &self.cels
     }
+
+    /// The loop this loop is a horizontally flipped copy of, if any
+    pub fn get_mirror_of(&self) -> Option<u8> {
+        self.mirror_of
+    }
+}
+
+/// Packs a row as colour/length runs of up to 15 pixels, dropping the transparent run at the end
+fn write_row(out:&mut Vec<u8>,row:&[u8],transparent:u8) {
+    let end = row.iter().rposition(|c| *c!=transparent).map_or(0,|p| p+1);
+    let mut x = 0;
+    while x<end {
+        let colour = row[x];
+        let mut len = 0;
+        while x<end && row[x]==colour && len<15 {
+            x+=1;
+            len+=1;
+        }
+        out.push((colour<<4) | len);
+    }
+    out.push(0);
+}
+
+fn write_offset(out:&mut [u8],at:usize,offset:usize) -> Result<(), String> {
+    if offset>0xFFFF {
+        return Err("View is too large to address".to_string());
+    }
+    out[at] = (offset&0xFF) as u8;
+    out[at+1] = (offset>>8) as u8;
+    Ok(())
 }
 
 impl ViewResource {
-    pub fn new(volume:&Volume, entry: &ResourceDirectoryEntry) -> Result<ViewResource, String> {
-        let mut t=VolumeCache::new();
-        let data_slice = volume.fetch_data_slice(&mut t,entry)?;
+    pub fn new(store:&mut ResourceStore, entry: &ResourceDirectoryEntry) -> Result<ViewResource, String> {
+        let (data,_) = store.fetch(entry)?;
+        ViewResource::new_from_slice(&data)
+    }
 
-        if data_slice.0.len() <=3 {
-            return Ok(ViewResource {description:String::new(),loops:Vec::new()});
+    pub fn new_from_slice(slice:&[u8]) -> Result<ViewResource, String> {
+        if slice.len() <=3 {
+            return Ok(ViewResource {description:String::new(),loops:Vec::new(),header:[1,1],description_first:false,placeholder:Some(slice.to_vec())});
         }
-        let slice = data_slice.0;
         let slice_iter = slice.iter();
 
-        // Read in header (skip first 2 bytes as they are unknown)
+        // Read in header (first 2 bytes are unknown, but kept so the view writes back unchanged)
+        let header = [slice[0],slice[1]];
         let mut slice_iter = slice_iter.skip(2);
 
         let loops = slice_iter.next().unwrap();
@@ -96,9 +276,11 @@ impl ViewResource {
             }
         }
 
+        let description_first = description_position!=0 && loop_positions.iter().all(|l| description_position<*l);
+
         let mut loops:Vec<ViewLoop>= Vec::new();
         loops.reserve(loop_positions.len());
-        for l in loop_positions {
+        for &l in &loop_positions {
 
             let slice = &slice[l..];
             let mut iter = slice.iter();
@@ -155,10 +337,109 @@ impl ViewResource {
                 }
                 cels.push(ViewCel { width: *width, height: *height, flags: *flags, data: image });
             }
-            loops.push(ViewLoop { cels });
+            loops.push(ViewLoop { cels, mirror_of:None, shares:None });
+        }
+
+        // Loops that share their data are mirrors when the cel flags have bit 0x80 set and name another loop
+        // as the owner of the data, otherwise they just reuse it as it is
+        for (index,l) in loop_positions.iter().enumerate() {
+            let shared:Vec<usize> = (0..loop_positions.len()).filter(|i| loop_positions[*i]==*l).collect();
+            if shared.len()<2 {
+                continue;
+            }
+            match loops[index].cels.first() {
+                Some(cel) if cel.flags&0x80!=0 && shared.contains(&(((cel.flags&0x70)>>4) as usize)) => {
+                    let owner = ((cel.flags&0x70)>>4) as usize;
+                    if owner!=index {
+                        loops[index].mirror_of = Some(owner as u8);
+                        loops[index].cels = loops[index].cels.iter().map(|c| c.mirrored()).collect();
+                    }
+                },
+                _ => {
+                    if shared[0]!=index {
+                        loops[index].shares = Some(shared[0] as u8);
+                    }
+                },
+            }
+        }
+
+        Ok(ViewResource {description, loops, header:[header[0],header[1]], description_first, placeholder:None})
+    }
+
+    /// Cel with its pixels already oriented for the loop, so mirrored loops need no flipping when drawn
+    /// (loops sharing data without the mirror flag are kept as stored)
+    pub fn get_cel(&self,cloop:u8,cel:u8) -> Option<&ViewCel> {
+        self.loops.get(cloop as usize)?.cels.get(cel as usize)
+    }
+
+    /// View from its loops, mirror loops take their cels flipped from the loop they mirror
+    pub fn new_from_loops(description:&str,loops:Vec<ViewLoop>) -> Result<ViewResource, String> {
+        let mut loops = loops;
+        if loops.len()>255 {
+            return Err("A view can have at most 255 loops".to_string());
+        }
+        for index in 0..loops.len() {
+            if let Some(source) = loops[index].mirror_of {
+                let source = source as usize;
+                if source>7 || source>=loops.len() || loops[source].mirror_of.is_some() {
+                    return Err(format!("Loop {} cannot mirror loop {}",index,source));
+                }
+                loops[index].cels = loops[source].cels.iter().map(|c| c.mirrored()).collect();
+            }
+        }
+        Ok(ViewResource { description:description.to_string(), loops, header:[1,1], description_first:false, placeholder:None })
+    }
+
+    /// The view resource data, with each row run length packed and mirrored loops sharing their source's cels
+    pub fn write(&self) -> Result<Vec<u8>, String> {
+        if let Some(placeholder) = &self.placeholder {
+            return Ok(placeholder.clone());
+        }
+        let mut out:Vec<u8> = vec![self.header[0],self.header[1],self.loops.len() as u8,0,0];
+        out.resize(5+self.loops.len()*2,0);
+
+        let write_description = |out:&mut Vec<u8>| -> Result<(), String> {
+            if !self.description.is_empty() {
+                let position = out.len();
+                out.extend(self.description.chars().map(|c| c as u8));
+                out.push(0);
+                write_offset(out,3,position)?;
+            }
+            Ok(())
+        };
+        if self.description_first {
+            write_description(&mut out)?;
+        }
+
+        let mut positions:Vec<Option<usize>> = vec![None;self.loops.len()];
+        for index in 0..self.loops.len() {
+            let source = self.loops[index].mirror_of.or(self.loops[index].shares).map_or(index,|s| s as usize);
+            if positions[source].is_none() {
+                let mirrored = self.loops.iter().any(|l| l.mirror_of==Some(source as u8));
+                let start = out.len();
+                let cels = &self.loops[source].cels;
+                out.push(cels.len() as u8);
+                out.resize(start+1+cels.len()*2,0);
+                for (c,cel) in cels.iter().enumerate() {
+                    let offset = out.len()-start;
+                    write_offset(&mut out,start+1+c*2,offset)?;
+                    let flags = if mirrored { 0x80 | ((source as u8)<<4) | (cel.flags&0x0F) } else { cel.flags };
+                    out.extend_from_slice(&[cel.width,cel.height,flags]);
+                    for row in cel.data.chunks(cel.width.max(1) as usize).take(cel.height as usize) {
+                        write_row(&mut out,row,cel.flags&0x0F);
+                    }
+                }
+                positions[source] = Some(start);
+            }
+            if let Some(position) = positions[source] {
+                write_offset(&mut out,5+index*2,position)?;
+            }
         }
 
-        Ok(ViewResource {description, loops})
+        if !self.description_first {
+            write_description(&mut out)?;
+        }
+        Ok(out)
     }
 
     pub fn get_description(&self) ->&String {
@@ -4,7 +4,7 @@ use std::fs;
 use dir_resource::{ResourceDirectory, ResourceDirectoryEntry, Root, ResourceType};
 use helpers::*;
 use view::ViewResource;
-use volume::{Volume, VolumeCache};
+use volume::ResourceStore;
 
 fn main() {
 
@@ -13,29 +13,24 @@ fn main() {
     let root = Root::new("../images/Gold Rush! v2.01 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.149");
     //let root = Root::new("../images/Black Cauldron, The v2.10 (1988)(Sierra On-Line, Inc.) [Adventure]/","3.002.098");
     let dir = ResourceDirectory::new(&root, ResourceType::Views).unwrap();
+    let mut store = ResourceStore::new_from_root(&root).unwrap();
 
     for (index,entry) in dir.into_iter().enumerate() {
         if !entry.empty() {
             println!("{} : V{} P{}",index,entry.volume,entry.position);
-            dump_view_resource(&root,&entry, index);
+            dump_view_resource(&mut store,&entry, index);
         }
     }
 
 }
 
-fn dump_view_resource(root:&Root,entry:&ResourceDirectoryEntry, index:usize) {
+fn dump_view_resource(store:&mut ResourceStore,entry:&ResourceDirectoryEntry, index:usize) {
 
-    let bytes = root.fetch_volume(entry);
+    let (data,_) = store.fetch(entry).unwrap();
 
-    let mut t = VolumeCache::new();
-    let volume = Volume::new(bytes.into_iter()).unwrap();
+    fs::write(format!("../{}-binary.bin",index).as_str(),&data[..]).unwrap();
 
-    let data_slice = volume.fetch_data_slice(&mut t,entry).unwrap();
-    let data = data_slice.0;
-
-    fs::write(format!("../{}-binary.bin",index).as_str(),data).unwrap();
-
-    let view = match ViewResource::new(&volume, entry) {
+    let view = match ViewResource::new(store, entry) {
         Ok(b) => b,
         Err(s) => panic!("Failed due to : {}", s),
     };
@@ -1,18 +1,359 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, fs, ops::{Deref, Range}, path::Path, sync::Arc};
+
+use dir_resource::{Root, ResourceDirectory, ResourceDirectoryEntry, ResourceCompression, ResourceType, DIRECTORY_MAX_POSITION};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input:&[u8]) -> LzwCompressed {
+        let compressed = agi_lzw_compress(input).unwrap();
+        assert_eq!(compressed.uncompressed_length as usize,input.len());
+        assert_eq!(compressed.compressed_length as usize,compressed.data.len());
+        let mut output:Vec<u8> = Vec::new();
+        agi_lzw_expand(&compressed.data,&mut output).unwrap();
+        assert_eq!(output,input);
+        compressed
+    }
+
+    // Deterministic noise, so the dictionary fills and has to be reset
+    fn noise(length:usize) -> Vec<u8> {
+        let mut seed:u32 = 0x1234567;
+        (0..length).map(|_| { seed = seed.wrapping_mul(1103515245).wrapping_add(12345); (seed>>16) as u8 }).collect()
+    }
+
+    #[test]
+    fn lzw_empty() {
+        round_trip(&[]);
+    }
+
+    #[test]
+    fn lzw_text() {
+        round_trip(b"TOBEORNOTTOBEORTOBEORNOT#");
+    }
+
+    #[test]
+    fn lzw_repeated_byte() {
+        // Exercises codes that refer to the entry being defined (KwKwK)
+        let compressed = round_trip(&[0x41u8;5000]);
+        assert!(compressed.data.len()<200);
+    }
+
+    #[test]
+    fn lzw_noise_resets_dictionary() {
+        round_trip(&noise(20000));
+    }
+
+    #[test]
+    fn lzw_mixed() {
+        let mut input = noise(3000);
+        input.extend((0..9000).map(|i| (i%7) as u8));
+        input.extend(noise(3000));
+        round_trip(&input);
+    }
+
+    #[test]
+    fn lzw_fail_too_long() {
+        assert!(agi_lzw_compress(&vec![0u8;0x10000]).is_err());
+    }
+
+    #[test]
+    fn picture_packs_colour_nibbles() {
+        let input = [0xF0,0x05,0xF2,0x0A,0xF6,0x10,0x20,0xFF];
+        let packed = agi_picture_compress(&input);
+        assert_eq!(packed,vec![0xF0,0x5F,0x2A,0xF6,0x10,0x20,0xFF]);
+        assert_eq!(agi_picture_expand(&packed),input.to_vec());
+    }
+
+    #[test]
+    fn picture_pads_odd_nibbles() {
+        let input = [0xF0,0x05,0xFF];
+        let packed = agi_picture_compress(&input);
+        assert_eq!(packed,vec![0xF0,0x5F,0xF0]);
+        assert_eq!(agi_picture_expand(&packed),input.to_vec());
+    }
+
+    fn fetch(volumes:&[Vec<u8>],entry:&ResourceDirectoryEntry) -> Vec<u8> {
+        let mut store = ResourceStore::new();
+        store.insert_volume(entry.volume,Volume::new(volumes[entry.volume as usize].iter().copied()).unwrap());
+        store.fetch(entry).unwrap().0.to_vec()
+    }
+
+    #[test]
+    fn builder_v2_round_trip() {
+        let mut builder = VolumeBuilder::new(false);
+        let logic = noise(300);
+        let view = vec![1u8,2,3];
+        let a = builder.add(ResourceType::Logic,0,&logic).unwrap();
+        let b = builder.add(ResourceType::Views,2,&view).unwrap();
+        assert_eq!(a.position,0);
+        assert_eq!(b.position,5+300);
+        assert_eq!(&builder.get_volumes()[0][0..5],&[0x12,0x34,0,0x2C,0x01]);
+        assert_eq!(fetch(builder.get_volumes(),&a),logic);
+        assert_eq!(fetch(builder.get_volumes(),&b),view);
+
+        let views = builder.directory(ResourceType::Views);
+        assert_eq!(views.len(),3);
+        assert!(views[0].empty() && views[1].empty());
+        assert!(views[2]==b);
+        assert!(builder.directory(ResourceType::Sounds).is_empty());
+    }
+
+    #[test]
+    fn builder_v3_round_trip() {
+        let mut builder = VolumeBuilder::new(true);
+        let logic:Vec<u8> = (0..2000).map(|i| (i%13) as u8).collect();
+        let sound = noise(100);
+        let picture = vec![0xF0,0x05,0xF2,0x0A,0xF6,0x10,0x20,0xFF];
+        let a = builder.add(ResourceType::Logic,0,&logic).unwrap();
+        let b = builder.add(ResourceType::Sounds,0,&sound).unwrap();
+        let c = builder.add(ResourceType::Pictures,0,&picture).unwrap();
+        assert!(a.compression==ResourceCompression::LZW);
+        assert!(c.compression==ResourceCompression::Picture);
+
+        let volume = &builder.get_volumes()[0];
+        assert!(volume.len()<2000);
+        // Incompressible data is stored as is, with equal lengths
+        let header = &volume[b.position as usize..b.position as usize+7];
+        assert_eq!(header,&[0x12,0x34,0,100,0,100,0]);
+        assert_eq!(volume[c.position as usize+2],0x80);
+
+        assert_eq!(fetch(builder.get_volumes(),&a),logic);
+        assert_eq!(fetch(builder.get_volumes(),&b),sound);
+        assert_eq!(agi_picture_expand(&fetch(builder.get_volumes(),&c)),picture);
+    }
+
+    #[test]
+    fn builder_splits_volumes() {
+        let mut builder = VolumeBuilder::new(false);
+        builder.set_max_volume_size(1000);
+        let a = builder.add(ResourceType::Logic,0,&[0u8;600]).unwrap();
+        let b = builder.add(ResourceType::Logic,1,&[1u8;600]).unwrap();
+        builder.start_volume();
+        let c = builder.add(ResourceType::Logic,2,&[2u8;10]).unwrap();
+        assert_eq!((a.volume,b.volume,c.volume),(0,1,2));
+        assert_eq!(builder.get_volumes().len(),3);
+        assert_eq!(fetch(builder.get_volumes(),&b),vec![1u8;600]);
+        assert!(builder.add(ResourceType::Logic,3,&[0u8;1000]).is_err());
+    }
 
-use dir_resource::{ResourceDirectoryEntry, ResourceCompression};
+    #[test]
+    fn builder_fail_duplicate_index() {
+        let mut builder = VolumeBuilder::new(false);
+        builder.add(ResourceType::Logic,0,&[1u8;10]).unwrap();
+        assert!(builder.add(ResourceType::Logic,0,&[2u8;10]).is_err());
+        assert!(builder.add(ResourceType::Views,0,&[2u8;10]).is_ok());
+        assert_eq!(builder.get_volumes()[0].len(),2*15);
+    }
+
+    #[test]
+    fn builder_fail_words_and_objects() {
+        for v3 in [false,true] {
+            let mut builder = VolumeBuilder::new(v3);
+            assert!(builder.add(ResourceType::Words,0,&[1u8;10]).is_err());
+            assert!(builder.add(ResourceType::Objects,0,&[1u8;10]).is_err());
+            assert!(builder.get_volumes().iter().all(|v| v.is_empty()));
+        }
+    }
+
+    #[test]
+    fn builder_fail_too_many_volumes_is_unchanged() {
+        let mut builder = VolumeBuilder::new(false);
+        builder.set_max_volume_size(20);
+        for index in 0..VOLUME_MAX_COUNT {
+            builder.add(ResourceType::Logic,index,&[0u8;10]).unwrap();
+        }
+        assert!(builder.add(ResourceType::Logic,VOLUME_MAX_COUNT,&[0u8;10]).is_err());
+        assert_eq!(builder.get_volumes().len(),VOLUME_MAX_COUNT);
+        assert_eq!(builder.directory(ResourceType::Logic).len(),VOLUME_MAX_COUNT);
+    }
+
+    fn fetch_error(data:&[u8],entry:&ResourceDirectoryEntry) -> VolumeErrorKind {
+        let mut store = ResourceStore::new();
+        store.insert_volume(entry.volume,Volume::new(data.iter().copied()).unwrap());
+        let error = store.fetch(entry).unwrap_err();
+        assert!(error.entry==*entry);
+        error.kind
+    }
+
+    #[test]
+    fn fetch_fail_bad_signature() {
+        let mut builder = VolumeBuilder::new(false);
+        let a = builder.add(ResourceType::Logic,0,&[1u8;10]).unwrap();
+        let b = builder.add(ResourceType::Logic,1,&[2u8;10]).unwrap();
+        let mut data = builder.get_volumes()[0].clone();
+        data[b.position as usize]=0x00;
+        assert_eq!(fetch_error(&data,&b),VolumeErrorKind::BadSignature);
+        // The intact resource in front of it still loads
+        assert_eq!(fetch(&[data],&a),vec![1u8;10]);
+    }
+
+    #[test]
+    fn fetch_fail_wrong_volume() {
+        let mut builder = VolumeBuilder::new(true);
+        let a = builder.add(ResourceType::Pictures,0,&[0xF0,0x01,0xFF]).unwrap();
+        let mut data = builder.get_volumes()[0].clone();
+        data[2]=0x83;
+        assert_eq!(fetch_error(&data,&a),VolumeErrorKind::WrongVolume(3));
+    }
+
+    #[test]
+    fn fetch_fail_truncated() {
+        let mut builder = VolumeBuilder::new(false);
+        let a = builder.add(ResourceType::Logic,0,&[1u8;10]).unwrap();
+        let data = &builder.get_volumes()[0];
+        assert_eq!(fetch_error(&data[..8],&a),VolumeErrorKind::Truncated);
+        assert_eq!(fetch_error(&data[..3],&a),VolumeErrorKind::Truncated);
+        let past_end = ResourceDirectoryEntry { volume:0, position:1000, compression:ResourceCompression::None };
+        assert_eq!(fetch_error(data,&past_end),VolumeErrorKind::Truncated);
+
+        let mut builder = VolumeBuilder::new(true);
+        let b = builder.add(ResourceType::Logic,0,&[1u8;100]).unwrap();
+        let data = &builder.get_volumes()[0];
+        assert_eq!(fetch_error(&data[..data.len()-1],&b),VolumeErrorKind::Truncated);
+    }
+
+    #[test]
+    fn fetch_fail_corrupt_lzw() {
+        let mut builder = VolumeBuilder::new(true);
+        let a = builder.add(ResourceType::Logic,0,&noise(50).repeat(20)).unwrap();
+        let mut data = builder.get_volumes()[0].clone();
+        for byte in data[a.position as usize+7..].iter_mut() {
+            *byte = !*byte;
+        }
+        assert!(matches!(fetch_error(&data,&a),VolumeErrorKind::BadCompression(_)));
+    }
+
+    #[test]
+    fn lzw_fail_does_not_panic() {
+        for seed in 0..64 {
+            let mut input = noise(200);
+            input.rotate_left(seed);
+            input[0]=seed as u8;
+            let mut output:Vec<u8> = Vec::new();
+            let _ = agi_lzw_expand(&input,&mut output);
+        }
+        let mut output:Vec<u8> = Vec::new();
+        assert!(agi_lzw_expand(&[],&mut output).is_err());
+    }
+
+    #[test]
+    fn store_shares_fetched_payloads() {
+        let mut builder = VolumeBuilder::new(true);
+        let logic:Vec<u8> = (0..2000).map(|i| (i%13) as u8).collect();
+        let a = builder.add(ResourceType::Logic,0,&logic).unwrap();
+        let mut store = ResourceStore::new();
+        store.insert_volume(0,Volume::new(builder.get_volumes()[0].iter().copied()).unwrap());
+
+        let (first,compression) = store.fetch(&a).unwrap();
+        let (second,_) = store.fetch(&a).unwrap();
+        assert!(compression==ResourceCompression::LZW);
+        assert_eq!(&first[..],&logic[..]);
+        assert!(Arc::ptr_eq(&first.bytes,&second.bytes));
+        assert_eq!(store.cached_len(),1);
+
+        store.evict(&a);
+        assert_eq!(store.cached_len(),0);
+        let (third,_) = store.fetch(&a).unwrap();
+        assert!(!Arc::ptr_eq(&first.bytes,&third.bytes));
+        store.clear();
+        assert_eq!(store.cached_len(),0);
+
+        // Replacing the volume drops what was fetched from it
+        store.insert_volume(0,Volume::new(std::iter::empty()).unwrap());
+        assert_eq!(store.cached_len(),0);
+        assert_eq!(store.fetch(&a).unwrap_err().kind,VolumeErrorKind::Truncated);
+    }
+
+    #[test]
+    fn store_shares_uncompressed_volume_bytes() {
+        let mut builder = VolumeBuilder::new(false);
+        let a = builder.add(ResourceType::Logic,0,&[1u8;40]).unwrap();
+        let b = builder.add(ResourceType::Logic,1,&[2u8;40]).unwrap();
+        let mut store = ResourceStore::new();
+        store.insert_volume(0,Volume::new(builder.get_volumes()[0].iter().copied()).unwrap());
+
+        let (first,_) = store.fetch(&a).unwrap();
+        let (second,_) = store.fetch(&b).unwrap();
+        assert_eq!(&first[..],&[1u8;40]);
+        assert_eq!(&second[..],&[2u8;40]);
+        assert!(Arc::ptr_eq(&first.bytes,&store.get_volume(0).unwrap().data));
+        assert!(Arc::ptr_eq(&first.bytes,&second.bytes));
+    }
+
+    #[test]
+    fn store_reads_volumes_from_root() {
+        let mut builder = VolumeBuilder::new(false);
+        builder.set_max_volume_size(100);
+        let a = builder.add(ResourceType::Logic,0,&[1u8;80]).unwrap();
+        let b = builder.add(ResourceType::Views,0,&[2u8;80]).unwrap();
+        let mut source = dir_resource::source::MemorySource::new("store");
+        source.insert("LOGDIR",builder.directory(ResourceType::Logic).write_v2().unwrap());
+        source.insert("VIEWDIR",builder.directory(ResourceType::Views).write_v2().unwrap());
+        source.insert("VOL.0",builder.get_volumes()[0].clone());
+        source.insert("VOL.1",builder.get_volumes()[1].clone());
+        let root = Root::new_from_source(Box::new(source),"2.917");
+
+        let mut store = ResourceStore::new_from_root(&root).unwrap();
+        assert!(store.get_volume(0).is_some() && store.get_volume(1).is_some());
+        assert_eq!(&store.fetch(&a).unwrap().0[..],&[1u8;80]);
+        assert_eq!(&store.fetch(&b).unwrap().0[..],&[2u8;80]);
+    }
+
+    #[test]
+    fn volume_error_names_entry() {
+        let entry = ResourceDirectoryEntry { volume:2, position:0x1234, compression:ResourceCompression::None };
+        let error:String = VolumeError { entry, kind:VolumeErrorKind::BadSignature }.into();
+        assert_eq!(error,"Resource in VOL.2 at 0x01234: missing 0x1234 signature");
+    }
+}
 
 pub struct Volume {
-    pub data:Vec<u8>,
+    pub data:Arc<[u8]>,
+}
+
+/// Why a resource could not be fetched from its volume
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub enum VolumeErrorKind {
+    /// Header does not start with 0x12 0x34
+    BadSignature,
+    /// Header names a different volume to the directory entry
+    WrongVolume(u8),
+    /// Header or data runs past the end of the volume
+    Truncated,
+    /// LZW data could not be expanded to the length given in the header
+    BadCompression(&'static str),
+}
+
+/// A resource that could not be fetched, along with the directory entry pointing at it
+#[derive(Clone,Copy,Debug,PartialEq)]
+pub struct VolumeError {
+    pub entry:ResourceDirectoryEntry,
+    pub kind:VolumeErrorKind,
 }
 
-pub struct VolumeCache {
-    cache:HashMap<usize,Vec<u8>>,
+impl fmt::Display for VolumeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VolumeErrorKind::BadSignature => write!(f,"missing 0x1234 signature"),
+            VolumeErrorKind::WrongVolume(v) => write!(f,"header is for volume {}",v),
+            VolumeErrorKind::Truncated => write!(f,"runs past the end of the volume"),
+            VolumeErrorKind::BadCompression(s) => write!(f,"{}",s),
+        }
+    }
 }
 
-impl VolumeCache {
-    pub fn new() -> VolumeCache {
-        VolumeCache { cache:HashMap::new() }
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"Resource in VOL.{} at {:#07X}: {}",self.entry.volume,self.entry.position,self.kind)
+    }
+}
+
+impl std::error::Error for VolumeError {}
+
+impl From<VolumeError> for String {
+    fn from(error:VolumeError) -> String {
+        error.to_string()
     }
 }
 
@@ -20,37 +361,66 @@ impl Volume {
     pub fn new(bytes: impl Iterator<Item = u8>) -> Result<Volume,&'static str> {
         Ok(Volume {data:bytes.collect()})
     }
-    
-    pub fn fetch_data_slice<'a>(&'a self,cache:&'a mut VolumeCache, entry: &ResourceDirectoryEntry) -> Result<(&'a [u8],ResourceCompression),&'static str> {
 
-        return match entry.compression {
-            ResourceCompression::None => self.fetch_data_slice_v2(entry),
-            ResourceCompression::LZW | ResourceCompression::Picture => self.fetch_data_slice_v3(cache,entry),
-        };
+    /// Where the entry's data is stored within the volume, along with how it is stored
+    fn locate(&self, entry: &ResourceDirectoryEntry) -> Result<StoredData,VolumeError> {
+        match entry.compression {
+            ResourceCompression::None => self.locate_v2(entry),
+            ResourceCompression::LZW | ResourceCompression::Picture => self.locate_v3(entry),
+        }
     }
-    
-    fn fetch_data_slice_v2<'a>(&'a self, entry: &ResourceDirectoryEntry) -> Result<(&'a [u8],ResourceCompression),&'static str> {
 
-        if (entry.position+5) as usize > self.data.len() {
-            return Ok((&[],ResourceCompression::None));
+    fn expand(&self, entry: &ResourceDirectoryEntry, stored:&StoredData) -> Result<Vec<u8>,VolumeError> {
+        let error = |kind| VolumeError { entry:*entry, kind };
+
+        let mut output:Vec<u8> = Vec::new();
+        output.reserve(stored.uncompressed_length);
+        if let Err(reason) = agi_lzw_expand(&self.data[stored.range.clone()], &mut output) {
+            return Err(error(VolumeErrorKind::BadCompression(reason)));
+        }
+        if stored.uncompressed_length!=output.len() {
+            return Err(error(VolumeErrorKind::BadCompression("Failed to decompress, final size != expected length")));
         }
-        let slice = &self.data[entry.position as usize..];
-        let slice = &slice[3..]; // Skip 0x1234 + Vol
+        Ok(output)
+    }
+
+    /// Checks the header at the entry's position, returning the bytes that follow the signature and volume
+    fn fetch_header<'a>(&'a self, entry: &ResourceDirectoryEntry, header_size:usize) -> Result<&'a [u8],VolumeError> {
+        let error = |kind| VolumeError { entry:*entry, kind };
+
+        let position = entry.position as usize;
+        if position+header_size > self.data.len() {
+            return Err(error(VolumeErrorKind::Truncated));
+        }
+        let slice = &self.data[position..];
+        if slice[0]!=0x12 || slice[1]!=0x34 {
+            return Err(error(VolumeErrorKind::BadSignature));
+        }
+        let volume = slice[2] & 0x7F;    // v3 sets the upper bit for pictures, the compression kind is enough to tell
+        if volume!=entry.volume {
+            return Err(error(VolumeErrorKind::WrongVolume(volume)));
+        }
+        Ok(&slice[3..])
+    }
+    
+    fn locate_v2(&self, entry: &ResourceDirectoryEntry) -> Result<StoredData,VolumeError> {
+
+        let slice = self.fetch_header(entry,5)?;
 
         let length:usize = slice[0].into();
         let upper:usize = slice[1].into();
         let upper = upper<<8;
         let length = length+upper;
-        Ok((&slice[2..length+2],ResourceCompression::None))
+        let start = entry.position as usize+5;
+        if start+length > self.data.len() {
+            return Err(VolumeError { entry:*entry, kind:VolumeErrorKind::Truncated });
+        }
+        Ok(StoredData { range:start..start+length, compression:ResourceCompression::None, uncompressed_length:length })
     }
 
-    fn fetch_data_slice_v3<'a>(&'a self, cache:&'a mut VolumeCache,entry: &ResourceDirectoryEntry) -> Result<(&'a [u8],ResourceCompression),&'static str> {
+    fn locate_v3(&self, entry: &ResourceDirectoryEntry) -> Result<StoredData,VolumeError> {
 
-        if (entry.position+7) as usize > self.data.len() {
-            return Ok((&[],ResourceCompression::None));
-        }
-        let slice = &self.data[entry.position as usize..];
-        let slice = &slice[3..]; // Skip 0x1234 + Vol (note we skip the upper bit in vol that indicates picture resource, assuming the compression kind to be enough)
+        let slice = self.fetch_header(entry,7)?;
 
         let length:usize = slice[0].into();
         let upper:usize = slice[1].into();
@@ -61,37 +431,129 @@ impl Volume {
         let upper = upper<<8;
         let compressed_length = length+upper;
 
-        match entry.compression {
-            ResourceCompression::None => Err("Should not reach here for uncompressed entry"),
-            ResourceCompression::Picture => Ok((&slice[4..compressed_length+4],ResourceCompression::Picture)),
-            ResourceCompression::LZW => {
-                if compressed_length == uncompressed_length {
-                    return Ok((&slice[4..uncompressed_length+4], ResourceCompression::None));
-                }
-                let cache_entry:usize=entry.position as usize;
-                let cache_entry = cache_entry + ((entry.volume as usize)<<32);
-                if !cache.cache.contains_key(&cache_entry) {
-                    let bytes = &slice[4..compressed_length+4];
-                    let mut output:Vec<u8> = Vec::new();
-                    output.reserve(uncompressed_length);
-                    let decoded = agi_lzw_expand(bytes, &mut output);
-                    if decoded.is_ok() {
-                        let result_size=output.len();
-                        if uncompressed_length==result_size {
-                            cache.cache.insert(cache_entry, output);
-                        } else {
-                            return Err("Failed to decompress, final size != expected length");
-                        }
-                    } else {
-                        return Err("Failed to decompress LZW encoded");
-                    }
+        let start = entry.position as usize+7;
+        if start+compressed_length > self.data.len() {
+            return Err(VolumeError { entry:*entry, kind:VolumeErrorKind::Truncated });
+        }
+        let range = start..start+compressed_length;
+
+        let compression = if entry.compression == ResourceCompression::Picture {
+            ResourceCompression::Picture
+        } else if compressed_length == uncompressed_length {
+            ResourceCompression::None
+        } else {
+            ResourceCompression::LZW
+        };
+        Ok(StoredData { range, compression, uncompressed_length })
+    }
+
+}
+
+/// Location of a resource's data within its volume
+struct StoredData {
+    range:Range<usize>,
+    compression:ResourceCompression,
+    uncompressed_length:usize,
+}
+
+/// Bytes of a fetched resource, either a range of the (shared) volume it is stored in or its own
+/// decompressed copy, so handing it out never copies the data
+#[derive(Clone,Debug)]
+pub struct Payload {
+    bytes:Arc<[u8]>,
+    range:Range<usize>,
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[self.range.clone()]
+    }
+}
+
+/// A resource's payload as handed out by ResourceStore
+pub type SharedPayload = (Payload,ResourceCompression);
+
+/// Every volume file a game uses, each read once, along with the payloads already fetched from them
+/// (decompressed where needed) so looking a resource up again is just a map lookup
+pub struct ResourceStore {
+    volumes:HashMap<u8,Volume>,
+    resources:HashMap<(u8,u32),SharedPayload>,
+}
+
+impl ResourceStore {
+    pub fn new() -> ResourceStore {
+        ResourceStore { volumes:HashMap::new(), resources:HashMap::new() }
+    }
+
+    /// Reads each volume referenced by the game's directories, a missing volume is left empty so only its resources fail
+    pub fn new_from_root(root:&Root) -> Result<ResourceStore,String> {
+        let mut store = ResourceStore::new();
+        for resource_type in [ResourceType::Logic,ResourceType::Pictures,ResourceType::Views,ResourceType::Sounds] {
+            let directory = match ResourceDirectory::new(root,resource_type) {
+                Ok(d) => d,
+                Err(_) => continue,     // no directory, so nothing of this type to read
+            };
+            for entry in directory {
+                if !entry.empty() && !store.volumes.contains_key(&entry.volume) {
+                    store.insert_volume(entry.volume,Volume { data:root.fetch_volume(&entry).into() });
                 }
-                let v=&cache.cache[&cache_entry];
-                Ok((v.as_slice(), ResourceCompression::LZW))
-            },
+            }
         }
+        Ok(store)
+    }
+
+    pub fn insert_volume(&mut self,number:u8,volume:Volume) {
+        self.resources.retain(|(v,_),_| *v!=number);
+        self.volumes.insert(number,volume);
+    }
+
+    pub fn get_volume(&self,number:u8) -> Option<&Volume> {
+        self.volumes.get(&number)
     }
 
+    /// Number of payloads held in the cache
+    pub fn cached_len(&self) -> usize {
+        self.resources.len()
+    }
+
+    /// Drops the cached payload of a resource (e.g. once the game discards it), it is fetched again if needed
+    pub fn evict(&mut self,entry:&ResourceDirectoryEntry) {
+        self.resources.remove(&(entry.volume,entry.position));
+    }
+
+    /// Drops every cached payload, the volumes stay loaded
+    pub fn clear(&mut self) {
+        self.resources.clear();
+    }
+
+    /// Payload of the resource, fetched from its volume the first time and shared after that. Data stored
+    /// uncompressed shares the volume's bytes, LZW data is expanded once.
+    pub fn fetch(&mut self,entry:&ResourceDirectoryEntry) -> Result<SharedPayload,VolumeError> {
+        let key = (entry.volume,entry.position);
+        if let Some(payload) = self.resources.get(&key) {
+            return Ok(payload.clone());
+        }
+        let empty = Volume { data:Arc::from([]) };
+        let volume = self.volumes.get(&entry.volume).unwrap_or(&empty);
+        let stored = volume.locate(entry)?;
+        let payload = match stored.compression {
+            ResourceCompression::LZW => {
+                let bytes:Arc<[u8]> = volume.expand(entry,&stored)?.into();
+                (Payload { range:0..bytes.len(), bytes },ResourceCompression::LZW)
+            },
+            compression => (Payload { bytes:volume.data.clone(), range:stored.range },compression),
+        };
+        self.resources.insert(key,payload.clone());
+        Ok(payload)
+    }
+}
+
+impl Default for ResourceStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 const TABLE_SIZE:usize = 4096;
@@ -129,7 +591,7 @@ impl LzwState {
         self.max_code=(1<<size)-2;
     }
 
-    fn get_code(&mut self,input_slice:&[u8]) -> (u32,usize) {
+    fn get_code(&mut self,input_slice:&[u8]) -> Option<(u32,usize)> {
 
         let mut input_pos=0;
         let mut code:u32=0;
@@ -143,22 +605,25 @@ impl LzwState {
                 shifter+=1;
             } else {
                 // refill
-                self.bit_buffer = input_slice[input_pos];
+                self.bit_buffer = *input_slice.get(input_pos)?;
                 self.bit_remain = 8;
                 input_pos+=1;
             }
         }
 
-        (code,input_pos)
+        Some((code,input_pos))
     }
 
-    fn decode(&self,output:&mut Vec<u8>,start:u32,include:bool,c:u8) -> u8 {
+    fn decode(&self,output:&mut Vec<u8>,start:u32,include:bool,c:u8) -> Option<u8> {
         let mut t:Vec<u8> = Vec::new();
         if include{
             t.push(c);
         }
         let mut code = start;
         while code>255 {
+            if t.len()>TABLE_SIZE {
+                return None;    // corrupt data has made a loop in the table
+            }
             t.push(self.decoded_byte[code as usize]);
             code=self.code_follow[code as usize];
         }
@@ -168,17 +633,21 @@ impl LzwState {
             output.push(*n);
         }
 
-        t[t.len()-1]
+        Some(t[t.len()-1])
     }
 
-    fn update_code(&mut self,code:u32,byte:u8) {
+    fn update_code(&mut self,code:u32,byte:u8) -> bool {
         if self.next_code > self.max_code {
             self.set_bits(self.num_bits+1);
         }
+        if self.next_code as usize >= TABLE_SIZE {
+            return false;
+        }
 
         self.code_follow[self.next_code as usize]=code;
         self.decoded_byte[self.next_code as usize]=byte;
         self.next_code+=1;
+        true
     }
 
     fn reset(&mut self,start_bits:u32,next_code:u32) {
@@ -196,17 +665,19 @@ fn agi_lzw_expand(input_slice:&[u8],output:&mut Vec<u8>) -> Result<(),&'static s
 
     let mut state=LzwState::new(9,257);
 
-    let (mut last_code,slice_pos) = state.get_code(input_slice);
+    const OUT_OF_INPUT:&str = "Ran out of input buffer, before hitting a 257 end of input";
+
+    let (mut last_code,slice_pos) = state.get_code(input_slice).ok_or(OUT_OF_INPUT)?;
     let mut input_slice=&input_slice[slice_pos..];
     let mut c=last_code as u8;
     while state.is_input_remaining(input_slice) {
-        let (next_code,slice_pos) = state.get_code(input_slice);
+        let (next_code,slice_pos) = state.get_code(input_slice).ok_or(OUT_OF_INPUT)?;
         input_slice=&input_slice[slice_pos..];
 
         match next_code {
             256 => {
                 state.reset(9,258);
-                let (next_code,slice_pos) = state.get_code(input_slice);
+                let (next_code,slice_pos) = state.get_code(input_slice).ok_or(OUT_OF_INPUT)?;
                 input_slice=&input_slice[slice_pos..];
                 last_code=next_code;
                 c=last_code as u8;
@@ -215,17 +686,298 @@ fn agi_lzw_expand(input_slice:&[u8],output:&mut Vec<u8>) -> Result<(),&'static s
             },
             257 => return Ok(()),
             _ => {
-                if next_code <state.next_code {
-                    c=state.decode(output,next_code,false,c);
+                let decoded = if next_code <state.next_code {
+                    state.decode(output,next_code,false,c)
                 } else {
-                    c=state.decode(output,last_code,true,c);
-                }
+                    state.decode(output,last_code,true,c)
+                };
+                c=decoded.ok_or("Invalid LZW code")?;
             },
         };
 
-        state.update_code(last_code,c);
+        if !state.update_code(last_code,c) {
+            return Err("LZW table is full without a reset");
+        }
         last_code=next_code;
     }
 
-    Err("Ran out of input buffer, before hitting a 257 end of input")
-}
\ No newline at end of file
+    Err(OUT_OF_INPUT)
+}
+const LZW_START_BITS:u32 = 9;
+const LZW_MAX_BITS:u32 = 11;    // LzwState never widens to 12 bits
+const LZW_RESET:u32 = 256;
+const LZW_END:u32 = 257;
+const LZW_FIRST_CODE:u32 = 258;
+
+/// Output of agi_lzw_compress, with the lengths for a v3 volume header. A v3 reader treats equal lengths
+/// as an uncompressed resource, so data that does not shrink should be stored as is.
+pub struct LzwCompressed {
+    pub data:Vec<u8>,
+    pub uncompressed_length:u16,
+    pub compressed_length:u16,
+}
+
+// Packs codes least significant bit first, tracking the code width the decoder will be using
+struct LzwWriter {
+    data:Vec<u8>,
+    bit_buffer:u32,
+    bit_count:u32,
+    num_bits:u32,
+    max_code:u32,
+    next_code:u32,
+    first:bool,
+    skip_update:bool,
+}
+
+impl LzwWriter {
+    fn new() -> LzwWriter {
+        LzwWriter {
+            data:Vec::new(),
+            bit_buffer:0,
+            bit_count:0,
+            num_bits:LZW_START_BITS,
+            max_code:(1<<LZW_START_BITS)-2,
+            next_code:LZW_END,
+            first:true,
+            skip_update:false,
+        }
+    }
+
+    fn write(&mut self,code:u32) {
+        self.bit_buffer |= code<<self.bit_count;
+        self.bit_count += self.num_bits;
+        while self.bit_count>=8 {
+            self.data.push(self.bit_buffer as u8);
+            self.bit_buffer>>=8;
+            self.bit_count-=8;
+        }
+
+        // Mirror what agi_lzw_expand does after reading this code
+        if self.first {
+            self.first=false;
+        } else if code==LZW_RESET {
+            self.num_bits=LZW_START_BITS;
+            self.max_code=(1<<LZW_START_BITS)-2;
+            self.next_code=LZW_FIRST_CODE;
+            self.skip_update=true;
+        } else if self.skip_update {
+            self.skip_update=false;
+        } else {
+            if self.next_code>self.max_code && self.num_bits<LZW_MAX_BITS {
+                self.num_bits+=1;
+                self.max_code=(1<<self.num_bits)-2;
+            }
+            self.next_code+=1;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count>0 {
+            self.data.push(self.bit_buffer as u8);
+        }
+        self.data
+    }
+}
+
+/// Compresses data into the 9-11 bit LZW code stream used by v3 volumes (the inverse of agi_lzw_expand)
+pub fn agi_lzw_compress(input:&[u8]) -> Result<LzwCompressed,&'static str> {
+
+    let uncompressed_length:u16 = match input.len().try_into() {
+        Ok(l) => l,
+        Err(_) => return Err("Resource is too large for a v3 volume (over 65535 bytes)"),
+    };
+
+    let mut writer = LzwWriter::new();
+    let mut dictionary:HashMap<(u32,u8),u32> = HashMap::new();
+    let mut next_entry = LZW_FIRST_CODE;
+
+    // The decoder does not output the first code, so the stream always starts with a reset
+    writer.write(LZW_RESET);
+
+    let mut bytes = input.iter();
+    if let Some(first) = bytes.next() {
+        let mut current = *first as u32;
+        for byte in bytes {
+            if let Some(code) = dictionary.get(&(current,*byte)) {
+                current = *code;
+                continue;
+            }
+            writer.write(current);
+            if next_entry > (1<<LZW_MAX_BITS)-1 {
+                // Out of codes, start again (the code after a reset must be a single byte, which it always is here)
+                writer.write(LZW_RESET);
+                dictionary.clear();
+                next_entry = LZW_FIRST_CODE;
+            } else {
+                dictionary.insert((current,*byte),next_entry);
+                next_entry+=1;
+            }
+            current = *byte as u32;
+        }
+        writer.write(current);
+    }
+    writer.write(LZW_END);
+
+    let data = writer.finish();
+    let compressed_length:u16 = match data.len().try_into() {
+        Ok(l) => l,
+        Err(_) => return Err("Compressed resource is too large for a v3 volume (over 65535 bytes)"),
+    };
+    Ok(LzwCompressed { data, uncompressed_length, compressed_length })
+}
+
+/// Packs the colour argument of each F0/F2 picture command into a single nibble, the form v3 volumes store pictures in
+pub fn agi_picture_compress(input:&[u8]) -> Vec<u8> {
+    let mut nibbles:Vec<u8> = Vec::with_capacity(input.len()*2);
+    let mut position = 0;
+    while position<input.len() {
+        let byte = input[position];
+        nibbles.push(byte>>4);
+        nibbles.push(byte&0xF);
+        position+=1;
+        if (byte==0xF0 || byte==0xF2) && position<input.len() {
+            nibbles.push(input[position]&0xF);
+            position+=1;
+        }
+    }
+    nibbles.chunks(2).map(|n| (n[0]<<4) | n.get(1).unwrap_or(&0)).collect()
+}
+
+/// Reverses agi_picture_compress, giving the uncompressed (v2) picture data
+pub fn agi_picture_expand(input:&[u8]) -> Vec<u8> {
+    let nibbles:Vec<u8> = input.iter().flat_map(|b| [b>>4,b&0xF]).collect();
+    let mut output:Vec<u8> = Vec::with_capacity(input.len()+input.len()/4);
+    let mut position = 0;
+    while position+1<nibbles.len() {
+        let byte = (nibbles[position]<<4) | nibbles[position+1];
+        output.push(byte);
+        position+=2;
+        if (byte==0xF0 || byte==0xF2) && position<nibbles.len() {
+            output.push(nibbles[position]);
+            position+=1;
+        }
+    }
+    output
+}
+
+const VOLUME_SIGNATURE:[u8;2] = [0x12,0x34];
+const VOLUME_PICTURE_FLAG:u8 = 0x80;
+const VOLUME_MAX_COUNT:usize = 15;     // volume 15 marks an empty directory entry
+
+/// Lays resources out across VOL.0..VOL.n, in either the v2 or v3 (compressed) format
+pub struct VolumeBuilder {
+    v3:bool,
+    max_volume_size:usize,
+    volumes:Vec<Vec<u8>>,
+    directories:HashMap<ResourceType,Vec<(usize,ResourceDirectoryEntry)>>,
+}
+
+impl VolumeBuilder {
+    pub fn new(v3:bool) -> VolumeBuilder {
+        VolumeBuilder { v3, max_volume_size:DIRECTORY_MAX_POSITION as usize+1, volumes:vec![Vec::new()], directories:HashMap::new() }
+    }
+
+    /// Largest size of a single VOL file, resources that would not fit start a new volume
+    pub fn set_max_volume_size(&mut self,size:usize) {
+        self.max_volume_size = size.min(DIRECTORY_MAX_POSITION as usize+1);
+    }
+
+    /// Puts the following resources into a new volume (e.g. to keep the first rooms on the first disk)
+    pub fn start_volume(&mut self) {
+        if !self.volumes.last().unwrap().is_empty() {
+            self.volumes.push(Vec::new());
+        }
+    }
+
+    pub fn get_volumes(&self) -> &Vec<Vec<u8>> {
+        &self.volumes
+    }
+
+    fn encode(&self,resource_type:ResourceType,data:&[u8]) -> Result<(Vec<u8>,ResourceCompression),&'static str> {
+        if matches!(resource_type,ResourceType::Words | ResourceType::Objects) {
+            return Err("Words and objects are not stored in volumes");
+        }
+        let length:u16 = match data.len().try_into() {
+            Ok(l) => l,
+            Err(_) => return Err("Resource is too large for a volume (over 65535 bytes)"),
+        };
+
+        let mut bytes:Vec<u8> = Vec::new();
+        bytes.extend_from_slice(&VOLUME_SIGNATURE);
+        bytes.push(0);  // volume number, filled in once placed
+        if !self.v3 {
+            bytes.extend_from_slice(&length.to_le_bytes());
+            bytes.extend_from_slice(data);
+            return Ok((bytes,ResourceCompression::None));
+        }
+
+        let (compressed,compression) = match resource_type {
+            ResourceType::Pictures => {
+                bytes[2] |= VOLUME_PICTURE_FLAG;
+                (agi_picture_compress(data),ResourceCompression::Picture)
+            },
+            _ => match agi_lzw_compress(data)? {
+                // Equal lengths tell the reader the data is stored uncompressed
+                c if c.data.len()<data.len() => (c.data,ResourceCompression::LZW),
+                _ => (data.to_vec(),ResourceCompression::LZW),
+            },
+        };
+        let compressed_length:u16 = match compressed.len().try_into() {
+            Ok(l) => l,
+            Err(_) => return Err("Compressed resource is too large for a volume (over 65535 bytes)"),
+        };
+        bytes.extend_from_slice(&length.to_le_bytes());
+        bytes.extend_from_slice(&compressed_length.to_le_bytes());
+        bytes.extend_from_slice(&compressed);
+        Ok((bytes,compression))
+    }
+
+    /// Appends a resource, returning the directory entry that locates it. The builder is left unchanged on error.
+    pub fn add(&mut self,resource_type:ResourceType,index:usize,data:&[u8]) -> Result<ResourceDirectoryEntry,&'static str> {
+        if self.directories.get(&resource_type).is_some_and(|added| added.iter().any(|(i,_)| *i==index)) {
+            return Err("Resource index has already been added");
+        }
+        let (mut bytes,compression) = self.encode(resource_type,data)?;
+        if bytes.len()>self.max_volume_size {
+            return Err("Resource does not fit in a volume");
+        }
+        let new_volume = self.volumes.last().unwrap().len()+bytes.len()>self.max_volume_size;
+        if self.volumes.len()+(new_volume as usize)>VOLUME_MAX_COUNT {
+            return Err("Resources do not fit in 15 volumes");
+        }
+        if new_volume {
+            self.volumes.push(Vec::new());
+        }
+
+        let volume = (self.volumes.len()-1) as u8;
+        bytes[2] |= volume;
+        let current = self.volumes.last_mut().unwrap();
+        let entry = ResourceDirectoryEntry { volume, position:current.len() as u32, compression };
+        current.extend(bytes);
+
+        self.directories.entry(resource_type).or_default().push((index,entry));
+        Ok(entry)
+    }
+
+    /// Directory for a resource type, with empty slots for unused indices
+    pub fn directory(&self,resource_type:ResourceType) -> ResourceDirectory {
+        let added = self.directories.get(&resource_type);
+        let length = added.and_then(|a| a.iter().map(|(i,_)| i+1).max()).unwrap_or_default();
+        let mut entries = vec![ResourceDirectoryEntry::new_empty();length];
+        for (index,entry) in added.into_iter().flatten() {
+            entries[*index] = *entry;
+        }
+        ResourceDirectory::new_from_entries(entries)
+    }
+
+    /// Writes the volumes as {prefix}VOL.n files (v3 games prefix the file names with the game's initials)
+    pub fn write(&self,base_path:&str,prefix:&str) -> Result<(),String> {
+        for (index,volume) in self.volumes.iter().enumerate().filter(|(_,v)| !v.is_empty()) {
+            let path = Path::new(base_path).join(format!("{}VOL.{}",prefix,index));
+            if let Err(e) = fs::write(&path,volume) {
+                return Err(format!("Failed to write {} : {}",path.display(),e));
+            }
+        }
+        Ok(())
+    }
+}